@@ -0,0 +1,564 @@
+//! Typing dynamics: dwell time, digraph flight time, press depth and WPM.
+//!
+//! [`Analytics`] consumes completed [`Keystroke`]s (from
+//! `AnalogKeyboard::keystrokes_since`) in press order and keeps
+//!
+//! - per key: keystroke count, dwell times (leaving rest to returning to it)
+//!   as a 1 ms histogram, deepest point of each press as a 10-bin
+//!   histogram, and peak velocity;
+//! - per bigram (two consecutive presses): flight time from releasing the
+//!   first key to pressing the second. Overlapping presses (rollover) give
+//!   negative flight times; pauses longer than [`MAX_FLIGHT_MS`] are not
+//!   counted as bigrams.
+//!
+//! Stats export as CSV (one table for keys, one for bigrams) or JSON.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+use crate::Keystroke;
+
+/// Gaps longer than this end a run of typing: no bigram, no WPM time.
+pub const MAX_FLIGHT_MS: f64 = 2000.0;
+/// Window for the "current" WPM.
+const RECENT_MS: f64 = 10_000.0;
+/// Completed keystrokes kept waiting for an earlier press still held.
+const MAX_PENDING: usize = 1024;
+/// Press depth histogram bins over 0..1.
+pub const DEPTH_BINS: usize = 10;
+/// Dwell histogram resolution and range; longer holds go in the overflow.
+const DWELL_BUCKET_MS: f64 = 1.0;
+const DWELL_BUCKETS: usize = 2000;
+
+/// Count, mean, min and max of a series without keeping the samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Summary {
+    pub fn add(&mut self, x: f64) {
+        if self.count == 0 {
+            self.min = x;
+            self.max = x;
+        } else {
+            self.min = self.min.min(x);
+            self.max = self.max.max(x);
+        }
+        self.count += 1;
+        self.sum += x;
+    }
+
+    pub fn merge(&mut self, other: &Summary) {
+        if self.count == 0 {
+            *self = *other;
+        } else if other.count > 0 {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+            self.count += other.count;
+            self.sum += other.sum;
+        }
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+}
+
+/// Dwell times in fixed 1 ms buckets up to 2 s, so a key's stats stay the
+/// same size however long the session runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DwellHistogram {
+    buckets: Vec<u32>,
+    overflow: u32,
+    /// Exact count, mean, min and max.
+    pub summary: Summary,
+}
+
+impl Default for DwellHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; DWELL_BUCKETS],
+            overflow: 0,
+            summary: Summary::default(),
+        }
+    }
+}
+
+impl DwellHistogram {
+    pub fn record(&mut self, ms: f64) {
+        let ms = ms.max(0.0);
+        match self.buckets.get_mut((ms / DWELL_BUCKET_MS) as usize) {
+            Some(b) => *b += 1,
+            None => self.overflow += 1,
+        }
+        self.summary.add(ms);
+    }
+
+    pub fn merge(&mut self, other: &DwellHistogram) {
+        for (b, n) in self.buckets.iter_mut().zip(&other.buckets) {
+            *b += n;
+        }
+        self.overflow += other.overflow;
+        self.summary.merge(&other.summary);
+    }
+
+    /// Nearest-rank percentile (`p` in 0..=1) to bucket resolution, ms:
+    /// the lower edge of the bucket, within the exact min and max.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let s = &self.summary;
+        if s.count == 0 {
+            return None;
+        }
+        let rank = ((s.count - 1) as f64 * p.clamp(0.0, 1.0)).round() as u64 + 1;
+        let mut seen = 0u64;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n as u64;
+            if seen >= rank {
+                return Some((i as f64 * DWELL_BUCKET_MS).clamp(s.min, s.max));
+            }
+        }
+        Some(s.max)
+    }
+}
+
+/// Statistics for one scancode.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyStats {
+    /// Dwell time of the keystrokes, ms.
+    pub dwell: DwellHistogram,
+    /// Deepest point of each press, binned over 0..1.
+    pub depth_hist: [u64; DEPTH_BINS],
+    pub max_depth: Summary,
+    /// Peak press velocity, depth per ms.
+    pub peak_velocity: Summary,
+}
+
+impl KeyStats {
+    pub fn count(&self) -> usize {
+        self.dwell.summary.count as usize
+    }
+
+    /// Dwell time percentile (`p` in 0..=1), ms.
+    pub fn dwell_percentile(&self, p: f64) -> Option<f64> {
+        self.dwell.percentile(p)
+    }
+}
+
+#[derive(Default)]
+pub struct Analytics {
+    keys: BTreeMap<u8, KeyStats>,
+    bigrams: BTreeMap<(u8, u8), Summary>,
+    last: Option<Keystroke>,
+    /// Completed keystrokes waiting for an earlier press still held.
+    pending: Vec<Keystroke>,
+    /// Time spent typing (gaps up to `MAX_FLIGHT_MS`), ms.
+    active_ms: f64,
+    /// Press times of recent keystrokes, for the current WPM.
+    recent: VecDeque<f64>,
+    /// Sequence number for `AnalogKeyboard::keystrokes_since`.
+    pub next_seq: u64,
+}
+
+impl Analytics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add completed keystrokes, processed in press order across calls.
+    /// A keystroke only completes on release, so one pressed earlier may
+    /// still be held: `held_since` is the earliest press still in progress
+    /// (`AnalogKeyboard::held_since`), and strokes pressed after it wait
+    /// for it. A hold longer than [`MAX_FLIGHT_MS`] at `now_ms` (a modifier,
+    /// a stuck key) is not waited for, and at most `MAX_PENDING` strokes
+    /// wait; the rest are taken out of order.
+    pub fn extend(
+        &mut self,
+        strokes: impl IntoIterator<Item = Keystroke>,
+        held_since: Option<f64>,
+        now_ms: f64,
+    ) {
+        self.pending.extend(strokes);
+        self.pending
+            .sort_by(|a, b| a.start_ms.total_cmp(&b.start_ms));
+        let ready = match held_since.filter(|&t| now_ms - t <= MAX_FLIGHT_MS) {
+            Some(t) => self.pending.partition_point(|s| s.start_ms <= t),
+            None => self.pending.len(),
+        }
+        .max(self.pending.len().saturating_sub(MAX_PENDING));
+        let strokes: Vec<_> = self.pending.drain(..ready).collect();
+        for s in strokes {
+            self.add(s);
+        }
+    }
+
+    pub fn add(&mut self, s: Keystroke) {
+        let k = self.keys.entry(s.scancode).or_default();
+        k.dwell.record(s.duration_ms());
+        let bin = ((s.max_depth * DEPTH_BINS as f32) as usize).min(DEPTH_BINS - 1);
+        k.depth_hist[bin] += 1;
+        k.max_depth.add(s.max_depth as f64);
+        k.peak_velocity.add(s.peak_velocity as f64);
+
+        if let Some(prev) = self.last {
+            // A stroke that started before the previous one completed later;
+            // it cannot form an ordered pair.
+            if s.start_ms >= prev.start_ms {
+                let gap = s.start_ms - prev.start_ms;
+                if gap <= MAX_FLIGHT_MS {
+                    self.active_ms += gap;
+                    self.bigrams
+                        .entry((prev.scancode, s.scancode))
+                        .or_default()
+                        .add(s.start_ms - prev.end_ms);
+                }
+            }
+        }
+        if self.last.is_none_or(|p| s.start_ms >= p.start_ms) {
+            self.last = Some(s);
+        }
+
+        self.recent.push_back(s.start_ms);
+        while self
+            .recent
+            .front()
+            .is_some_and(|&t| t < s.start_ms - RECENT_MS)
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn key(&self, scancode: u8) -> Option<&KeyStats> {
+        self.keys.get(&scancode)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = (u8, &KeyStats)> {
+        self.keys.iter().map(|(&sc, k)| (sc, k))
+    }
+
+    pub fn bigrams(&self) -> impl Iterator<Item = ((u8, u8), &Summary)> {
+        self.bigrams.iter().map(|(&b, s)| (b, s))
+    }
+
+    pub fn total_keystrokes(&self) -> usize {
+        self.keys.values().map(|k| k.count()).sum()
+    }
+
+    /// Dwell times of all keys together.
+    pub fn dwell_percentile(&self, p: f64) -> Option<f64> {
+        let mut all = DwellHistogram::default();
+        for k in self.keys.values() {
+            all.merge(&k.dwell);
+        }
+        all.percentile(p)
+    }
+
+    /// Mean flight time over all bigrams, ms.
+    pub fn mean_flight_ms(&self) -> Option<f64> {
+        let (n, sum) = self
+            .bigrams
+            .values()
+            .fold((0, 0.0), |(n, s), b| (n + b.count, s + b.sum));
+        (n > 0).then(|| sum / n as f64)
+    }
+
+    /// Words per minute (5 keystrokes per word) over the time spent typing.
+    pub fn wpm(&self) -> Option<f64> {
+        (self.active_ms > 0.0)
+            .then(|| self.total_keystrokes() as f64 / 5.0 / (self.active_ms / 60_000.0))
+    }
+
+    /// Words per minute over the 10 s before `now_ms`: falls once typing
+    /// stops, `None` once nothing was typed for that long.
+    pub fn recent_wpm(&self, now_ms: f64) -> Option<f64> {
+        let since = now_ms - RECENT_MS;
+        let n = self.recent.iter().filter(|&&t| t >= since).count();
+        let first = self.recent.iter().find(|&&t| t >= since)?;
+        let span = now_ms - first;
+        (n > 1 && span > 0.0).then(|| (n - 1) as f64 / 5.0 / (span / 60_000.0))
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self {
+            next_seq: self.next_seq,
+            ..Self::default()
+        };
+    }
+
+    /// `scancode,count,dwell_mean_ms,dwell_p50_ms,dwell_p95_ms,depth_mean,
+    /// peak_velocity_mean,depth_0..depth_9` (depth bins of 0.1).
+    pub fn keys_csv(&self) -> String {
+        let mut out = String::from(
+            "scancode,count,dwell_mean_ms,dwell_p50_ms,dwell_p95_ms,depth_mean,peak_velocity_mean",
+        );
+        for i in 0..DEPTH_BINS {
+            let _ = write!(out, ",depth_{i}");
+        }
+        out.push('\n');
+        for (sc, k) in &self.keys {
+            let _ = write!(
+                out,
+                "0x{sc:02X},{},{:.2},{:.2},{:.2},{:.4},{:.6}",
+                k.count(),
+                k.dwell.summary.mean().unwrap_or(0.0),
+                k.dwell_percentile(0.5).unwrap_or(0.0),
+                k.dwell_percentile(0.95).unwrap_or(0.0),
+                k.max_depth.mean().unwrap_or(0.0),
+                k.peak_velocity.mean().unwrap_or(0.0),
+            );
+            for n in k.depth_hist {
+                let _ = write!(out, ",{n}");
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// `from,to,count,flight_mean_ms,flight_min_ms,flight_max_ms`.
+    pub fn bigrams_csv(&self) -> String {
+        let mut out = String::from("from,to,count,flight_mean_ms,flight_min_ms,flight_max_ms\n");
+        for ((a, b), s) in &self.bigrams {
+            let _ = writeln!(
+                out,
+                "0x{a:02X},0x{b:02X},{},{:.2},{:.2},{:.2}",
+                s.count,
+                s.mean().unwrap_or(0.0),
+                s.min,
+                s.max
+            );
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(sc, k)| {
+                serde_json::json!({
+                    "scancode": sc,
+                    "count": k.count(),
+                    "dwellMs": {
+                        "p50": k.dwell_percentile(0.5),
+                        "p95": k.dwell_percentile(0.95),
+                        "min": k.dwell_percentile(0.0),
+                        "max": k.dwell_percentile(1.0),
+                    },
+                    "depthMean": k.max_depth.mean(),
+                    "depthHistogram": k.depth_hist,
+                    "peakVelocityMean": k.peak_velocity.mean(),
+                })
+            })
+            .collect();
+        let bigrams: Vec<_> = self
+            .bigrams
+            .iter()
+            .map(|((a, b), s)| {
+                serde_json::json!({
+                    "from": a, "to": b, "count": s.count,
+                    "flightMs": { "mean": s.mean(), "min": s.min, "max": s.max },
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "keystrokes": self.total_keystrokes(),
+            "wpm": self.wpm(),
+            "dwellP50Ms": self.dwell_percentile(0.5),
+            "flightMeanMs": self.mean_flight_ms(),
+            "keys": keys,
+            "bigrams": bigrams,
+        })
+    }
+
+    /// Write `keys.csv`, `bigrams.csv` and `stats.json` into `dir`.
+    pub fn save(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("keys.csv"), self.keys_csv())?;
+        std::fs::write(dir.join("bigrams.csv"), self.bigrams_csv())?;
+        let json = serde_json::to_string_pretty(&self.to_json()).map_err(io::Error::other)?;
+        std::fs::write(dir.join("stats.json"), json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(sc: u8, start: f64, end: f64, depth: f32) -> Keystroke {
+        Keystroke {
+            scancode: sc,
+            start_ms: start,
+            end_ms: end,
+            max_depth: depth,
+            peak_velocity: 0.01,
+            peak_release_velocity: 0.01,
+        }
+    }
+
+    #[test]
+    fn dwell_and_depth_per_key() {
+        let mut a = Analytics::new();
+        a.extend(
+            [
+                stroke(4, 0.0, 100.0, 0.95),
+                stroke(4, 300.0, 380.0, 0.45),
+                stroke(4, 600.0, 720.0, 1.0),
+            ],
+            None,
+            720.0,
+        );
+        let k = a.key(4).unwrap();
+        assert_eq!(k.count(), 3);
+        assert_eq!(k.dwell_percentile(0.5), Some(100.0));
+        assert_eq!(k.dwell_percentile(1.0), Some(120.0));
+        assert_eq!(k.depth_hist[4], 1);
+        assert_eq!(k.depth_hist[9], 2, "1.0 falls in the last bin");
+    }
+
+    #[test]
+    fn bigram_flight_times() {
+        let mut a = Analytics::new();
+        // T pressed, released; H pressed 30 ms later; E pressed before H is released.
+        a.extend(
+            [
+                stroke(0x0B, 150.0, 260.0, 1.0),
+                stroke(0x17, 0.0, 120.0, 1.0),
+                stroke(0x08, 240.0, 330.0, 1.0),
+            ],
+            None,
+            330.0,
+        );
+        let b: BTreeMap<_, _> = a.bigrams().map(|(k, s)| (k, *s)).collect();
+        assert_eq!(b[&(0x17, 0x0B)].mean(), Some(30.0));
+        assert_eq!(b[&(0x0B, 0x08)].mean(), Some(-20.0), "rollover");
+
+        // After a long pause the next press does not pair up.
+        a.add(stroke(0x04, 10_000.0, 10_100.0, 1.0));
+        assert!(!a.bigrams().any(|((x, y), _)| (x, y) == (0x08, 0x04)));
+    }
+
+    #[test]
+    fn overlapping_strokes_across_batches() {
+        let mut a = Analytics::new();
+        // Shift (0xE1) is held from 0 to 500 ms while T and H are typed; the
+        // first batch only has T and H, Shift completes in the second.
+        a.extend(
+            [
+                stroke(0x17, 100.0, 180.0, 1.0),
+                stroke(0x0B, 250.0, 320.0, 1.0),
+            ],
+            Some(0.0),
+            320.0,
+        );
+        assert_eq!(a.total_keystrokes(), 0, "waits for the held Shift");
+        a.extend([stroke(0xE1, 0.0, 500.0, 1.0)], None, 500.0);
+        assert_eq!(a.total_keystrokes(), 3);
+        let b: BTreeMap<_, _> = a.bigrams().map(|(k, s)| (k, *s)).collect();
+        assert_eq!(b[&(0xE1, 0x17)].mean(), Some(-400.0), "rollover");
+        assert_eq!(b[&(0x17, 0x0B)].mean(), Some(70.0));
+        assert!((a.wpm().unwrap() - 3.0 / 5.0 / (250.0 / 60_000.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn long_holds_do_not_block() {
+        let mut a = Analytics::new();
+        // A key held since 0 ms (stuck, or a modifier) while typing goes on.
+        a.extend([stroke(4, 100.0, 180.0, 1.0)], Some(0.0), 1000.0);
+        assert_eq!(a.total_keystrokes(), 0, "a short hold is waited for");
+        a.extend([stroke(5, 2500.0, 2580.0, 1.0)], Some(0.0), 2580.0);
+        assert_eq!(a.total_keystrokes(), 2, "not past MAX_FLIGHT_MS");
+
+        // Even a hold that is waited for only delays MAX_PENDING strokes.
+        let mut a = Analytics::new();
+        let strokes = (0..MAX_PENDING + 10).map(|i| stroke(4, i as f64, i as f64 + 0.5, 1.0));
+        a.extend(strokes, Some(0.0), 1.0);
+        assert_eq!(a.total_keystrokes(), 10);
+        assert_eq!(a.pending.len(), MAX_PENDING);
+    }
+
+    #[test]
+    fn dwell_histogram_is_bounded() {
+        let mut a = Analytics::new();
+        a.extend(
+            (0..10_000).map(|i| stroke(4, i as f64 * 1000.0, i as f64 * 1000.0 + 80.0, 1.0)),
+            None,
+            1e7,
+        );
+        a.add(stroke(4, 1e8, 1e8 + 5000.0, 1.0));
+        let k = a.key(4).unwrap();
+        assert_eq!(k.dwell.buckets.len(), DWELL_BUCKETS);
+        assert_eq!(k.count(), 10_001);
+        assert_eq!(k.dwell_percentile(0.5), Some(80.0));
+        assert_eq!(
+            k.dwell_percentile(1.0),
+            Some(5000.0),
+            "overflow keeps the max"
+        );
+    }
+
+    #[test]
+    fn wpm_ignores_pauses() {
+        let mut a = Analytics::new();
+        // 11 keystrokes, one every 100 ms.
+        a.extend(
+            (0..11).map(|i| stroke(4, i as f64 * 100.0, i as f64 * 100.0 + 50.0, 1.0)),
+            None,
+            1050.0,
+        );
+        let wpm = a.wpm().unwrap();
+        assert!((wpm - 132.0).abs() < 1e-6, "{wpm}"); // 11 strokes over 1 s
+        assert!((a.recent_wpm(1000.0).unwrap() - 120.0).abs() < 1e-6);
+        // Typing stopped: the current rate falls, then goes away.
+        assert!((a.recent_wpm(5000.0).unwrap() - 24.0).abs() < 1e-6);
+        assert_eq!(a.recent_wpm(20_000.0), None);
+
+        a.add(stroke(4, 60_000.0, 60_050.0, 1.0));
+        assert!((a.wpm().unwrap() - 144.0).abs() < 1e-6, "pause not counted");
+    }
+
+    #[test]
+    fn csv_and_json_export() {
+        let mut a = Analytics::new();
+        a.extend(
+            [stroke(4, 0.0, 100.0, 0.5), stroke(5, 150.0, 200.0, 1.0)],
+            None,
+            200.0,
+        );
+        let keys = a.keys_csv();
+        let mut lines = keys.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("scancode,count,dwell_mean_ms"));
+        assert_eq!(
+            lines.next().unwrap(),
+            "0x04,1,100.00,100.00,100.00,0.5000,0.010000,0,0,0,0,0,1,0,0,0,0"
+        );
+        assert_eq!(
+            a.bigrams_csv(),
+            "from,to,count,flight_mean_ms,flight_min_ms,flight_max_ms\n0x04,0x05,1,50.00,50.00,50.00\n"
+        );
+        let j = a.to_json();
+        assert_eq!(j["keystrokes"], 2);
+        assert_eq!(j["bigrams"][0]["flightMs"]["mean"], 50.0);
+        assert_eq!(j["keys"][1]["depthHistogram"][9], 1);
+    }
+
+    #[test]
+    fn save_writes_three_files() {
+        let dir = std::env::temp_dir().join(format!("kb-hall-stats-{}", std::process::id()));
+        let mut a = Analytics::new();
+        a.add(stroke(4, 0.0, 100.0, 0.5));
+        a.save(&dir).unwrap();
+        for f in ["keys.csv", "bigrams.csv", "stats.json"] {
+            assert!(dir.join(f).exists(), "{f}");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+// WebHID bridge page logic. Config constants (VID, PID, WS_PORT, WS_TOKEN,
+// PROTOCOL_VERSION) are injected by the inline script in bridge.html.
+let ws,
+  rpt = 0,
+  rawDev,
+  outId = 0,
+  device = null,
+  heartbeat;
+const $ = (id) => document.getElementById(id);
+
+// Protocol: see src/protocol.rs. JSON text for control messages,
+// binary [type, version, ...] for reports and clock sync.
+(function wsConnect() {
+  ws = new WebSocket("ws://127.0.0.1:" + WS_PORT + "/?token=" + WS_TOKEN);
+  ws.binaryType = "arraybuffer";
+  ws.onopen = () => {
+    sendControl({ type: "hello", version: PROTOCOL_VERSION, device });
+    clearInterval(heartbeat);
+    heartbeat = setInterval(
+      () => sendControl({ type: "heartbeat", t: pageNow(performance.now()) }),
+      1000,
+    );
+  };
+  ws.onmessage = onWsMessage;
+  ws.onclose = () => {
+    clearInterval(heartbeat);
+    setTimeout(wsConnect, 800);
+  };
+  ws.onerror = () => {};
+})();
+
+// Epoch ms on the page clock; Rust estimates the offset via 0x04 sync.
+const pageNow = (t) => performance.timeOrigin + t;
+
+function sendControl(msg) {
+  if (!ws || ws.readyState !== 1) return;
+  ws.send(JSON.stringify(msg));
+}
+
+function reportError(e) {
+  sendControl({ type: "error", message: String(e) });
+}
+
+function describe(d) {
+  return {
+    productName: d.productName,
+    vendorId: d.vendorId,
+    productId: d.productId,
+    collections: d.collections.map((c) => ({
+      usagePage: c.usagePage,
+      usage: c.usage,
+      inputReports: (c.inputReports || []).map((r) => r.reportId),
+      outputReports: (c.outputReports || []).map((r) => r.reportId),
+      featureReports: (c.featureReports || []).map((r) => r.reportId),
+    })),
+  };
+}
+
+function onWsMessage(ev) {
+  if (typeof ev.data === "string") {
+    const msg = JSON.parse(ev.data);
+    if (msg.type === "welcome") {
+      $("status").textContent = "Ready - click Connect";
+      $("status").style.color = "#8f8";
+    } else if (msg.type === "error") {
+      $("status").textContent = "Bridge error: " + msg.message;
+      $("status").style.color = "#f88";
+    }
+    return;
+  }
+  const req = new Uint8Array(ev.data);
+  if (req[0] === 0x05 && req.length >= 7) {
+    sendOutputReport(req);
+    return;
+  }
+  // Echo clock sync requests [0x04, v, t0] as [0x04, v, t0, t1].
+  if (req[0] !== 0x04 || req.length < 10) return;
+  const m = new Uint8Array(18);
+  m.set(req.subarray(0, 10));
+  new DataView(m.buffer).setFloat64(10, pageNow(performance.now()), true);
+  ws.send(m.buffer);
+}
+
+// Output report from Rust: [0x05, v, id:u32, reportId, data...].
+async function sendOutputReport(frame) {
+  const id = new DataView(frame.buffer).getUint32(2, true);
+  if (!rawDev) {
+    sendControl({ type: "ack", id, error: "no device open" });
+    return;
+  }
+  try {
+    await rawDev.sendReport(frame[6], frame.slice(7));
+    sendControl({ type: "ack", id });
+  } catch (e) {
+    sendControl({ type: "ack", id, error: String(e) });
+  }
+}
+
+function wsSend(data, ts) {
+  if (!ws || ws.readyState !== 1) return;
+  const m = new Uint8Array(10 + data.length);
+  m[0] = 0x03;
+  m[1] = PROTOCOL_VERSION;
+  new DataView(m.buffer).setFloat64(2, ts, true);
+  m.set(data, 10);
+  ws.send(m.buffer);
+}
+
+function onInput(ev) {
+  const d = new Uint8Array(ev.data.buffer);
+  if (d[0] === 0xa0) {
+    rpt++;
+    wsSend(d, pageNow(ev.timeStamp));
+  }
+}
+
+navigator.hid.addEventListener("disconnect", (ev) => {
+  const d = ev.device;
+  if (d.vendorId !== VID || d.productId !== PID) return;
+  if (d === rawDev) rawDev = null;
+  device = null;
+  sendControl({ type: "device", event: "disconnected", device: describe(d) });
+  $("btn").disabled = false;
+  $("btn").textContent = "Reconnect";
+  $("status").textContent = "Keyboard disconnected";
+  $("status").style.color = "#f88";
+});
+
+async function go() {
+  $("btn").disabled = true;
+  $("status").textContent = "Select Ace 60 Pro in the picker...";
+  $("status").style.color = "#ff0";
+  try {
+    const devs = await navigator.hid.requestDevice({
+      filters: [{ vendorId: VID, productId: PID }],
+    });
+    if (!devs.length) {
+      $("btn").disabled = false;
+      return;
+    }
+
+    for (const d of devs) {
+      if (!d.opened) await d.open();
+      d.addEventListener("inputreport", onInput);
+      for (const c of d.collections) {
+        const outs = (c.outputReports || []).map((r) => r.reportId);
+        if (outs.length > 0) {
+          rawDev = d;
+          outId = outs[0];
+        }
+      }
+    }
+
+    for (const d of await navigator.hid.getDevices()) {
+      if (d.vendorId !== VID || d.productId !== PID) continue;
+      if (devs.includes(d)) continue;
+      if (!d.opened) await d.open();
+      d.addEventListener("inputreport", onInput);
+      if (!rawDev)
+        for (const c of d.collections) {
+          const outs = (c.outputReports || []).map((r) => r.reportId);
+          if (outs.length > 0) {
+            rawDev = d;
+            outId = outs[0];
+          }
+        }
+    }
+
+    if (rawDev) {
+      device = describe(rawDev);
+      sendControl({ type: "device", event: "opened", device });
+      for (const cmd of [0xa8, 0xa0]) {
+        const p = new Uint8Array(64);
+        p[0] = 0x55;
+        p[1] = cmd;
+        try { await rawDev.sendReport(outId, p); } catch (e) { reportError(e); }
+      }
+    }
+
+    $("btn").textContent = "Connected";
+    $("status").textContent =
+      "Analog active - press keys on your keyboard!";
+    $("status").style.color = "#8f8";
+
+    setInterval(() => {
+      $("stats").textContent =
+        rpt > 0 ? rpt + " analog reports" : "Waiting for key data...";
+    }, 500);
+  } catch (e) {
+    reportError(e);
+    $("status").textContent = "Error: " + e;
+    $("status").style.color = "#f88";
+    $("btn").disabled = false;
+    $("btn").textContent = "Retry";
+  }
+}
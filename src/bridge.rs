@@ -0,0 +1,785 @@
+//! Local HTTP + WebSocket bridge to the WebHID page running in Chrome.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::WebSocket;
+
+use crate::http;
+use crate::protocol::{self, Control, DeviceEvent, Incoming, ProtocolError, PROTOCOL_VERSION};
+use crate::{
+    check_sensors, now_ms, parse_analog_input, set_status, AnalogKeyboard,
+};
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(2);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// Drop the session when the page has been silent this long.
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+/// The page must say hello within this time after the WebSocket opens.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+/// Socket read timeout; bounds how long a queued output report waits.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Why an output report did not reach the keyboard.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SendError {
+    /// No bridge page is connected.
+    NotConnected,
+    /// The page closed before acknowledging the report.
+    Disconnected,
+    /// No acknowledgement within the timeout.
+    Timeout,
+    /// `sendReport` failed on the page (or no device is open).
+    Device(String),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConnected => write!(f, "bridge page not connected"),
+            Self::Disconnected => write!(f, "bridge page disconnected"),
+            Self::Timeout => write!(f, "no acknowledgement from bridge page"),
+            Self::Device(e) => write!(f, "sendReport failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+struct OutReport {
+    id: u32,
+    report_id: u8,
+    data: Vec<u8>,
+}
+
+/// Output reports waiting to be written by the session, and their acks.
+#[derive(Default)]
+pub(crate) struct Outbox {
+    connected: bool,
+    next_id: u32,
+    queue: Vec<OutReport>,
+    pending: HashMap<u32, mpsc::Sender<Result<(), SendError>>>,
+}
+
+impl Outbox {
+    /// Fail everything in flight; called when the session ends.
+    fn disconnect(&mut self) {
+        self.connected = false;
+        self.queue.clear();
+        for (_, tx) in self.pending.drain() {
+            let _ = tx.send(Err(SendError::Disconnected));
+        }
+    }
+}
+
+/// Queue an output report and block until the page acknowledges it.
+pub(crate) fn send_report(
+    kb: &AnalogKeyboard,
+    report_id: u8,
+    data: &[u8],
+    timeout: Duration,
+) -> Result<(), SendError> {
+    let (tx, rx) = mpsc::channel();
+    let id = {
+        let mut out = kb.outbox.lock().map_err(|_| SendError::NotConnected)?;
+        if !out.connected {
+            return Err(SendError::NotConnected);
+        }
+        let id = out.next_id;
+        out.next_id = out.next_id.wrapping_add(1);
+        out.queue.push(OutReport {
+            id,
+            report_id,
+            data: data.to_vec(),
+        });
+        out.pending.insert(id, tx);
+        id
+    };
+
+    let result = rx.recv_timeout(timeout).unwrap_or(Err(SendError::Timeout));
+    if result == Err(SendError::Timeout) {
+        if let Ok(mut out) = kb.outbox.lock() {
+            out.pending.remove(&id);
+        }
+    }
+    result
+}
+
+/// What a client must present to talk to the bridge: the per-session token
+/// (as `?token=` on the page, `/status` and WebSocket URLs) and, for the
+/// WebSocket and `/status`, the `Origin` of the page we served.
+#[derive(Clone)]
+pub(crate) struct BridgeAuth {
+    token: String,
+    origin: String,
+}
+
+impl BridgeAuth {
+    fn new(http_port: u16) -> Option<Self> {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).ok()?;
+        Some(Self {
+            token: bytes.iter().map(|b| format!("{b:02x}")).collect(),
+            origin: format!("http://127.0.0.1:{http_port}"),
+        })
+    }
+
+    /// True when the request target carries our token in its query string.
+    pub(crate) fn has_token(&self, target: &str) -> bool {
+        let query = target.split_once('?').map_or("", |(_, q)| q);
+        query
+            .split('&')
+            .filter_map(|kv| kv.strip_prefix("token="))
+            .any(|t| constant_time_eq(t.as_bytes(), self.token.as_bytes()))
+    }
+
+    /// True when `origin` is the page we served.
+    pub(crate) fn is_origin(&self, origin: &str) -> bool {
+        origin == self.origin
+    }
+
+    /// Check a WebSocket upgrade request; `Err` holds the reason to log.
+    fn check(&self, req: &Request) -> Result<(), &'static str> {
+        let target = req.uri().path_and_query().map_or("", |pq| pq.as_str());
+        if !self.has_token(target) {
+            return Err("missing or wrong token");
+        }
+        match req.headers().get("origin").and_then(|o| o.to_str().ok()) {
+            Some(o) if self.is_origin(o) => Ok(()),
+            Some(_) => Err("unexpected Origin"),
+            None => Err("no Origin header"),
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn bridge_html(ws_port: u16, token: &str, vid: u16, pid: u16) -> String {
+    include_str!("bridge.html")
+        .replace("__WS_PORT__", &ws_port.to_string())
+        .replace("__WS_TOKEN__", token)
+        .replace("__PROTOCOL_VERSION__", &PROTOCOL_VERSION.to_string())
+        .replace("__VID__", &format!("0x{:04X}", vid))
+        .replace("__PID__", &format!("0x{:04X}", pid))
+}
+
+/// Bind the page and WebSocket ports and serve the page in the background.
+/// Returns the page URL with the session token, and what [`serve_ws`] needs.
+fn bind_bridge(kb: &AnalogKeyboard) -> io::Result<(String, TcpListener, BridgeAuth)> {
+    let http_listener = TcpListener::bind("127.0.0.1:0")?;
+    let ws_listener = TcpListener::bind("127.0.0.1:0")?;
+
+    let http_port = http_listener.local_addr()?.port();
+    let ws_port = ws_listener.local_addr()?.port();
+    let auth = BridgeAuth::new(http_port)
+        .ok_or_else(|| io::Error::other("no OS randomness for the bridge token"))?;
+    let site = Arc::new(http::Site {
+        html: bridge_html(ws_port, &auth.token, kb.vid, kb.pid),
+        auth: auth.clone(),
+        kb: kb.clone(),
+    });
+    thread::spawn(move || http::serve(http_listener, site));
+
+    let url = format!("http://127.0.0.1:{http_port}/?token={}", auth.token);
+    Ok((url, ws_listener, auth))
+}
+
+/// Serve the bridge without opening a browser; returns the page URL.
+pub(crate) fn start_bridge(kb: &AnalogKeyboard) -> io::Result<String> {
+    let (url, ws_listener, auth) = bind_bridge(kb)?;
+    let kb = kb.clone();
+    thread::spawn(move || serve_ws(ws_listener, &kb, &auth));
+    Ok(url)
+}
+
+pub(crate) fn start_webhid_bridge(kb: &AnalogKeyboard) {
+    let (url, ws_listener, auth) = match bind_bridge(kb) {
+        Ok(b) => b,
+        Err(e) => {
+            set_status(kb, &format!("Bridge: {e}"));
+            return;
+        }
+    };
+    set_status(kb, &format!("Open Chrome -> {url}"));
+
+    if cfg!(target_os = "macos") {
+        let _ = std::process::Command::new("open")
+            .args(["-a", "Google Chrome", &url])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn();
+    } else {
+        let _ = std::process::Command::new("xdg-open").arg(&url).spawn();
+    }
+
+    serve_ws(ws_listener, kb, &auth);
+}
+
+/// Accept bridge pages one at a time, forever.
+pub(crate) fn serve_ws(ws_listener: TcpListener, kb: &AnalogKeyboard, auth: &BridgeAuth) {
+    ws_listener.set_nonblocking(true).ok();
+
+    loop {
+        set_status(kb, "Waiting for Chrome connection...");
+        set_active(kb, false);
+
+        let stream = loop {
+            match ws_listener.accept() {
+                Ok((s, _)) => {
+                    s.set_nonblocking(false).ok();
+                    break s;
+                }
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        };
+
+        let Some(websocket) = accept_authenticated(stream, auth) else {
+            continue;
+        };
+        run_session(websocket, kb);
+
+        if let Ok(mut out) = kb.outbox.lock() {
+            out.disconnect();
+        }
+        set_active(kb, false);
+        set_device(kb, None);
+        set_status(kb, "Chrome disconnected - reconnecting...");
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn set_active(kb: &AnalogKeyboard, active: bool) {
+    if let Ok(mut h) = kb.active.lock() {
+        *h = active;
+    }
+}
+
+fn set_device(kb: &AnalogKeyboard, device: Option<protocol::DeviceInfo>) {
+    if let Ok(mut d) = kb.device.lock() {
+        *d = device;
+    }
+}
+
+pub(crate) fn is_timeout(e: &tungstenite::Error) -> bool {
+    matches!(
+        e,
+        tungstenite::Error::Io(io) if matches!(
+            io.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        )
+    )
+}
+
+/// Wait for the page's `hello`. Returns the device it already knows about.
+fn handshake(
+    websocket: &mut WebSocket<TcpStream>,
+) -> Result<Option<protocol::DeviceInfo>, String> {
+    let started = Instant::now();
+    loop {
+        if started.elapsed() > HELLO_TIMEOUT {
+            return Err("no hello from bridge page".into());
+        }
+        let msg = match websocket.read() {
+            Ok(m) => m,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        match protocol::decode(&msg) {
+            Ok(Incoming::Control(Control::Hello { version, device })) => {
+                if version != PROTOCOL_VERSION {
+                    return Err(format!(
+                        "bridge page speaks protocol v{version}, expected v{PROTOCOL_VERSION}"
+                    ));
+                }
+                return Ok(device);
+            }
+            Ok(Incoming::Close) => return Err("closed before hello".into()),
+            Err(ProtocolError::Ignored) => {}
+            Ok(_) | Err(_) => return Err("expected hello as first message".into()),
+        }
+    }
+}
+
+/// WebSocket upgrade that refuses clients without our token and Origin.
+// The callback's error type is fixed by tungstenite.
+#[allow(clippy::result_large_err)]
+fn accept_authenticated(stream: TcpStream, auth: &BridgeAuth) -> Option<WebSocket<TcpStream>> {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "unknown peer".into(), |a| a.to_string());
+    // Read timeout lets the session send sync, heartbeats and output reports while idle.
+    stream.set_read_timeout(Some(POLL_INTERVAL)).ok();
+    let check = |req: &Request, resp: Response| -> Result<Response, ErrorResponse> {
+        auth.check(req).map(|_| resp).map_err(|reason| {
+            log::warn!("[HID] rejected WebSocket from {peer}: {reason}");
+            let mut err = ErrorResponse::new(Some(reason.into()));
+            *err.status_mut() = StatusCode::FORBIDDEN;
+            err
+        })
+    };
+    tungstenite::accept_hdr(stream, check).ok()
+}
+
+/// Apply an input report stamped `hid_ts` by the page and received at `now`
+/// (both ms): the key's value and the trackers fed from it, and latency.
+fn handle_report(kb: &AnalogKeyboard, hid_ts: f64, data: &[u8], now: f64) {
+    if let Some((sc, raw, depth)) = parse_analog_input(data, kb) {
+        if let Ok(mut d) = kb.dynamics.lock() {
+            d.record(sc, depth, hid_ts);
+        }
+        if let Ok(mut h) = kb.history.lock() {
+            h.record(sc, depth, hid_ts);
+        }
+        if let Ok(mut s) = kb.sensors.lock() {
+            s.record(sc, raw, hid_ts);
+        }
+        if let Ok(mut q) = kb.qa.lock() {
+            if let Some(q) = q.as_mut() {
+                q.record(sc, raw, hid_ts);
+            }
+        }
+    }
+    if let Ok(mut l) = kb.latency.lock() {
+        l.record_report(hid_ts, now);
+    }
+}
+
+fn run_session(mut websocket: WebSocket<TcpStream>, kb: &AnalogKeyboard) {
+    match handshake(&mut websocket) {
+        Ok(device) => {
+            let _ = websocket.send(protocol::encode_control(&Control::Welcome {
+                version: PROTOCOL_VERSION,
+            }));
+            set_device(kb, device);
+        }
+        Err(message) => {
+            log::warn!("[HID] rejecting bridge connection: {message}");
+            let _ = websocket.send(protocol::encode_control(&Control::Error { message }));
+            let _ = websocket.close(None);
+            let _ = websocket.flush();
+            return;
+        }
+    }
+
+    set_status(kb, "Chrome connected - click Connect in browser");
+    if let Ok(mut l) = kb.latency.lock() {
+        l.reset();
+    }
+    if let Ok(mut out) = kb.outbox.lock() {
+        out.connected = true;
+    }
+    let mut got_analog = false;
+    let mut last_sync: Option<Instant> = None;
+    let mut last_heartbeat: Option<Instant> = None;
+    let mut last_seen = Instant::now();
+
+    'session: loop {
+        if last_sync.is_none_or(|t| t.elapsed() >= SYNC_INTERVAL) {
+            last_sync = Some(Instant::now());
+            if websocket.send(protocol::encode_sync_request(now_ms())).is_err() {
+                break;
+            }
+        }
+        if last_heartbeat.is_none_or(|t| t.elapsed() >= HEARTBEAT_INTERVAL) {
+            last_heartbeat = Some(Instant::now());
+            check_sensors(kb);
+            let beat = Control::Heartbeat { t: now_ms() };
+            if websocket.send(protocol::encode_control(&beat)).is_err() {
+                break;
+            }
+        }
+        let queued = kb
+            .outbox
+            .lock()
+            .map(|mut out| std::mem::take(&mut out.queue))
+            .unwrap_or_default();
+        for r in queued {
+            let frame = protocol::encode_send_report(r.id, r.report_id, &r.data);
+            if websocket.send(frame).is_err() {
+                break 'session;
+            }
+        }
+        if last_seen.elapsed() > PEER_TIMEOUT {
+            log::warn!("[HID] bridge page stopped responding");
+            break;
+        }
+
+        let msg = match websocket.read() {
+            Ok(m) => m,
+            Err(e) if is_timeout(&e) => continue,
+            Err(_) => break,
+        };
+        last_seen = Instant::now();
+
+        match protocol::decode(&msg) {
+            Ok(Incoming::Report { hid_ts, data }) => {
+                if !got_analog {
+                    got_analog = true;
+                    set_active(kb, true);
+                    set_status(kb, "Analog active!");
+                }
+                handle_report(kb, hid_ts, &data, now_ms());
+
+                let pressed = kb
+                    .values
+                    .lock()
+                    .map(|t| t.iter().filter(|&&v| v > 0.01).count())
+                    .unwrap_or(0);
+                if let Ok(mut m) = kb.status.lock() {
+                    *m = format!("Analog active! ({pressed} keys)");
+                }
+            }
+            Ok(Incoming::SyncReply { t0, t1 }) => {
+                if let Ok(mut l) = kb.latency.lock() {
+                    l.sync_mut().add_sample(t0, t1, now_ms());
+                }
+            }
+            Ok(Incoming::Control(Control::Device { event, device })) => match event {
+                DeviceEvent::Opened => {
+                    set_status(kb, &format!("Opened {}", device.product_name));
+                    set_device(kb, Some(device));
+                }
+                DeviceEvent::Disconnected => {
+                    got_analog = false;
+                    set_active(kb, false);
+                    set_device(kb, None);
+                    set_status(kb, &format!("{} disconnected", device.product_name));
+                }
+            },
+            Ok(Incoming::Control(Control::Error { message })) => {
+                set_status(kb, &format!("Bridge error: {message}"));
+            }
+            Ok(Incoming::Control(Control::Ack { id, error })) => {
+                let tx = kb.outbox.lock().ok().and_then(|mut out| out.pending.remove(&id));
+                if let Some(tx) = tx {
+                    let _ = tx.send(error.map_or(Ok(()), |e| Err(SendError::Device(e))));
+                }
+            }
+            Ok(Incoming::Control(_)) => {}
+            Ok(Incoming::Close) => break,
+            Err(ProtocolError::Ignored) => {}
+            Err(e) => log::warn!("[HID] {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{encode_control, encode_report, encode_sync_reply, DeviceInfo};
+    use std::net::TcpStream;
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::Message as WsMessage;
+
+    type Client = WebSocket<MaybeTlsStream<TcpStream>>;
+
+    const TOKEN: &str = "0123456789abcdef";
+    const ORIGIN: &str = "http://127.0.0.1:8080";
+
+    fn spawn_server() -> (AnalogKeyboard, u16) {
+        let kb = AnalogKeyboard::new(0x41E4, 0x2103);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server_kb = kb.clone();
+        let auth = BridgeAuth {
+            token: TOKEN.into(),
+            origin: ORIGIN.into(),
+        };
+        thread::spawn(move || serve_ws(listener, &server_kb, &auth));
+        (kb, port)
+    }
+
+    /// Connect as a page would; `Err` holds the HTTP status of a refused upgrade.
+    fn try_connect(
+        port: u16,
+        token: Option<&str>,
+        origin: Option<&str>,
+    ) -> Result<Client, Option<StatusCode>> {
+        use tungstenite::client::IntoClientRequest;
+
+        let query = token.map_or(String::new(), |t| format!("?token={t}"));
+        let mut req = format!("ws://127.0.0.1:{port}/{query}")
+            .into_client_request()
+            .unwrap();
+        if let Some(o) = origin {
+            req.headers_mut().insert("Origin", o.parse().unwrap());
+        }
+        let (ws, _) = tungstenite::connect(req).map_err(|e| match e {
+            tungstenite::Error::Http(resp) => Some(resp.status()),
+            _ => None,
+        })?;
+        if let MaybeTlsStream::Plain(s) = ws.get_ref() {
+            s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        }
+        Ok(ws)
+    }
+
+    fn connect(port: u16) -> Client {
+        try_connect(port, Some(TOKEN), Some(ORIGIN)).unwrap()
+    }
+
+    fn assert_forbidden(r: Result<Client, Option<StatusCode>>) {
+        assert_eq!(r.err(), Some(Some(StatusCode::FORBIDDEN)));
+    }
+
+    fn hello(version: u8) -> WsMessage {
+        encode_control(&Control::Hello {
+            version,
+            device: None,
+        })
+    }
+
+    fn device() -> DeviceInfo {
+        DeviceInfo {
+            product_name: "Ace 60 Pro".into(),
+            vendor_id: 0x41E4,
+            product_id: 0x2103,
+            collections: vec![],
+        }
+    }
+
+    /// Next control message from the server, skipping sync requests.
+    fn next_control(ws: &mut Client) -> Option<Control> {
+        loop {
+            match ws.read() {
+                Ok(WsMessage::Text(t)) => return serde_json::from_str(&t).ok(),
+                Ok(WsMessage::Binary(_)) => continue,
+                _ => return None,
+            }
+        }
+    }
+
+    fn wait_until(mut cond: impl FnMut() -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(3) {
+            if cond() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn handshake_welcomes_matching_version() {
+        let (_kb, port) = spawn_server();
+        let mut ws = connect(port);
+        ws.send(hello(PROTOCOL_VERSION)).unwrap();
+        assert_eq!(
+            next_control(&mut ws),
+            Some(Control::Welcome {
+                version: PROTOCOL_VERSION
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_missing_or_wrong_token() {
+        let (_kb, port) = spawn_server();
+        assert_forbidden(try_connect(port, None, Some(ORIGIN)));
+        assert_forbidden(try_connect(port, Some("0123456789abcdee"), Some(ORIGIN)));
+        assert_forbidden(try_connect(port, Some("0123"), Some(ORIGIN)));
+        // the server keeps serving after rejecting
+        connect(port);
+    }
+
+    #[test]
+    fn rejects_foreign_or_missing_origin() {
+        let (_kb, port) = spawn_server();
+        assert_forbidden(try_connect(port, Some(TOKEN), Some("https://evil.example")));
+        assert_forbidden(try_connect(port, Some(TOKEN), None));
+    }
+
+    #[test]
+    fn token_is_read_from_query() {
+        let auth = BridgeAuth {
+            token: TOKEN.into(),
+            origin: ORIGIN.into(),
+        };
+        assert!(auth.has_token(&format!("/?token={TOKEN}")));
+        assert!(auth.has_token(&format!("/?a=1&token={TOKEN}")));
+        assert!(!auth.has_token(&format!("/token={TOKEN}")));
+        assert!(!auth.has_token("/?token="));
+        assert!(BridgeAuth::new(1).unwrap().token.len() == 32);
+    }
+
+    #[test]
+    fn handshake_rejects_wrong_version() {
+        let (_kb, port) = spawn_server();
+        let mut ws = connect(port);
+        ws.send(hello(99)).unwrap();
+        assert!(matches!(
+            next_control(&mut ws),
+            Some(Control::Error { message }) if message.contains("v99")
+        ));
+        // server closes after the error
+        assert!(next_control(&mut ws).is_none());
+    }
+
+    #[test]
+    fn reports_before_hello_are_rejected() {
+        let (kb, port) = spawn_server();
+        let mut ws = connect(port);
+        ws.send(encode_report(0.0, &[0xA0, 0, 0, 0x04, 0xFF, 0xFF]))
+            .unwrap();
+        assert!(matches!(next_control(&mut ws), Some(Control::Error { .. })));
+        assert_eq!(kb.value(0x04), 0.0);
+    }
+
+    #[test]
+    fn input_reports_update_values() {
+        let (kb, port) = spawn_server();
+        let mut ws = connect(port);
+        ws.send(hello(PROTOCOL_VERSION)).unwrap();
+        next_control(&mut ws);
+
+        ws.send(encode_report(now_ms(), &[0xA0, 0, 0, 0x04, 0xFF, 0xFF]))
+            .unwrap();
+        assert!(wait_until(|| kb.value(0x04) == 1.0));
+        assert!(kb.is_active());
+    }
+
+    #[test]
+    fn device_events_track_device_info() {
+        let (kb, port) = spawn_server();
+        let mut ws = connect(port);
+        ws.send(hello(PROTOCOL_VERSION)).unwrap();
+        next_control(&mut ws);
+
+        ws.send(encode_control(&Control::Device {
+            event: DeviceEvent::Opened,
+            device: device(),
+        }))
+        .unwrap();
+        assert!(wait_until(|| kb.device_info() == Some(device())));
+
+        ws.send(encode_control(&Control::Device {
+            event: DeviceEvent::Disconnected,
+            device: device(),
+        }))
+        .unwrap();
+        assert!(wait_until(|| kb.device_info().is_none()));
+    }
+
+    #[test]
+    fn sync_requests_are_answered() {
+        let (kb, port) = spawn_server();
+        let mut ws = connect(port);
+        ws.send(hello(PROTOCOL_VERSION)).unwrap();
+
+        let t0 = loop {
+            if let WsMessage::Binary(d) = ws.read().unwrap() {
+                if d[0] == protocol::MSG_SYNC {
+                    break f64::from_le_bytes(d[2..10].try_into().unwrap());
+                }
+            }
+        };
+        ws.send(encode_sync_reply(t0, t0 + 1000.0)).unwrap();
+        assert!(wait_until(|| kb.latency_report().offset_ms.is_some()));
+    }
+
+    #[test]
+    fn report_message_updates_value_and_latency() {
+        let kb = AnalogKeyboard::new(0, 0);
+        // page clock is 500 ms ahead of ours
+        if let Ok(mut l) = kb.latency.lock() {
+            l.sync_mut().add_sample(100.0, 602.0, 104.0);
+        }
+        handle_report(&kb, 1000.0, &[0xA0, 0x00, 0x00, 0x04, 0xFF, 0xFF], 506.5);
+
+        assert_eq!(kb.value(0x04), 1.0);
+        let r = kb.latency_report();
+        assert_eq!(r.offset_ms, Some(500.0));
+        assert_eq!(r.bridge.max(), Some(6.5));
+
+        kb.mark_frame();
+        assert_eq!(kb.latency_report().frame.count(), 1);
+    }
+
+    #[test]
+    fn send_report_without_bridge_fails() {
+        let kb = AnalogKeyboard::new(0, 0);
+        assert_eq!(kb.send_report(0, &[0x55, 0xA8]), Err(SendError::NotConnected));
+    }
+
+    /// Connect a mock page that answers every output report with `reply(data)`.
+    fn mock_page(reply: fn(&[u8]) -> Option<String>) -> AnalogKeyboard {
+        let (kb, port) = spawn_server();
+        let mut ws = connect(port);
+        ws.send(hello(PROTOCOL_VERSION)).unwrap();
+        next_control(&mut ws);
+        thread::spawn(move || {
+            while let Ok(msg) = ws.read() {
+                let WsMessage::Binary(frame) = msg else {
+                    continue;
+                };
+                if let Some((id, report_id, data)) = protocol::decode_send_report(&frame) {
+                    assert_eq!(report_id, 0);
+                    let error = reply(&data);
+                    ws.send(encode_control(&Control::Ack { id, error })).unwrap();
+                }
+            }
+        });
+        assert!(wait_until(|| kb.outbox.lock().unwrap().connected));
+        kb
+    }
+
+    #[test]
+    fn send_report_is_acknowledged() {
+        let kb = mock_page(|data| (data != [0x55, 0xA8]).then(|| "unexpected bytes".into()));
+        assert_eq!(kb.send_report(0, &[0x55, 0xA8]), Ok(()));
+        assert_eq!(
+            kb.send_report(0, &[0x55, 0x00]),
+            Err(SendError::Device("unexpected bytes".into()))
+        );
+    }
+
+    #[test]
+    fn concurrent_sends_are_correlated() {
+        let kb = mock_page(|data| (data[1] % 2 == 1).then(|| format!("odd {}", data[1])));
+        let handles: Vec<_> = (0..8u8)
+            .map(|i| {
+                let kb = kb.clone();
+                thread::spawn(move || (i, kb.send_report(0, &[0x55, i])))
+            })
+            .collect();
+        for h in handles {
+            let (i, result) = h.join().unwrap();
+            if i % 2 == 1 {
+                assert_eq!(result, Err(SendError::Device(format!("odd {i}"))));
+            } else {
+                assert_eq!(result, Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn server_sends_heartbeats() {
+        let (_kb, port) = spawn_server();
+        let mut ws = connect(port);
+        ws.send(hello(PROTOCOL_VERSION)).unwrap();
+        next_control(&mut ws);
+        assert!(matches!(
+            next_control(&mut ws),
+            Some(Control::Heartbeat { .. })
+        ));
+    }
+
+    #[test]
+    fn bridge_html_substitutes_placeholders() {
+        let html = bridge_html(4242, TOKEN, 0x41E4, 0x2103);
+        assert!(html.contains("WS_PORT = 4242"));
+        assert!(html.contains(&format!("WS_TOKEN = \"{TOKEN}\"")));
+        assert!(html.contains("0x41E4"));
+        assert!(!html.contains("__PROTOCOL_VERSION__"));
+    }
+}
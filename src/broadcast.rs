@@ -0,0 +1,676 @@
+//! Rebroadcast of the analog state to other local programs.
+//!
+//! Clients connect to the broadcast port either over WebSocket or plain TCP
+//! and pick what they receive with a query string:
+//!
+//! ```text
+//! format=json|binary   frame encoding (default json)
+//! keys=4,5,0x2c        scancodes to receive (default all)
+//! rate=60              maximum frames per second, 1..=1000 (default 60)
+//! ```
+//!
+//! | transport | subscription                                               |
+//! |-----------|------------------------------------------------------------|
+//! | WebSocket | `ws://127.0.0.1:7373/?keys=4,5`; a later text frame with a |
+//! |           | query string replaces the subscription                     |
+//! | TCP       | first line sent by the client (may be empty)               |
+//!
+//! Frames are deltas: each carries the subscribed keys whose value changed
+//! since the previous frame, so a client starts from all zeros (again after
+//! every re-subscription). Nothing is sent while nothing changes.
+//!
+//! JSON frames are text messages (one per line over TCP):
+//!
+//! ```text
+//! {"t":1712345678901.2,"active":true,"keys":{"4":0.5213,"44":0}}
+//! ```
+//!
+//! Binary frames (WebSocket binary messages, back to back over TCP):
+//!
+//! ```text
+//! t:f64  flags:u8 (bit 0 = active)  n:u16  n x (scancode:u8 value:u16)
+//! ```
+//!
+//! with little-endian numbers and `value` scaled to `0..=65535`.
+//!
+//! Plain HTTP requests on the same port are handed to the overlay routes in
+//! `http.rs`, so the OBS overlay page can subscribe on the host it came from.
+//!
+//! The server only listens on loopback. WebSocket upgrades carrying an
+//! `Origin` other than a loopback page are refused, so websites open in a
+//! browser cannot read the keyboard.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+
+use crate::{http, now_ms, AnalogKeyboard};
+
+pub const DEFAULT_PORT: u16 = 7373;
+pub const DEFAULT_RATE: u32 = 60;
+pub const MAX_RATE: u32 = 1000;
+/// Connections beyond this are closed right away.
+const MAX_CLIENTS: usize = 16;
+const MAX_LINE: u64 = 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    Binary,
+}
+
+/// What a client wants to receive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    pub format: Format,
+    /// `None` subscribes to every scancode.
+    pub keys: Option<Vec<u8>>,
+    /// Maximum frames per second.
+    pub rate: u32,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self {
+            format: Format::Json,
+            keys: None,
+            rate: DEFAULT_RATE,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SubscriptionError(String);
+
+impl fmt::Display for SubscriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bad subscription: {}", self.0)
+    }
+}
+
+impl std::error::Error for SubscriptionError {}
+
+impl Subscription {
+    /// Parse `format=..&keys=..&rate=..`; a leading `/?` or `?` is ignored.
+    pub fn parse(query: &str) -> Result<Self, SubscriptionError> {
+        let query = query.trim();
+        let query = query.strip_prefix('/').unwrap_or(query);
+        let query = query.strip_prefix('?').unwrap_or(query);
+        let mut sub = Self::default();
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            match k {
+                "format" => {
+                    sub.format = match v {
+                        "json" => Format::Json,
+                        "binary" => Format::Binary,
+                        _ => return Err(SubscriptionError(format!("unknown format {v:?}"))),
+                    }
+                }
+                "keys" => {
+                    let keys = v
+                        .split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(parse_scancode)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    sub.keys = Some(keys);
+                }
+                "rate" => {
+                    sub.rate = v
+                        .parse()
+                        .ok()
+                        .filter(|r| (1..=MAX_RATE).contains(r))
+                        .ok_or_else(|| SubscriptionError(format!("rate must be 1..={MAX_RATE}")))?;
+                }
+                _ => return Err(SubscriptionError(format!("unknown parameter {k:?}"))),
+            }
+        }
+        Ok(sub)
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(1) / self.rate
+    }
+
+    fn wants(&self, scancode: usize) -> bool {
+        self.keys
+            .as_ref()
+            .is_none_or(|k| k.contains(&(scancode as u8)))
+    }
+}
+
+fn parse_scancode(s: &str) -> Result<u8, SubscriptionError> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| SubscriptionError(format!("bad scancode {s:?}")))
+}
+
+fn quantize(v: f32) -> u16 {
+    (v.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+/// Per-client view of the state, used to compute deltas.
+struct Tracker {
+    sub: Subscription,
+    last: [u16; 256],
+    active: Option<bool>,
+}
+
+impl Tracker {
+    fn new(sub: Subscription) -> Self {
+        Self {
+            sub,
+            last: [0; 256],
+            active: None,
+        }
+    }
+
+    /// Changed keys since the last call, or `None` if nothing changed.
+    fn next_frame(&mut self, values: &[f32; 256], active: bool) -> Option<Frame> {
+        let mut keys = Vec::new();
+        for (sc, &v) in values.iter().enumerate() {
+            let q = quantize(v);
+            if q != self.last[sc] && self.sub.wants(sc) {
+                self.last[sc] = q;
+                keys.push((sc as u8, q));
+            }
+        }
+        if keys.is_empty() && self.active == Some(active) {
+            return None;
+        }
+        self.active = Some(active);
+        Some(Frame {
+            t: now_ms(),
+            active,
+            keys,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Frame {
+    t: f64,
+    active: bool,
+    keys: Vec<(u8, u16)>,
+}
+
+impl Frame {
+    fn to_json(&self) -> String {
+        let keys: serde_json::Map<String, serde_json::Value> = self
+            .keys
+            .iter()
+            .map(|&(sc, q)| {
+                let v = (q as f64 / 65535.0 * 10000.0).round() / 10000.0;
+                (sc.to_string(), v.into())
+            })
+            .collect();
+        serde_json::json!({ "t": self.t, "active": self.active, "keys": keys }).to_string()
+    }
+
+    fn to_binary(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(11 + 3 * self.keys.len());
+        b.extend_from_slice(&self.t.to_le_bytes());
+        b.push(self.active as u8);
+        b.extend_from_slice(&(self.keys.len() as u16).to_le_bytes());
+        for &(sc, q) in &self.keys {
+            b.push(sc);
+            b.extend_from_slice(&q.to_le_bytes());
+        }
+        b
+    }
+}
+
+/// Bind `addr` and stream `kb` to every client that connects, in the
+/// background. Returns the bound address (useful with port 0).
+pub(crate) fn serve(kb: &AnalogKeyboard, addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+    if !local.ip().is_loopback() {
+        log::warn!("[broadcast] listening on non-loopback address {local}");
+    }
+    let kb = kb.clone();
+    thread::spawn(move || accept_loop(listener, kb));
+    log::info!("[broadcast] listening on {local}");
+    Ok(local)
+}
+
+fn accept_loop(listener: TcpListener, kb: AnalogKeyboard) {
+    let clients = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming().flatten() {
+        if clients.load(Ordering::Relaxed) >= MAX_CLIENTS {
+            log::warn!("[broadcast] too many clients, dropping connection");
+            continue;
+        }
+        clients.fetch_add(1, Ordering::Relaxed);
+        let kb = kb.clone();
+        let clients = clients.clone();
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| "unknown peer".into(), |a| a.to_string());
+            if let Err(e) = handle_client(stream, &kb) {
+                log::info!("[broadcast] {peer}: {e}");
+            }
+            clients.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+fn handle_client(stream: TcpStream, kb: &AnalogKeyboard) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let head = peek_head(&stream)?;
+    if !(head.starts_with(b"GET ") || head.starts_with(b"HEAD ")) {
+        return serve_tcp(stream, kb);
+    }
+    match http::parse_request(&head) {
+        Ok(req) if req.is_upgrade() => serve_websocket(stream, kb),
+        _ => {
+            http::handle(stream, |req| http::overlay_route(req, kb));
+            Ok(())
+        }
+    }
+}
+
+/// Look at the start of the connection without consuming it: the first
+/// line for TCP clients, or the whole request head for HTTP.
+fn peek_head(stream: &TcpStream) -> io::Result<Vec<u8>> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let mut buf = vec![0u8; http::MAX_HEAD];
+    loop {
+        let n = stream.peek(&mut buf)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let head = &buf[..n];
+        let done = if head.starts_with(b"GET ") || head.starts_with(b"HEAD ") {
+            head.windows(4).any(|w| w == b"\r\n\r\n")
+        } else {
+            // Wait while this could still become "GET " or "HEAD ".
+            !(b"GET ".starts_with(head) || b"HEAD ".starts_with(head))
+        };
+        if done || n == buf.len() || Instant::now() > deadline {
+            buf.truncate(n);
+            return Ok(buf);
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn serve_tcp(stream: TcpStream, kb: &AnalogKeyboard) -> io::Result<()> {
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .take(MAX_LINE)
+        .read_line(&mut line)?;
+    let mut stream = stream;
+    let sub = match Subscription::parse(&line) {
+        Ok(sub) => sub,
+        Err(e) => {
+            let _ = writeln!(stream, "{{\"error\":{}}}", serde_json::json!(e.to_string()));
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+        }
+    };
+    stream.set_nodelay(true).ok();
+
+    let mut tracker = Tracker::new(sub);
+    loop {
+        let started = Instant::now();
+        // Nothing is written while the keyboard is idle, so a write error
+        // alone would not free the slot of a client that went away.
+        if client_closed(&mut stream)? {
+            return Ok(());
+        }
+        if let Some(frame) = tracker.next_frame(&kb.values(), kb.is_active()) {
+            match tracker.sub.format {
+                Format::Json => writeln!(stream, "{}", frame.to_json())?,
+                Format::Binary => stream.write_all(&frame.to_binary())?,
+            }
+        }
+        thread::sleep(tracker.sub.interval().saturating_sub(started.elapsed()));
+    }
+}
+
+/// True once a TCP client closed its end. Anything it sends after the
+/// subscription line is read and dropped.
+fn client_closed(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut buf = [0u8; 256];
+    let closed = loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break Ok(true),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(false),
+            Err(e) => break Err(e),
+        }
+    };
+    stream.set_nonblocking(false)?;
+    closed
+}
+
+/// Refuse browser pages that are not served from this machine.
+fn check_origin(req: &Request) -> Result<(), &'static str> {
+    let Some(origin) = req.headers().get("origin") else {
+        return Ok(());
+    };
+    let origin = origin.to_str().map_err(|_| "bad Origin")?;
+    let rest = origin.split_once("://").map_or("", |(_, r)| r);
+    let host = match rest.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => rest,
+    };
+    match host {
+        "127.0.0.1" | "localhost" | "[::1]" => Ok(()),
+        _ => Err("Origin not allowed"),
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn serve_websocket(stream: TcpStream, kb: &AnalogKeyboard) -> io::Result<()> {
+    let mut query = String::new();
+    let mut sub_error = None;
+    let callback = |req: &Request, resp: Response| -> Result<Response, ErrorResponse> {
+        let reject = |status: StatusCode, reason: String| {
+            let mut err = ErrorResponse::new(Some(reason));
+            *err.status_mut() = status;
+            err
+        };
+        check_origin(req).map_err(|r| reject(StatusCode::FORBIDDEN, r.into()))?;
+        query = req.uri().query().unwrap_or("").to_string();
+        if let Err(e) = Subscription::parse(&query) {
+            sub_error = Some(e.to_string());
+            return Err(reject(StatusCode::BAD_REQUEST, e.to_string()));
+        }
+        Ok(resp)
+    };
+    let accepted = tungstenite::accept_hdr(stream, callback).map_err(|e| e.to_string());
+    let mut ws = accepted.map_err(|e| io::Error::other(sub_error.unwrap_or(e)))?;
+    let sub = Subscription::parse(&query).unwrap_or_default();
+    run_websocket(&mut ws, kb, sub)
+}
+
+fn run_websocket(
+    ws: &mut WebSocket<TcpStream>,
+    kb: &AnalogKeyboard,
+    sub: Subscription,
+) -> io::Result<()> {
+    let mut tracker = Tracker::new(sub);
+    let mut next = Instant::now();
+    loop {
+        // Wait for the next tick while still reading re-subscriptions and pings.
+        let wait = next.saturating_duration_since(Instant::now());
+        ws.get_ref()
+            .set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+        match ws.read() {
+            Ok(Message::Text(text)) => match Subscription::parse(&text) {
+                Ok(sub) => tracker = Tracker::new(sub),
+                Err(e) => {
+                    let msg = serde_json::json!({ "error": e.to_string() }).to_string();
+                    ws.send(Message::Text(msg)).map_err(io::Error::other)?;
+                }
+            },
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(e) if crate::bridge::is_timeout(&e) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(io::Error::other(e)),
+        }
+        if Instant::now() < next {
+            continue;
+        }
+        next = Instant::now() + tracker.sub.interval();
+
+        if let Some(frame) = tracker.next_frame(&kb.values(), kb.is_active()) {
+            let msg = match tracker.sub.format {
+                Format::Json => Message::Text(frame.to_json()),
+                Format::Binary => Message::Binary(frame.to_binary()),
+            };
+            ws.send(msg).map_err(io::Error::other)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn() -> (AnalogKeyboard, SocketAddr) {
+        let kb = AnalogKeyboard::new(0x41E4, 0x2103);
+        let addr = serve(&kb, "127.0.0.1:0").unwrap();
+        (kb, addr)
+    }
+
+    fn press(kb: &AnalogKeyboard, sc: u8, v: f32) {
+        let mut vals = kb.values();
+        vals[sc as usize] = v;
+        kb.set_values(&vals);
+    }
+
+    fn ws_connect(
+        addr: SocketAddr,
+        query: &str,
+    ) -> WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>> {
+        let (ws, _) = tungstenite::connect(format!("ws://{addr}/?{query}")).unwrap();
+        ws
+    }
+
+    fn next_json(
+        ws: &mut WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>,
+    ) -> serde_json::Value {
+        loop {
+            if let Message::Text(t) = ws.read().unwrap() {
+                return serde_json::from_str(&t).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn parse_subscription() {
+        assert_eq!(Subscription::parse("").unwrap(), Subscription::default());
+        let sub = Subscription::parse("/?format=binary&keys=4,0x2C,29&rate=240").unwrap();
+        assert_eq!(sub.format, Format::Binary);
+        assert_eq!(sub.keys, Some(vec![4, 0x2C, 29]));
+        assert_eq!(sub.rate, 240);
+
+        assert!(Subscription::parse("rate=0").is_err());
+        assert!(Subscription::parse("rate=5000").is_err());
+        assert!(Subscription::parse("keys=300").is_err());
+        assert!(Subscription::parse("format=xml").is_err());
+        assert!(Subscription::parse("color=red").is_err());
+    }
+
+    #[test]
+    fn tracker_sends_only_changes() {
+        let mut t = Tracker::new(Subscription::parse("keys=4,5").unwrap());
+        let mut vals = [0.0f32; 256];
+
+        let first = t.next_frame(&vals, true).unwrap();
+        assert!(first.keys.is_empty(), "first frame carries the active flag");
+        assert_eq!(t.next_frame(&vals, true), None);
+
+        vals[4] = 1.0;
+        vals[6] = 1.0; // not subscribed
+        assert_eq!(t.next_frame(&vals, true).unwrap().keys, vec![(4, 65535)]);
+        assert_eq!(t.next_frame(&vals, true), None);
+
+        vals[4] = 0.0;
+        assert_eq!(t.next_frame(&vals, false).unwrap().keys, vec![(4, 0)]);
+    }
+
+    #[test]
+    fn binary_frame_layout() {
+        let frame = Frame {
+            t: 1.5,
+            active: true,
+            keys: vec![(0x2C, 0x1234)],
+        };
+        let b = frame.to_binary();
+        assert_eq!(&b[..8], &1.5f64.to_le_bytes());
+        assert_eq!(&b[8..], &[1, 1, 0, 0x2C, 0x34, 0x12]);
+        assert_eq!(
+            frame.to_json(),
+            r#"{"active":true,"keys":{"44":0.0711},"t":1.5}"#
+        );
+    }
+
+    #[test]
+    fn websocket_json_stream() {
+        let (kb, addr) = spawn();
+        let mut ws = ws_connect(addr, "keys=4&rate=200");
+        assert_eq!(next_json(&mut ws)["active"], false);
+
+        press(&kb, 0x04, 0.5);
+        press(&kb, 0x05, 0.5);
+        let frame = next_json(&mut ws);
+        assert_eq!(frame["keys"], serde_json::json!({ "4": 0.5 }));
+
+        // Re-subscribe to another key: state restarts from zero.
+        ws.send(Message::Text("keys=5&rate=200".into())).unwrap();
+        let frame = loop {
+            let f = next_json(&mut ws);
+            if f["keys"].get("5").is_some() {
+                break f;
+            }
+        };
+        assert!(frame["keys"].get("4").is_none());
+
+        ws.send(Message::Text("rate=0".into())).unwrap();
+        let err = next_json(&mut ws);
+        assert!(err["error"].as_str().unwrap().contains("rate"));
+    }
+
+    #[test]
+    fn websocket_binary_stream() {
+        let (kb, addr) = spawn();
+        press(&kb, 0x2C, 1.0);
+        let mut ws = ws_connect(addr, "format=binary&keys=0x2c");
+        let b = loop {
+            if let Message::Binary(b) = ws.read().unwrap() {
+                break b;
+            }
+        };
+        assert_eq!(&b[8..], &[0, 1, 0, 0x2C, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn websocket_rejects_foreign_origin_and_bad_query() {
+        use tungstenite::client::IntoClientRequest;
+        let (_kb, addr) = spawn();
+
+        let mut req = format!("ws://{addr}/").into_client_request().unwrap();
+        req.headers_mut()
+            .insert("Origin", "https://evil.example".parse().unwrap());
+        match tungstenite::connect(req) {
+            Err(tungstenite::Error::Http(resp)) => assert_eq!(resp.status(), StatusCode::FORBIDDEN),
+            other => panic!("expected 403, got {:?}", other.map(|_| ())),
+        }
+
+        let mut req = format!("ws://{addr}/").into_client_request().unwrap();
+        req.headers_mut()
+            .insert("Origin", "http://127.0.0.1:8080".parse().unwrap());
+        assert!(tungstenite::connect(req).is_ok());
+
+        match tungstenite::connect(format!("ws://{addr}/?format=xml")) {
+            Err(tungstenite::Error::Http(resp)) => {
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST)
+            }
+            other => panic!("expected 400, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn tcp_json_lines() {
+        let (kb, addr) = spawn();
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.write_all(b"keys=4&rate=200\n").unwrap();
+        let mut lines = BufReader::new(s.try_clone().unwrap()).lines();
+        let first: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(first["keys"], serde_json::json!({}));
+
+        press(&kb, 0x04, 0.25);
+        let frame: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(frame["keys"], serde_json::json!({ "4": 0.25 }));
+    }
+
+    #[test]
+    fn tcp_binary_and_errors() {
+        let (kb, addr) = spawn();
+        press(&kb, 0x04, 1.0);
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.write_all(b"format=binary\n").unwrap();
+        let mut b = [0u8; 14];
+        s.read_exact(&mut b).unwrap();
+        assert_eq!(&b[8..], &[0, 1, 0, 0x04, 0xFF, 0xFF]);
+
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.write_all(b"rate=abc\n").unwrap();
+        let mut out = String::new();
+        s.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("{\"error\":"), "{out}");
+    }
+
+    #[test]
+    fn idle_tcp_clients_that_leave_free_their_slot() {
+        let (_kb, addr) = spawn();
+        let subscribe = || {
+            let mut s = TcpStream::connect(addr).unwrap();
+            s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            s.write_all(b"rate=200\n").unwrap();
+            let mut first = String::new();
+            BufReader::new(&s).read_line(&mut first).unwrap();
+            first
+        };
+        for _ in 0..MAX_CLIENTS {
+            assert!(subscribe().contains("\"keys\""));
+        }
+        thread::sleep(Duration::from_millis(100));
+        // Refused if the 16 idle clients above still held their slots.
+        assert!(subscribe().contains("\"keys\""));
+    }
+
+    fn http_get(addr: SocketAddr, target: &str) -> String {
+        let mut s = TcpStream::connect(addr).unwrap();
+        write!(s, "GET {target} HTTP/1.1\r\nHost: {addr}\r\n\r\n").unwrap();
+        let mut out = String::new();
+        s.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn serves_overlay_over_http() {
+        let (_kb, addr) = spawn();
+        let page = http_get(addr, "/overlay?layout=60&caps=0");
+        assert!(page.starts_with("HTTP/1.1 200"), "{page}");
+        assert!(page.contains("/overlay.js"));
+        assert!(http_get(addr, "/overlay.json?layout=tkl").starts_with("HTTP/1.1 200"));
+        assert!(http_get(addr, "/favicon.ico").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn origin_check() {
+        let req = |origin: Option<&str>| {
+            let mut b = Request::builder().uri("/");
+            if let Some(o) = origin {
+                b = b.header("Origin", o);
+            }
+            b.body(()).unwrap()
+        };
+        assert!(check_origin(&req(None)).is_ok());
+        assert!(check_origin(&req(Some("http://localhost:3000"))).is_ok());
+        assert!(check_origin(&req(Some("http://127.0.0.1"))).is_ok());
+        assert!(check_origin(&req(Some("null"))).is_err());
+        assert!(check_origin(&req(Some("http://127.0.0.1.evil.com"))).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//! Per-key velocity, acceleration and keystroke peaks.
+//!
+//! Computed from the timestamped input reports (Chrome's receive time), so
+//! the result does not depend on the frame rate. Depth is 0..1 as in
+//! `values()`; velocity is depth per millisecond (positive = pressing) and
+//! acceleration is depth per millisecond squared.
+
+use std::collections::VecDeque;
+
+/// Below this depth a key is at rest; a keystroke runs from leaving rest to
+/// returning to it.
+pub const REST: f32 = 0.02;
+/// Completed keystrokes kept for `keystrokes_since`.
+const MAX_STROKES: usize = 1024;
+/// Reports closer than this are merged, to avoid dividing by ~0.
+const MIN_DT_MS: f64 = 0.05;
+/// A resting key may not report for a long time; assume a press started at
+/// most this long before its first report.
+const MAX_REST_DT_MS: f64 = 8.0;
+
+/// Motion of one key as of its latest report.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyDynamics {
+    pub depth: f32,
+    /// Depth per ms; positive while pressing, negative while releasing.
+    pub velocity: f32,
+    /// Depth per ms².
+    pub acceleration: f32,
+    /// Highest press velocity of the current keystroke (0 at rest).
+    pub peak_velocity: f32,
+    /// Deepest point of the current keystroke (0 at rest).
+    pub max_depth: f32,
+    /// Deepest point of any keystroke since tracking started.
+    pub session_max: f32,
+    /// Time of the latest report, page clock ms.
+    pub t_ms: f64,
+}
+
+/// One completed press, from leaving rest to returning to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keystroke {
+    pub scancode: u8,
+    pub start_ms: f64,
+    pub end_ms: f64,
+    /// Deepest point reached.
+    pub max_depth: f32,
+    /// Highest press velocity, depth per ms.
+    pub peak_velocity: f32,
+    /// Highest release velocity as a positive number, depth per ms.
+    pub peak_release_velocity: f32,
+}
+
+impl Keystroke {
+    pub fn duration_ms(&self) -> f64 {
+        self.end_ms - self.start_ms
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Track {
+    now: KeyDynamics,
+    /// Set once the key has reported at least once.
+    seen: bool,
+    stroke: Option<Keystroke>,
+}
+
+/// Dynamics for all 256 scancodes plus a history of keystrokes.
+pub struct DynamicsTracker {
+    keys: Box<[Track; 256]>,
+    strokes: VecDeque<Keystroke>,
+    /// Sequence number of `strokes[0]`.
+    first_seq: u64,
+}
+
+impl Default for DynamicsTracker {
+    fn default() -> Self {
+        Self {
+            keys: Box::new([Track::default(); 256]),
+            strokes: VecDeque::new(),
+            first_seq: 0,
+        }
+    }
+}
+
+impl DynamicsTracker {
+    /// Feed one report: `scancode` is now at `depth` at time `t_ms`.
+    pub fn record(&mut self, scancode: u8, depth: f32, t_ms: f64) {
+        let tr = &mut self.keys[scancode as usize];
+        let prev = tr.now;
+        let was_seen = tr.seen;
+        let mut dt = t_ms - prev.t_ms;
+        if prev.depth <= REST {
+            dt = dt.min(MAX_REST_DT_MS);
+        }
+        if was_seen && dt < MIN_DT_MS {
+            // Same instant (or clock went back): keep the motion, take the depth.
+            tr.now.depth = depth;
+            tr.now.t_ms = tr.now.t_ms.max(t_ms);
+        } else {
+            let (velocity, acceleration) = if was_seen {
+                let velocity = (depth - prev.depth) / dt as f32;
+                (velocity, (velocity - prev.velocity) / dt as f32)
+            } else {
+                (0.0, 0.0)
+            };
+            tr.now = KeyDynamics {
+                depth,
+                velocity,
+                acceleration,
+                peak_velocity: prev.peak_velocity,
+                max_depth: prev.max_depth,
+                session_max: prev.session_max,
+                t_ms,
+            };
+        }
+        tr.seen = true;
+
+        let v = tr.now.velocity;
+        if depth > REST {
+            let s = tr.stroke.get_or_insert(Keystroke {
+                scancode,
+                start_ms: t_ms,
+                end_ms: t_ms,
+                max_depth: 0.0,
+                peak_velocity: 0.0,
+                peak_release_velocity: 0.0,
+            });
+            s.max_depth = s.max_depth.max(depth);
+            s.peak_velocity = s.peak_velocity.max(v);
+            s.peak_release_velocity = s.peak_release_velocity.max(-v);
+            tr.now.peak_velocity = s.peak_velocity;
+            tr.now.max_depth = s.max_depth;
+            tr.now.session_max = tr.now.session_max.max(s.max_depth);
+        } else {
+            tr.now.peak_velocity = 0.0;
+            tr.now.max_depth = 0.0;
+            if let Some(mut s) = tr.stroke.take() {
+                s.end_ms = t_ms;
+                s.peak_release_velocity = s.peak_release_velocity.max(-v);
+                if self.strokes.len() == MAX_STROKES {
+                    self.strokes.pop_front();
+                    self.first_seq += 1;
+                }
+                self.strokes.push_back(s);
+            }
+        }
+    }
+
+    pub fn key(&self, scancode: u8) -> KeyDynamics {
+        self.keys[scancode as usize].now
+    }
+
+    pub fn all(&self) -> [KeyDynamics; 256] {
+        std::array::from_fn(|i| self.keys[i].now)
+    }
+
+    /// Keystrokes completed after sequence number `seq`, and the sequence
+    /// number to pass next time. Start with 0; strokes older than the
+    /// history are skipped.
+    pub fn keystrokes_since(&self, seq: u64) -> (Vec<Keystroke>, u64) {
+        let next = self.first_seq + self.strokes.len() as u64;
+        let skip = seq.saturating_sub(self.first_seq) as usize;
+        (self.strokes.iter().skip(skip).copied().collect(), next)
+    }
+
+    /// Press time of the earliest keystroke still in progress.
+    pub fn held_since(&self) -> Option<f64> {
+        self.keys
+            .iter()
+            .filter_map(|t| t.stroke.map(|s| s.start_ms))
+            .min_by(f64::total_cmp)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn velocity_and_acceleration() {
+        let mut d = DynamicsTracker::default();
+        d.record(4, 0.1, 100.0);
+        d.record(4, 0.2, 110.0); // 0.01 / ms
+        let k = d.key(4);
+        assert!(close(k.velocity, 0.01));
+        assert!(close(k.acceleration, 0.001));
+        d.record(4, 0.5, 120.0); // 0.03 / ms
+        let k = d.key(4);
+        assert!(close(k.velocity, 0.03));
+        assert!(close(k.acceleration, 0.002));
+        assert!(close(k.peak_velocity, 0.03));
+
+        d.record(4, 0.4, 130.0);
+        let k = d.key(4);
+        assert!(k.velocity < 0.0);
+        assert!(close(k.peak_velocity, 0.03), "peak survives the slowdown");
+    }
+
+    #[test]
+    fn keystrokes_are_recorded() {
+        let mut d = DynamicsTracker::default();
+        d.record(4, 0.0, 0.0);
+        d.record(4, 0.5, 10.0);
+        d.record(5, 0.5, 12.0);
+        d.record(4, 1.0, 15.0);
+        assert_eq!(d.held_since(), Some(10.0));
+        d.record(4, 0.5, 40.0);
+        d.record(4, 0.0, 45.0);
+        assert_eq!(d.key(4).peak_velocity, 0.0);
+        assert_eq!(d.held_since(), Some(12.0));
+
+        let (strokes, next) = d.keystrokes_since(0);
+        assert_eq!(next, 1);
+        let s = strokes[0];
+        assert_eq!((s.scancode, s.start_ms, s.end_ms), (4, 10.0, 45.0));
+        assert_eq!(s.max_depth, 1.0);
+        assert!(close(s.peak_velocity, 0.1));
+        assert!(close(s.peak_release_velocity, 0.1));
+
+        assert!(d.keystrokes_since(next).0.is_empty());
+    }
+
+    #[test]
+    fn depth_peaks() {
+        let mut d = DynamicsTracker::default();
+        d.record(4, 0.6, 0.0);
+        d.record(4, 0.9, 1.0);
+        d.record(4, 0.7, 2.0);
+        assert_eq!((d.key(4).max_depth, d.key(4).session_max), (0.9, 0.9));
+        d.record(4, 0.0, 3.0);
+        d.record(4, 0.4, 10.0);
+        assert_eq!((d.key(4).max_depth, d.key(4).session_max), (0.4, 0.9));
+        d.record(4, 0.0, 11.0);
+        assert_eq!((d.key(4).max_depth, d.key(4).session_max), (0.0, 0.9));
+    }
+
+    #[test]
+    fn long_rest_does_not_flatten_velocity() {
+        let mut d = DynamicsTracker::default();
+        d.record(4, 0.0, 0.0);
+        d.record(4, 0.4, 60_000.0);
+        assert!(close(d.key(4).velocity, 0.4 / MAX_REST_DT_MS as f32));
+    }
+
+    #[test]
+    fn same_timestamp_does_not_blow_up() {
+        let mut d = DynamicsTracker::default();
+        d.record(4, 0.0, 10.0);
+        d.record(4, 0.5, 10.0);
+        let k = d.key(4);
+        assert!(k.velocity.is_finite());
+        assert_eq!(k.depth, 0.5);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut d = DynamicsTracker::default();
+        for i in 0..(MAX_STROKES + 10) {
+            let t = i as f64 * 10.0;
+            d.record(4, 1.0, t);
+            d.record(4, 0.0, t + 5.0);
+        }
+        let (strokes, next) = d.keystrokes_since(0);
+        assert_eq!(strokes.len(), MAX_STROKES);
+        assert_eq!(next, (MAX_STROKES + 10) as u64);
+        let (recent, _) = d.keystrokes_since(next - 2);
+        assert_eq!(recent.len(), 2);
+    }
+}
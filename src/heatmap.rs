@@ -0,0 +1,268 @@
+//! Long-term usage per key: press count and total travel.
+//!
+//! With the analog bridge, [`Heatmap`] is fed at report resolution: every
+//! depth report (`AnalogKeyboard::reports_since`) for travel, and every
+//! completed keystroke (`AnalogKeyboard::keystrokes_since`) that went past
+//! [`PRESS`] as a press, so a tap between two frames still counts. The Bevy
+//! keyboard fallback has no reports; it is sampled once per frame, where a
+//! press must drop back below [`RELEASE`] before it counts again. Travel is
+//! the summed depth change in both directions, in full key travels (one
+//! press to the bottom and back is 2.0).
+//!
+//! Sessions persist as JSON so several can be accumulated and layouts
+//! compared.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Keystroke;
+
+/// Depth at which a press is counted.
+pub const PRESS: f32 = 0.5;
+/// Depth a key must return below before the next press counts.
+pub const RELEASE: f32 = 0.3;
+/// Full key travel of the supported switches, in mm.
+pub const TRAVEL_MM: f32 = 4.0;
+
+/// What the heatmap colours keys by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Metric {
+    #[default]
+    Presses,
+    Travel,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heatmap {
+    presses: [u64; 256],
+    travel: [f64; 256],
+    last: [f32; 256],
+    down: [bool; 256],
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self {
+            presses: [0; 256],
+            travel: [0.0; 256],
+            last: [0.0; 256],
+            down: [false; 256],
+        }
+    }
+}
+
+/// On-disk form; only keys that were used are stored.
+#[derive(Serialize, Deserialize, Default)]
+struct Saved {
+    presses: BTreeMap<u8, u64>,
+    travel: BTreeMap<u8, f64>,
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed one depth report of a key (0..1), for travel.
+    pub fn record(&mut self, scancode: u8, depth: f32) {
+        let i = scancode as usize;
+        let v = depth.clamp(0.0, 1.0);
+        self.travel[i] += (v - self.last[i]).abs() as f64;
+        self.last[i] = v;
+    }
+
+    /// Count a completed keystroke if it went at least [`PRESS`] deep.
+    pub fn add_keystroke(&mut self, s: &Keystroke) {
+        if s.max_depth >= PRESS {
+            self.presses[s.scancode as usize] += 1;
+        }
+    }
+
+    /// Feed one analog snapshot (0..1 per scancode), for input without
+    /// reports.
+    pub fn update(&mut self, values: &[f32; 256]) {
+        for (i, &v) in values.iter().enumerate() {
+            self.record(i as u8, v);
+            let v = v.clamp(0.0, 1.0);
+            if !self.down[i] && v >= PRESS {
+                self.down[i] = true;
+                self.presses[i] += 1;
+            } else if self.down[i] && v < RELEASE {
+                self.down[i] = false;
+            }
+        }
+    }
+
+    pub fn presses(&self, scancode: u8) -> u64 {
+        self.presses[scancode as usize]
+    }
+
+    /// Total travel in full key travels.
+    pub fn travel(&self, scancode: u8) -> f64 {
+        self.travel[scancode as usize]
+    }
+
+    pub fn get(&self, scancode: u8, metric: Metric) -> f64 {
+        match metric {
+            Metric::Presses => self.presses(scancode) as f64,
+            Metric::Travel => self.travel(scancode),
+        }
+    }
+
+    /// Largest value over all keys, for normalising colours.
+    pub fn max(&self, metric: Metric) -> f64 {
+        (0..=255u8)
+            .map(|sc| self.get(sc, metric))
+            .fold(0.0, f64::max)
+    }
+
+    /// `get` scaled so the most used key is 1.0 (all 0 when nothing was used).
+    pub fn normalized(&self, scancode: u8, metric: Metric) -> f32 {
+        let max = self.max(metric);
+        if max > 0.0 {
+            (self.get(scancode, metric) / max) as f32
+        } else {
+            0.0
+        }
+    }
+
+    pub fn total_presses(&self) -> u64 {
+        self.presses.iter().sum()
+    }
+
+    /// Forget all counts. Keys held right now are not counted again.
+    pub fn reset(&mut self) {
+        self.presses = [0; 256];
+        self.travel = [0.0; 256];
+    }
+
+    /// Add the counts of another session.
+    pub fn merge(&mut self, other: &Heatmap) {
+        for i in 0..256 {
+            self.presses[i] += other.presses[i];
+            self.travel[i] += other.travel[i];
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut saved = Saved::default();
+        for sc in 0..=255u8 {
+            if self.presses(sc) > 0 {
+                saved.presses.insert(sc, self.presses(sc));
+            }
+            if self.travel(sc) > 0.0 {
+                saved.travel.insert(sc, self.travel(sc));
+            }
+        }
+        serde_json::to_string_pretty(&saved).unwrap_or_default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let saved: Saved = serde_json::from_str(json)?;
+        let mut h = Self::default();
+        for (sc, n) in saved.presses {
+            h.presses[sc as usize] = n;
+        }
+        for (sc, t) in saved.travel {
+            h.travel[sc as usize] = t;
+        }
+        Ok(h)
+    }
+
+    /// Load a saved heatmap; a missing file is an empty one.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => Self::from_json(&json).map_err(io::Error::other),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(h: &mut Heatmap, sc: u8, v: f32) {
+        let mut vals = [0.0; 256];
+        vals[sc as usize] = v;
+        h.update(&vals);
+    }
+
+    #[test]
+    fn counts_presses_with_hysteresis() {
+        let mut h = Heatmap::new();
+        for v in [0.0, 0.6, 1.0, 0.4, 0.6, 0.2, 0.0, 0.55, 0.0] {
+            frame(&mut h, 4, v);
+        }
+        // The dip to 0.4 does not release the key.
+        assert_eq!(h.presses(4), 2);
+        assert_eq!(h.presses(5), 0);
+        assert_eq!(h.total_presses(), 2);
+    }
+
+    #[test]
+    fn travel_sums_both_directions() {
+        let mut h = Heatmap::new();
+        for v in [0.0, 0.5, 1.0, 0.0] {
+            frame(&mut h, 4, v);
+        }
+        assert!((h.travel(4) - 2.0).abs() < 1e-6);
+        assert_eq!(h.normalized(4, Metric::Travel), 1.0);
+        assert_eq!(h.normalized(5, Metric::Travel), 0.0);
+    }
+
+    #[test]
+    fn reports_and_keystrokes() {
+        let mut h = Heatmap::new();
+        // A tap that is over within one frame: only the reports see it.
+        for d in [0.3, 0.8, 0.2, 0.0] {
+            h.record(4, d);
+        }
+        assert!((h.travel(4) - 1.6).abs() < 1e-6);
+        let stroke = |max_depth| Keystroke {
+            scancode: 4,
+            start_ms: 0.0,
+            end_ms: 8.0,
+            max_depth,
+            peak_velocity: 0.1,
+            peak_release_velocity: 0.1,
+        };
+        h.add_keystroke(&stroke(0.8));
+        h.add_keystroke(&stroke(0.3));
+        assert_eq!(h.presses(4), 1, "too shallow to count");
+    }
+
+    #[test]
+    fn reset_does_not_recount_held_keys() {
+        let mut h = Heatmap::new();
+        frame(&mut h, 4, 1.0);
+        h.reset();
+        frame(&mut h, 4, 1.0);
+        assert_eq!(h.presses(4), 0);
+        assert_eq!(h.travel(4), 0.0);
+    }
+
+    #[test]
+    fn json_round_trip_and_merge() {
+        let mut h = Heatmap::new();
+        frame(&mut h, 4, 1.0);
+        frame(&mut h, 4, 0.0);
+        frame(&mut h, 44, 1.0);
+        let mut back = Heatmap::from_json(&h.to_json()).unwrap();
+        assert_eq!(back.presses(4), 1);
+        assert_eq!(back.presses(44), 1);
+        assert!((back.travel(4) - 2.0).abs() < 1e-6);
+
+        back.merge(&h);
+        assert_eq!(back.presses(4), 2);
+        assert_eq!(back.max(Metric::Presses), 2.0);
+    }
+}
@@ -0,0 +1,117 @@
+//! Recent depth reports per key, at full report resolution.
+//!
+//! Every analog report is kept for [`HISTORY_MS`] (page clock, like
+//! [`dynamics`](crate::dynamics)), so a key's depth curve can be drawn
+//! without sampling it at the frame rate.
+
+use std::collections::VecDeque;
+
+/// How far back reports are kept.
+pub const HISTORY_MS: f64 = 30_000.0;
+/// Per-key cap, in case a key reports much faster than expected.
+const MAX_SAMPLES: usize = 65_536;
+
+pub struct KeyHistory {
+    keys: Vec<VecDeque<(f64, f32)>>,
+    /// Sequence number of each key's oldest kept report.
+    first_seq: Vec<u64>,
+    latest_ms: Option<f64>,
+}
+
+impl Default for KeyHistory {
+    fn default() -> Self {
+        Self {
+            keys: vec![VecDeque::new(); 256],
+            first_seq: vec![0; 256],
+            latest_ms: None,
+        }
+    }
+}
+
+impl KeyHistory {
+    pub fn record(&mut self, scancode: u8, depth: f32, t_ms: f64) {
+        let q = &mut self.keys[scancode as usize];
+        q.push_back((t_ms, depth));
+        while q.len() > MAX_SAMPLES || q.front().is_some_and(|s| s.0 < t_ms - HISTORY_MS) {
+            q.pop_front();
+            self.first_seq[scancode as usize] += 1;
+        }
+        self.latest_ms = Some(self.latest_ms.map_or(t_ms, |l| l.max(t_ms)));
+    }
+
+    /// Time of the newest report of any key.
+    pub fn latest_ms(&self) -> Option<f64> {
+        self.latest_ms
+    }
+
+    /// Reports of `scancode` at or after `since_ms`, oldest first, preceded
+    /// by the last report before it (the depth the key had at `since_ms`).
+    pub fn since(&self, scancode: u8, since_ms: f64) -> Vec<(f64, f32)> {
+        let q = &self.keys[scancode as usize];
+        let start = q.partition_point(|s| s.0 < since_ms).saturating_sub(1);
+        q.range(start..).copied().collect()
+    }
+
+    /// Depths reported for `scancode` after sequence number `seq`, and the
+    /// sequence number to pass next time. Start with 0; reports older than
+    /// the history are skipped.
+    pub fn depths_since(&self, scancode: u8, seq: u64) -> (Vec<f32>, u64) {
+        let q = &self.keys[scancode as usize];
+        let first = self.first_seq[scancode as usize];
+        let skip = seq.saturating_sub(first) as usize;
+        (
+            q.iter().skip(skip).map(|s| s.1).collect(),
+            first + q.len() as u64,
+        )
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn since_includes_the_preceding_report() {
+        let mut h = KeyHistory::default();
+        for (t, d) in [(0.0, 0.0), (10.0, 0.5), (20.0, 1.0), (30.0, 0.0)] {
+            h.record(4, d, t);
+        }
+        assert_eq!(
+            h.since(4, 15.0),
+            vec![(10.0, 0.5), (20.0, 1.0), (30.0, 0.0)]
+        );
+        assert_eq!(h.since(4, 0.0).len(), 4);
+        assert_eq!(h.since(4, 100.0), vec![(30.0, 0.0)]);
+        assert!(h.since(5, 0.0).is_empty());
+        assert_eq!(h.latest_ms(), Some(30.0));
+    }
+
+    #[test]
+    fn old_reports_expire() {
+        let mut h = KeyHistory::default();
+        h.record(4, 0.5, 0.0);
+        h.record(4, 0.6, HISTORY_MS / 2.0);
+        h.record(4, 0.7, HISTORY_MS + 1.0);
+        assert_eq!(h.since(4, f64::MIN).len(), 2);
+    }
+
+    #[test]
+    fn depths_since_sequence() {
+        let mut h = KeyHistory::default();
+        h.record(4, 0.5, 0.0);
+        h.record(4, 0.6, 1.0);
+        assert_eq!(h.depths_since(4, 0), (vec![0.5, 0.6], 2));
+        // Same timestamp as the last one: still new.
+        h.record(4, 0.7, 1.0);
+        assert_eq!(h.depths_since(4, 2), (vec![0.7], 3));
+        assert_eq!(h.depths_since(5, 0), (vec![], 0));
+
+        // Expired reports are skipped, the numbering goes on.
+        h.record(4, 0.8, HISTORY_MS + 0.5);
+        assert_eq!(h.depths_since(4, 0), (vec![0.6, 0.7, 0.8], 4));
+    }
+}
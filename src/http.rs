@@ -0,0 +1,432 @@
+//! Minimal HTTP/1.1 server for the bridge page, the OBS overlay and their
+//! assets.
+//!
+//! | route              | response                                          |
+//! |--------------------|---------------------------------------------------|
+//! | `/?token=..`       | bridge page (403 without the session token)       |
+//! | `/bridge.js`       | page script                                       |
+//! | `/bridge.css`      | page stylesheet                                   |
+//! | `/status?token=..` | JSON snapshot of the `AnalogKeyboard` state (403  |
+//! |                    | without the token or from another page's Origin)  |
+//! | `/health`          | `ok`                                              |
+//!
+//! The overlay routes are answered on the broadcast port (see
+//! `broadcast.rs`), whose WebSocket feeds the page:
+//!
+//! | route                               | response                         |
+//! |-------------------------------------|----------------------------------|
+//! | `/overlay?layout=60&theme=default`  | overlay page (`overlay.js`)      |
+//! | `/overlay.json?layout=..&theme=..`  | key rects in pixels and colours  |
+//! | `/health`                           | `ok`                             |
+//!
+//! Without `layout`, the overlay shows the layout the application set with
+//! `AnalogKeyboard::set_layout`.
+//!
+//! Every response closes the connection. Only `GET` and `HEAD` are served.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::bridge::BridgeAuth;
+use crate::layout::{self, Layout, KEY_GAP, KEY_H, KEY_UNIT};
+use crate::protocol::PROTOCOL_VERSION;
+use crate::theme::{self, Theme};
+use crate::AnalogKeyboard;
+
+pub(crate) const MAX_HEAD: usize = 8 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+const BRIDGE_JS: &str = include_str!("bridge.js");
+const BRIDGE_CSS: &str = include_str!("bridge.css");
+const OVERLAY_HTML: &str = include_str!("overlay.html");
+const OVERLAY_JS: &str = include_str!("overlay.js");
+
+/// A parsed request head; bodies are not read.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Request {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or("")
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The value of query parameter `name`, undecoded.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        let query = self.target.split_once('?')?.1;
+        query.split('&').find_map(|kv| match kv.split_once('=') {
+            Some((k, v)) if k == name => Some(v),
+            _ => None,
+        })
+    }
+
+    /// True for a WebSocket upgrade request.
+    pub fn is_upgrade(&self) -> bool {
+        self.header("upgrade")
+            .is_some_and(|u| u.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ParseError {
+    /// Connection closed or timed out before the head was complete.
+    Incomplete,
+    TooLarge,
+    Malformed,
+}
+
+/// Parse a request head (`request line` + headers + blank line).
+pub(crate) fn parse_request(head: &[u8]) -> Result<Request, ParseError> {
+    let text = std::str::from_utf8(head).map_err(|_| ParseError::Malformed)?;
+    let mut lines = text.split("\r\n");
+
+    let mut parts = lines.next().unwrap_or("").split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ParseError::Malformed);
+    };
+    if method.is_empty() || !target.starts_with('/') || !version.starts_with("HTTP/1.") {
+        return Err(ParseError::Malformed);
+    }
+
+    let mut headers = Vec::new();
+    for line in lines.take_while(|l| !l.is_empty()) {
+        let (k, v) = line.split_once(':').ok_or(ParseError::Malformed)?;
+        if k.is_empty() || k.contains(' ') {
+            return Err(ParseError::Malformed);
+        }
+        headers.push((k.to_string(), v.trim().to_string()));
+    }
+
+    Ok(Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        headers,
+    })
+}
+
+/// Read from the stream until the end of the request head.
+fn read_head(stream: &mut TcpStream) -> Result<Vec<u8>, ParseError> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            if end + 4 > MAX_HEAD {
+                return Err(ParseError::TooLarge);
+            }
+            buf.truncate(end + 4);
+            return Ok(buf);
+        }
+        if buf.len() > MAX_HEAD {
+            return Err(ParseError::TooLarge);
+        }
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return Err(ParseError::Incomplete),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+pub(crate) struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Self {
+        Self::new(status, "text/plain;charset=utf-8", body)
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn write_to(&self, w: &mut impl Write, head_only: bool) -> std::io::Result<()> {
+        let mut out = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Cache-Control: no-store\r\nX-Content-Type-Options: nosniff\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len()
+        );
+        if self.status == 405 {
+            out.push_str("Allow: GET, HEAD\r\n");
+        }
+        out.push_str("Connection: close\r\n\r\n");
+        w.write_all(out.as_bytes())?;
+        if !head_only {
+            w.write_all(&self.body)?;
+        }
+        w.flush()
+    }
+}
+
+/// Everything the routes need: the rendered page, its token and the keyboard.
+pub(crate) struct Site {
+    pub html: String,
+    pub auth: BridgeAuth,
+    pub kb: AnalogKeyboard,
+}
+
+fn status_json(kb: &AnalogKeyboard) -> String {
+    let values = kb.values();
+    let pressed: Vec<usize> = (0..values.len()).filter(|&i| values[i] > 0.01).collect();
+    let latency = kb.latency_report();
+    serde_json::json!({
+        "status": kb.status(),
+        "active": kb.is_active(),
+        "protocolVersion": PROTOCOL_VERSION,
+        "vendorId": kb.vid(),
+        "productId": kb.pid(),
+        "device": kb.device_info(),
+        "pressed": pressed,
+        "latency": {
+            "bridgeP50": latency.bridge.percentile(0.5),
+            "bridgeP99": latency.bridge.percentile(0.99),
+            "frameP50": latency.frame.percentile(0.5),
+            "frameP99": latency.frame.percentile(0.99),
+        },
+    })
+    .to_string()
+}
+
+pub(crate) fn route(req: &Request, site: &Site) -> Response {
+    match req.path() {
+        "/" | "/index.html" => {
+            // The page embeds the WebSocket token, so only hand it to the
+            // browser we launched with the token in its URL.
+            if site.auth.has_token(&req.target) {
+                Response::new(200, "text/html;charset=utf-8", site.html.as_bytes())
+            } else {
+                log::warn!("[HID] rejected page request without token");
+                Response::text(403, "forbidden\n")
+            }
+        }
+        "/bridge.js" => Response::new(200, "text/javascript;charset=utf-8", BRIDGE_JS),
+        "/bridge.css" => Response::new(200, "text/css;charset=utf-8", BRIDGE_CSS),
+        "/status" => {
+            // Lists pressed keys, so it takes the token too, and a browser
+            // fetch must come from our own page (non-browser clients send no
+            // `Origin`).
+            let origin_ok = req.header("origin").is_none_or(|o| site.auth.is_origin(o));
+            if site.auth.has_token(&req.target) && origin_ok {
+                Response::new(200, "application/json", status_json(&site.kb))
+            } else {
+                log::warn!("[HID] rejected status request without token or from another origin");
+                Response::text(403, "forbidden\n")
+            }
+        }
+        "/health" => Response::text(200, "ok\n"),
+        _ => Response::text(404, "not found\n"),
+    }
+}
+
+/// What the overlay page draws: each key's rectangles in pixels, laid out
+/// like the visualizer's flat view, and the theme colours it is filled with.
+/// `rect2` is relative to the key; `r` turns the key clockwise about
+/// `rx`, `ry`.
+fn overlay_json(layout: &Layout, theme: &Theme) -> String {
+    // A key of n units spans the n - 1 gaps inside it.
+    let px = |u: f32, unit: f32| u * (unit + KEY_GAP);
+    let size = |n: f32, unit: f32| px(n, unit) - KEY_GAP;
+    let keys: Vec<_> = layout
+        .keys
+        .iter()
+        .map(|k| {
+            let mut key = serde_json::json!({
+                "sc": k.scancode,
+                "label": k.label,
+                "x": px(k.x, KEY_UNIT),
+                "y": px(k.y, KEY_H),
+                "w": size(k.w, KEY_UNIT),
+                "h": size(k.h, KEY_H),
+            });
+            if let Some([x, y, w, h]) = k.rect2 {
+                key["rect2"] = serde_json::json!([
+                    px(x, KEY_UNIT),
+                    px(y, KEY_H),
+                    size(w, KEY_UNIT),
+                    size(h, KEY_H)
+                ]);
+            }
+            if k.r != 0.0 {
+                key["r"] = k.r.into();
+                key["rx"] = px(k.rx, KEY_UNIT).into();
+                key["ry"] = px(k.ry, KEY_H).into();
+            }
+            key
+        })
+        .collect();
+    serde_json::json!({
+        "width": size(layout.width(), KEY_UNIT),
+        "height": size(layout.height(), KEY_H),
+        "keys": keys,
+        "cap": theme.cap,
+        "capPressed": theme.cap_pressed,
+        "label": theme.label,
+        "value": theme.value,
+        "fill": theme.fill,
+    })
+    .to_string()
+}
+
+/// The OBS overlay page, its script and the geometry and colours for a
+/// built-in layout (by default the one `kb` shows) and theme.
+pub(crate) fn overlay_route(req: &Request, kb: &AnalogKeyboard) -> Response {
+    match req.path() {
+        "/" | "/overlay" => Response::new(200, "text/html;charset=utf-8", OVERLAY_HTML),
+        "/overlay.js" => Response::new(200, "text/javascript;charset=utf-8", OVERLAY_JS),
+        "/overlay.json" => {
+            let layout = match req.query("layout") {
+                Some(name) => layout::by_name(name),
+                None => kb.layout().or_else(|| layout::by_name("60")),
+            };
+            let Some(layout) = layout else {
+                return Response::text(404, "unknown layout\n");
+            };
+            let Some(theme) = theme::by_name(req.query("theme").unwrap_or("default")) else {
+                return Response::text(404, "unknown theme\n");
+            };
+            Response::new(200, "application/json", overlay_json(&layout, &theme))
+        }
+        "/health" => Response::text(200, "ok\n"),
+        _ => Response::text(404, "not found\n"),
+    }
+}
+
+/// Answer one request on `stream` with `route` and close it.
+pub(crate) fn handle(mut stream: TcpStream, route: impl Fn(&Request) -> Response) {
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+    let (resp, head_only) = match read_head(&mut stream).and_then(|h| parse_request(&h)) {
+        Ok(req) if req.method != "GET" && req.method != "HEAD" => {
+            (Response::text(405, "method not allowed\n"), false)
+        }
+        Ok(req) => (route(&req), req.method == "HEAD"),
+        Err(ParseError::Incomplete) => return,
+        Err(ParseError::TooLarge) => (Response::text(431, "request head too large\n"), false),
+        Err(ParseError::Malformed) => (Response::text(400, "bad request\n"), false),
+    };
+    let _ = resp.write_to(&mut stream, head_only);
+    // Closing with unread input would reset the connection before the client
+    // sees an error response, so drain what is left (bounded by the timeout).
+    if resp.status >= 400 {
+        let _ = stream.shutdown(std::net::Shutdown::Write);
+        let mut sink = [0u8; 1024];
+        while matches!(stream.read(&mut sink), Ok(n) if n > 0) {}
+    }
+}
+
+/// Serve requests on `listener` forever, one thread per connection.
+pub(crate) fn serve(listener: TcpListener, site: Arc<Site>) {
+    for stream in listener.incoming().flatten() {
+        let site = site.clone();
+        thread::spawn(move || handle(stream, |req| route(req, &site)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_geometry_and_colours() {
+        let kb = AnalogKeyboard::new(0x41E4, 0x2103);
+        let get = |target: &str| {
+            let req = parse_request(format!("GET {target} HTTP/1.1\r\n\r\n").as_bytes());
+            overlay_route(&req.unwrap(), &kb)
+        };
+        assert_eq!(get("/overlay?layout=60").status, 200);
+        assert_eq!(
+            get("/overlay.js").content_type,
+            "text/javascript;charset=utf-8"
+        );
+
+        let resp = get("/overlay.json?layout=60&theme=light");
+        assert_eq!(resp.status, 200);
+        let json: serde_json::Value = serde_json::from_slice(&resp.body).unwrap();
+        // Esc, then Space: 6.25 units wide from the fifth row.
+        assert_eq!(
+            json["keys"][0],
+            serde_json::json!({ "sc": 0x29, "label": "Esc", "x": 0.0, "y": 0.0, "w": 46.0, "h": 42.0 })
+        );
+        assert_eq!(json["keys"][56]["label"], "Space");
+        assert_eq!(json["keys"][56]["y"], 4.0 * 46.0);
+        assert_eq!(json["keys"][56]["w"], 6.25 * 50.0 - 4.0);
+        assert_eq!(json["width"], 15.0 * 50.0 - 4.0);
+        let light = theme::by_name("light").unwrap();
+        assert_eq!(json["cap"], serde_json::to_value(light.cap).unwrap());
+        assert!(json["fill"].as_array().unwrap().len() > 1);
+
+        // Without a layout, the one the app shows: stepped and rotated keys.
+        kb.set_layout(
+            &Layout::from_json(
+                r#"{ "rows": [
+                    [{ "sc": "0x28", "w": 1.25, "h": 2, "rect2": [-0.25, 0, 1.5, 1] }],
+                    [],
+                    [{ "sc": 4, "r": 90, "rx": 1, "ry": 2 }]
+                ] }"#,
+            )
+            .unwrap(),
+        );
+        let json: serde_json::Value = serde_json::from_slice(&get("/overlay.json").body).unwrap();
+        let (enter, a) = (&json["keys"][0], &json["keys"][1]);
+        assert_eq!(enter["rect2"], serde_json::json!([-12.5, 0.0, 71.0, 42.0]));
+        assert!(enter.get("r").is_none());
+        assert_eq!(
+            (a["r"].as_f64(), a["rx"].as_f64()),
+            (Some(90.0), Some(50.0))
+        );
+        assert_eq!(a["ry"], 92.0);
+
+        assert_eq!(get("/overlay.json?layout=nope").status, 404);
+        assert_eq!(get("/overlay.json?theme=nope").status, 404);
+        assert_eq!(get("/bridge.js").status, 404);
+    }
+
+    #[test]
+    fn parse_request_line_and_headers() {
+        let req = parse_request(b"GET /a?b=1 HTTP/1.1\r\nHost: x\r\nOrigin:  y \r\n\r\n").unwrap();
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.path(), "/a");
+        assert_eq!(req.header("ORIGIN"), Some("y"));
+        assert_eq!(req.query("b"), Some("1"));
+        assert_eq!(req.query("c"), None);
+        assert!(!req.is_upgrade());
+        assert_eq!(
+            parse_request(b"GET a HTTP/1.1\r\n\r\n"),
+            Err(ParseError::Malformed)
+        );
+    }
+}
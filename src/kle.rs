@@ -0,0 +1,521 @@
+//! Import layouts from keyboard-layout-editor.com (KLE).
+//!
+//! Accepts both the editor's "Raw data" (rows without the outer brackets,
+//! unquoted property names) and downloaded JSON. Key positions follow KLE:
+//! `x`/`y` move the cursor, `w`/`h` size the next key, `x2`/`y2`/`w2`/`h2`
+//! give stepped and ISO Enter keys their second rectangle, and `r`/`rx`/`ry`
+//! rotate a cluster. Decals (`d`) are skipped.
+//!
+//! Legends are mapped to US ANSI HID scancodes: each line of a legend is
+//! tried bottom-up ("!\n1" is `1`), names such as "Caps Lock", "PgUp" or
+//! "Num Lock" are understood, and keys that appear twice (Shift, Ctrl,
+//! Enter, numpad digits and symbols) get the right-hand or numpad scancode
+//! the second time. The whole legend is returned in `unmapped` for keys
+//! that match nothing.
+
+use serde_json::Value;
+
+use crate::layout::{Key, Layout};
+
+pub struct Import {
+    pub layout: Layout,
+    /// Legends of the keys that were left out because no scancode matched.
+    pub unmapped: Vec<String>,
+}
+
+/// Parse KLE raw data or JSON.
+pub fn import(text: &str) -> Result<Import, serde_json::Error> {
+    let (name, placed) = place(parse_rows(text)?);
+    let (keys, unmapped) = assign_scancodes(placed);
+    let mut layout = Layout {
+        name,
+        keys,
+        fn_layer: None,
+    };
+    normalize(&mut layout);
+    Ok(Import { layout, unmapped })
+}
+
+/// Walk KLE rows: the name from the metadata, and each key with its legend.
+pub(crate) fn place(rows: Vec<Value>) -> (String, Vec<(String, Key)>) {
+    let mut name = String::new();
+    let mut cur = Cursor::default();
+    let mut placed = Vec::new();
+
+    for row in rows {
+        let items = match row {
+            Value::Array(items) => items,
+            Value::Object(meta) => {
+                if let Some(n) = meta.get("name").and_then(Value::as_str) {
+                    name = n.to_string();
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        for item in items {
+            match item {
+                Value::Object(props) => cur.apply(&props),
+                Value::String(legend) => {
+                    if !cur.decal {
+                        placed.push((legend, cur.key()));
+                    }
+                    cur.next_key();
+                }
+                _ => {}
+            }
+        }
+        cur.next_row();
+    }
+    (name, placed)
+}
+
+/// The rows of either format as JSON values.
+fn parse_rows(text: &str) -> Result<Vec<Value>, serde_json::Error> {
+    let quoted = quote_keys(text);
+    let Value::Array(mut rows) = serde_json::from_str(&format!("[{quoted}]"))? else {
+        unreachable!("parsed from an array");
+    };
+    // Downloaded JSON is itself an array of rows: unwrap it.
+    if let [Value::Array(inner)] = &rows[..] {
+        if inner.iter().any(Value::is_array) {
+            let Some(Value::Array(inner)) = rows.pop() else {
+                unreachable!();
+            };
+            return Ok(inner);
+        }
+    }
+    Ok(rows)
+}
+
+/// Quote the bare property names of KLE raw data (`{w:2}`).
+fn quote_keys(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_str = false;
+    // After `{` or `,`, where a property name may start.
+    let mut key_pos = false;
+    while let Some(c) = chars.next() {
+        if in_str {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_str = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_str = true;
+                key_pos = false;
+                out.push(c);
+            }
+            '{' | ',' => {
+                key_pos = true;
+                out.push(c);
+            }
+            c if key_pos && (c.is_ascii_alphabetic() || c == '_') => {
+                let mut ident = String::from(c);
+                while let Some(&n) = chars.peek().filter(|n| n.is_ascii_alphanumeric()) {
+                    ident.push(n);
+                    chars.next();
+                }
+                out.push('"');
+                out.push_str(&ident);
+                out.push('"');
+                key_pos = false;
+            }
+            c if c.is_whitespace() => out.push(c),
+            _ => {
+                key_pos = false;
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+
+/// KLE's running state while walking the rows.
+#[derive(Default)]
+struct Cursor {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+    x2: f32,
+    y2: f32,
+    w2: f32,
+    h2: f32,
+    r: f32,
+    rx: f32,
+    ry: f32,
+    decal: bool,
+}
+
+impl Cursor {
+    fn apply(&mut self, props: &serde_json::Map<String, Value>) {
+        let num = |k: &str| props.get(k).and_then(Value::as_f64).map(|v| v as f32);
+        if let Some(r) = num("r") {
+            self.r = r;
+        }
+        if let Some(rx) = num("rx") {
+            self.rx = rx;
+            (self.x, self.y) = (self.rx, self.ry);
+        }
+        if let Some(ry) = num("ry") {
+            self.ry = ry;
+            (self.x, self.y) = (self.rx, self.ry);
+        }
+        self.x += num("x").unwrap_or(0.0);
+        self.y += num("y").unwrap_or(0.0);
+        if let Some(w) = num("w") {
+            self.w = w;
+        }
+        if let Some(h) = num("h") {
+            self.h = h;
+        }
+        for (k, v) in [
+            ("x2", &mut self.x2),
+            ("y2", &mut self.y2),
+            ("w2", &mut self.w2),
+            ("h2", &mut self.h2),
+        ] {
+            if let Some(n) = num(k) {
+                *v = n;
+            }
+        }
+        if let Some(d) = props.get("d").and_then(Value::as_bool) {
+            self.decal = d;
+        }
+    }
+
+    fn size(&self) -> (f32, f32) {
+        (
+            if self.w > 0.0 { self.w } else { 1.0 },
+            if self.h > 0.0 { self.h } else { 1.0 },
+        )
+    }
+
+    fn key(&self) -> Key {
+        let (w, h) = self.size();
+        let (w2, h2) = (
+            if self.w2 > 0.0 { self.w2 } else { w },
+            if self.h2 > 0.0 { self.h2 } else { h },
+        );
+        let stepped = self.x2 != 0.0 || self.y2 != 0.0 || w2 != w || h2 != h;
+        Key {
+            x: self.x,
+            y: self.y,
+            w,
+            h,
+            rect2: stepped.then_some([self.x2, self.y2, w2, h2]),
+            r: self.r,
+            rx: self.rx,
+            ry: self.ry,
+            ..Key::default()
+        }
+    }
+
+    fn next_key(&mut self) {
+        self.x += self.size().0;
+        (self.w, self.h, self.x2, self.y2, self.w2, self.h2) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        self.decal = false;
+    }
+
+    fn next_row(&mut self) {
+        self.y += 1.0;
+        self.x = self.rx;
+    }
+}
+
+/// Scancode for a legend, and the one to use if it is already taken.
+fn lookup(name: &str) -> Option<(u8, Option<u8>)> {
+    let one = |sc| Some((sc, None));
+    match name {
+        "esc" | "escape" => one(0x29),
+        "tab" => one(0x2B),
+        "caps lock" | "caps" | "capslock" => one(0x39),
+        "shift" => Some((0xE1, Some(0xE5))),
+        "ctrl" | "control" => Some((0xE0, Some(0xE4))),
+        "alt" | "option" => Some((0xE2, Some(0xE6))),
+        "altgr" | "alt gr" => one(0xE6),
+        "win" | "super" | "meta" | "gui" | "cmd" | "os" => Some((0xE3, Some(0xE7))),
+        "menu" | "app" | "apps" => one(0x65),
+        "fn" => one(0xFF),
+        "enter" | "return" => Some((0x28, Some(0x58))),
+        "backspace" | "back space" | "bksp" => one(0x2A),
+        "space" | "spacebar" => one(0x2C),
+        "`" | "~" => one(0x35),
+        "-" => Some((0x2D, Some(0x56))),
+        "=" => one(0x2E),
+        "[" => one(0x2F),
+        "]" => one(0x30),
+        "\\" => one(0x31),
+        "#" => one(0x32),
+        ";" => one(0x33),
+        "'" => one(0x34),
+        "," => one(0x36),
+        "." => Some((0x37, Some(0x63))),
+        "/" => Some((0x38, Some(0x54))),
+        "*" => one(0x55),
+        "+" => one(0x57),
+        "prtsc" | "print screen" | "prtsc sysrq" | "print" | "prt sc" => one(0x46),
+        "scroll lock" | "scrlk" | "scroll" => one(0x47),
+        "pause" | "pause break" => one(0x48),
+        "insert" | "ins" => one(0x49),
+        "home" => one(0x4A),
+        "pgup" | "page up" | "pg up" => one(0x4B),
+        "delete" | "del" => one(0x4C),
+        "end" => one(0x4D),
+        "pgdn" | "page down" | "pg dn" => one(0x4E),
+        "→" | "right" => one(0x4F),
+        "←" | "left" => one(0x50),
+        "↓" | "down" => one(0x51),
+        "↑" | "up" => one(0x52),
+        "num lock" | "numlock" | "num" => one(0x53),
+        // Numpad keys with their navigation legend.
+        "7 home" => one(0x5F),
+        "8 ↑" => one(0x60),
+        "9 pgup" => one(0x61),
+        "4 ←" => one(0x5C),
+        "6 →" => one(0x5E),
+        "1 end" => one(0x59),
+        "2 ↓" => one(0x5A),
+        "3 pgdn" => one(0x5B),
+        "0 ins" => one(0x62),
+        ". del" => one(0x63),
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c @ 'a'..='z'), None) => one(0x04 + (c as u8 - b'a')),
+                (Some('0'), None) => Some((0x27, Some(0x62))),
+                (Some(c @ '1'..='9'), None) => {
+                    let n = c as u8 - b'1';
+                    Some((0x1E + n, Some(0x59 + n)))
+                }
+                _ => {
+                    let f: u8 = name.strip_prefix('f')?.parse().ok()?;
+                    (1..=12).contains(&f).then_some((0x3A + f - 1, None))
+                }
+            }
+        }
+    }
+}
+
+/// Lines of a legend, lowercased with HTML arrows decoded.
+fn legend_lines(legend: &str) -> Vec<String> {
+    legend
+        .split('\n')
+        .map(|l| {
+            l.trim()
+                .to_lowercase()
+                .replace("&uarr;", "↑")
+                .replace("&darr;", "↓")
+                .replace("&larr;", "←")
+                .replace("&rarr;", "→")
+        })
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+/// Scancode candidates for a key; `true` when the legend was specific
+/// enough (several lines, or a full match) to claim its scancode first.
+fn candidates(legend: &str, key: &Key) -> Option<((u8, Option<u8>), bool)> {
+    let lines = legend_lines(legend);
+    if lines.is_empty() {
+        return (key.w >= 3.0).then_some(((0x2C, None), true));
+    }
+    let specific = lines.len() > 1;
+    if let Some(c) = lookup(&lines.join(" ")) {
+        return Some((c, specific));
+    }
+    lines
+        .iter()
+        .rev()
+        .find_map(|l| lookup(l))
+        .map(|c| (c, specific))
+}
+
+/// ISO boards have a tall, stepped Enter, or a key (the non-US backslash)
+/// between the left Shift and Z. A stepped Caps Lock alone is not ISO.
+/// `scancodes` holds each key's first scancode candidate.
+fn is_iso(placed: &[(String, Key)], scancodes: &[Option<u8>]) -> bool {
+    let keys: Vec<_> = placed
+        .iter()
+        .zip(scancodes)
+        .filter_map(|((_, k), &sc)| Some((sc?, k)))
+        .collect();
+    let first = |sc: u8| keys.iter().find(|&&(s, _)| s == sc).map(|&(_, k)| k);
+    if keys
+        .iter()
+        .any(|&(sc, k)| sc == 0x28 && k.rect2.is_some() && k.h >= 2.0)
+    {
+        return true;
+    }
+    let (Some(shift), Some(z)) = (first(0xE1), first(0x1D)) else {
+        return false;
+    };
+    keys.iter().any(|&(_, k)| {
+        (k.y - z.y).abs() < 0.5 && k.x >= shift.x + shift.w - 0.01 && k.x + k.w <= z.x + 0.01
+    })
+}
+
+pub(crate) fn assign_scancodes(placed: Vec<(String, Key)>) -> (Vec<Key>, Vec<String>) {
+    let found: Vec<_> = placed
+        .iter()
+        .map(|(legend, key)| candidates(legend, key))
+        .collect();
+    let first: Vec<_> = found.iter().map(|c| c.map(|((sc, _), _)| sc)).collect();
+    let iso = is_iso(&placed, &first);
+    let mut taken = [false; 256];
+    let mut scancodes = vec![None; placed.len()];
+    // Specific legends first, so "?\n/" gets `/` before a numpad "/".
+    for pass in [true, false] {
+        for (i, c) in found.iter().enumerate() {
+            let Some(((sc, alt), specific)) = *c else {
+                continue;
+            };
+            if specific != pass {
+                continue;
+            }
+            let sc = match (iso, sc) {
+                (true, 0x31) => 0x64,
+                _ => sc,
+            };
+            let sc = match alt {
+                Some(alt) if taken[sc as usize] && !taken[alt as usize] => alt,
+                _ => sc,
+            };
+            taken[sc as usize] = true;
+            scancodes[i] = Some(sc);
+        }
+    }
+
+    let mut keys = Vec::new();
+    let mut unmapped = Vec::new();
+    for ((legend, key), sc) in placed.into_iter().zip(scancodes) {
+        match sc {
+            Some(scancode) => keys.push(Key {
+                scancode,
+                label: legend.lines().last().unwrap_or_default().trim().to_string(),
+                ..key
+            }),
+            None => unmapped.push(legend),
+        }
+    }
+    (keys, unmapped)
+}
+
+/// Move the board so its top-left corner is at 0, 0.
+pub(crate) fn normalize(layout: &mut Layout) {
+    let corners: Vec<_> = layout.keys.iter().flat_map(|k| k.corners()).collect();
+    let min_x = corners.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+    let min_y = corners.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+    if !min_x.is_finite() {
+        return;
+    }
+    for k in &mut layout.keys {
+        k.x -= min_x;
+        k.y -= min_y;
+        k.rx -= min_x;
+        k.ry -= min_y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSI_104: &str = include_str!("../tests/fixtures/kle/ansi-104.json");
+    const ISO_60: &str = include_str!("../tests/fixtures/kle/iso-60-raw.txt");
+    const ROTATED: &str = include_str!("../tests/fixtures/kle/rotated-raw.txt");
+
+    fn at(l: &Layout, sc: u8) -> (f32, f32, f32, f32) {
+        let k = l.key(sc).unwrap_or_else(|| panic!("no key 0x{sc:02X}"));
+        (k.x, k.y, k.w, k.h)
+    }
+
+    #[test]
+    fn ansi_104_matches_the_built_in_full_size() {
+        let imp = import(ANSI_104).unwrap();
+        assert!(imp.unmapped.is_empty(), "{:?}", imp.unmapped);
+        let l = imp.layout;
+        assert_eq!(l.keys.len(), 104);
+        assert_eq!((l.width(), l.height()), (22.5, 6.5));
+
+        let full = crate::layout::by_name("full").unwrap();
+        for k in &full.keys {
+            assert_eq!(at(&l, k.scancode), (k.x, k.y, k.w, k.h), "{}", k.label);
+        }
+        assert_eq!(l.key(0x38).unwrap().label, "/");
+        assert_eq!(l.key(0x54).unwrap().label, "/");
+        assert_eq!(l.key(0x02C).unwrap().w, 6.25);
+    }
+
+    #[test]
+    fn iso_raw_data_with_stepped_enter() {
+        let imp = import(ISO_60).unwrap();
+        assert!(imp.unmapped.is_empty(), "{:?}", imp.unmapped);
+        let l = imp.layout;
+        assert_eq!(l.name, "ISO 60%");
+        assert_eq!(l.keys.len(), 62);
+        assert_eq!((l.width(), l.height()), (15.0, 5.0));
+
+        let enter = l.key(0x28).unwrap();
+        assert_eq!(
+            (enter.x, enter.y, enter.w, enter.h),
+            (13.75, 1.0, 1.25, 2.0)
+        );
+        assert_eq!(enter.rect2, Some([-0.25, 0.0, 1.5, 1.0]));
+        assert_eq!(at(&l, 0x64), (1.25, 3.0, 1.0, 1.0));
+        assert_eq!(at(&l, 0x32).0, 12.75);
+        assert_eq!(l.key(0xE6).unwrap().label, "AltGr");
+    }
+
+    #[test]
+    fn stepped_caps_lock_is_not_iso() {
+        let caps = r#"[{w:1.5},"Tab","Q",{w:1.5},"|\n\\"],
+[{w:1.25,w2:1.75,l:true},"Caps Lock",{x:0.5},"A",{w:2.25},"Enter"],
+[{w:2.25},"Shift","Z"]"#;
+        let l = import(caps).unwrap().layout;
+        assert!(l.key(0x39).unwrap().rect2.is_some());
+        assert!(l.key(0x31).is_some());
+        assert!(l.key(0x64).is_none());
+
+        // Flat Enter, but a key between Shift and Z: ISO.
+        let iso = r#"["A",{w:2.25},"Enter"],
+[{w:1.25},"Shift","|\n\\","Z"]"#;
+        let l = import(iso).unwrap().layout;
+        assert_eq!(at(&l, 0x64), (1.25, 1.0, 1.0, 1.0));
+        assert!(l.key(0x31).is_none());
+    }
+
+    #[test]
+    fn rotated_clusters_and_skipped_keys() {
+        let imp = import(ROTATED).unwrap();
+        assert_eq!(imp.unmapped, vec!["<i class='kb kb-Multimedia-Play'></i>"]);
+        let l = imp.layout;
+        assert_eq!(l.keys.len(), 6);
+
+        let (q, o) = (l.key(0x14).unwrap(), l.key(0x12).unwrap());
+        assert_eq!((q.r, o.r), (10.0, -10.0));
+        // Q rotates about its own top-left corner, which is where KLE put it.
+        assert_eq!((q.x, q.y), (q.rx, q.ry));
+        assert_eq!((o.x - o.rx, o.y - o.ry), (-2.0, 0.0));
+        assert_eq!(l.key(0x04).map(|a| a.y - q.y), Some(1.0));
+        // Normalized: the rotated corners touch the origin.
+        let corners: Vec<_> = l.keys.iter().flat_map(|k| k.corners()).collect();
+        let min = |f: fn(&(f32, f32)) -> f32| corners.iter().map(f).fold(f32::MAX, f32::min);
+        assert!(min(|p| p.0).abs() < 1e-5 && min(|p| p.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn quotes_bare_property_names_only() {
+        assert_eq!(
+            quote_keys(r#"[{x:1,w2:1.5},"a:b",{"y":2}]"#),
+            r#"[{"x":1,"w2":1.5},"a:b",{"y":2}]"#
+        );
+        assert!(import("[{x:1},").is_err());
+    }
+}
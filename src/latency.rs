@@ -0,0 +1,283 @@
+//! End-to-end latency measurement: HID report -> Chrome -> WebSocket -> frame.
+//!
+//! The bridge page stamps every report with the time Chrome received it
+//! (`performance.timeOrigin + event.timeStamp`). Rust periodically sends a
+//! clock sync request; the page echoes it with its own clock, which gives an
+//! NTP-style offset estimate between the two clocks.
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Histogram resolution in milliseconds.
+const BUCKET_MS: f64 = 0.1;
+/// Number of regular buckets (covers 0..100 ms); slower samples go to overflow.
+const BUCKETS: usize = 1000;
+/// Number of recent clock sync samples kept for the offset estimate.
+const SYNC_WINDOW: usize = 16;
+/// Pending report timestamps kept between two frames.
+const MAX_PENDING: usize = 1024;
+
+/// Wall-clock time in milliseconds since the Unix epoch.
+pub fn now_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0)
+}
+
+/// Fixed-resolution latency histogram (0.1 ms buckets up to 100 ms).
+#[derive(Clone)]
+pub struct LatencyHistogram {
+    buckets: Vec<u32>,
+    overflow: u32,
+    count: u64,
+    sum: f64,
+    max: f64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS],
+            overflow: 0,
+            count: 0,
+            sum: 0.0,
+            max: 0.0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Record one sample. Negative values (clock jitter) count as zero.
+    pub fn record(&mut self, ms: f64) {
+        let ms = ms.max(0.0);
+        let idx = (ms / BUCKET_MS) as usize;
+        match self.buckets.get_mut(idx) {
+            Some(b) => *b += 1,
+            None => self.overflow += 1,
+        }
+        self.count += 1;
+        self.sum += ms;
+        self.max = self.max.max(ms);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// Latency below which `p` (0.0..=1.0) of the samples fall.
+    /// Returns the upper edge of the matching bucket, or the max for overflow.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((p.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0u64;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n as u64;
+            if seen >= rank {
+                return Some(((i + 1) as f64 * BUCKET_MS).min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Clock offset estimator using the lowest round-trip sync sample.
+#[derive(Clone, Default)]
+pub struct ClockSync {
+    samples: Vec<(f64, f64)>,
+}
+
+impl ClockSync {
+    /// Add a sync round trip: `t0` local send, `t1` remote clock, `t2` local receive.
+    pub fn add_sample(&mut self, t0: f64, t1: f64, t2: f64) {
+        let rtt = t2 - t0;
+        if rtt < 0.0 {
+            return;
+        }
+        let offset = t1 - (t0 + t2) / 2.0;
+        if self.samples.len() == SYNC_WINDOW {
+            self.samples.remove(0);
+        }
+        self.samples.push((rtt, offset));
+    }
+
+    /// Remote clock minus local clock, in ms. `None` until a sync completes.
+    pub fn offset(&self) -> Option<f64> {
+        self.samples
+            .iter()
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|&(_, off)| off)
+    }
+
+    /// Round-trip time of the sample used for the current offset.
+    pub fn best_rtt(&self) -> Option<f64> {
+        self.samples.iter().map(|s| s.0).min_by(|a, b| a.total_cmp(b))
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Collects per-stage latency histograms for the analog stream.
+#[derive(Clone, Default)]
+pub struct LatencyTracker {
+    sync: ClockSync,
+    bridge: LatencyHistogram,
+    frame: LatencyHistogram,
+    pending: Vec<f64>,
+}
+
+impl LatencyTracker {
+    pub fn sync(&self) -> &ClockSync {
+        &self.sync
+    }
+
+    pub fn sync_mut(&mut self) -> &mut ClockSync {
+        &mut self.sync
+    }
+
+    /// Record a report stamped `remote_ts` (page clock) that arrived at `local_ts`.
+    /// Ignored until the clock offset is known.
+    pub fn record_report(&mut self, remote_ts: f64, local_ts: f64) {
+        let Some(offset) = self.sync.offset() else {
+            return;
+        };
+        let hid_local = remote_ts - offset;
+        self.bridge.record(local_ts - hid_local);
+        if self.pending.len() < MAX_PENDING {
+            self.pending.push(hid_local);
+        }
+    }
+
+    /// Record HID-to-frame latency for every report received since the last frame.
+    pub fn mark_frame(&mut self, local_ts: f64) {
+        for hid_local in self.pending.drain(..) {
+            self.frame.record(local_ts - hid_local);
+        }
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            offset_ms: self.sync.offset(),
+            sync_rtt_ms: self.sync.best_rtt(),
+            bridge: self.bridge.clone(),
+            frame: self.frame.clone(),
+        }
+    }
+
+    /// Drop collected samples (keeps the clock offset).
+    pub fn clear(&mut self) {
+        self.bridge.clear();
+        self.frame.clear();
+        self.pending.clear();
+    }
+
+    /// Forget everything, including the clock offset (new connection).
+    pub fn reset(&mut self) {
+        self.clear();
+        self.sync.reset();
+    }
+}
+
+/// Snapshot of the collected latency statistics.
+#[derive(Clone)]
+pub struct LatencyReport {
+    /// Page clock minus local clock, in ms.
+    pub offset_ms: Option<f64>,
+    /// Round-trip time of the sync sample the offset is based on.
+    pub sync_rtt_ms: Option<f64>,
+    /// HID report in Chrome -> received by Rust over the WebSocket.
+    pub bridge: LatencyHistogram,
+    /// HID report in Chrome -> consumed by a rendered frame.
+    pub frame: LatencyHistogram,
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.offset_ms, self.sync_rtt_ms) {
+            (Some(off), Some(rtt)) => {
+                writeln!(f, "clock offset {off:+.3} ms (sync rtt {rtt:.3} ms)")?
+            }
+            _ => writeln!(f, "clock offset unknown (no sync yet)")?,
+        }
+        for (name, h) in [("hid->ws", &self.bridge), ("hid->frame", &self.frame)] {
+            match (h.percentile(0.5), h.percentile(0.99), h.max()) {
+                (Some(p50), Some(p99), Some(max)) => writeln!(
+                    f,
+                    "{name:<11} n={:<7} p50 {p50:6.1} ms  p99 {p99:6.1} ms  max {max:6.1} ms",
+                    h.count()
+                )?,
+                _ => writeln!(f, "{name:<11} no samples")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_percentiles() {
+        let mut h = LatencyHistogram::default();
+        for i in 1..=100 {
+            h.record(i as f64);
+        }
+        assert_eq!(h.count(), 100);
+        let p50 = h.percentile(0.5).unwrap();
+        let p99 = h.percentile(0.99).unwrap();
+        // percentiles resolve to the upper edge of a 0.1 ms bucket
+        assert!((p50 - 50.0).abs() < 0.2, "p50 = {p50}");
+        assert!((p99 - 99.0).abs() < 0.2, "p99 = {p99}");
+        assert_eq!(h.max(), Some(100.0));
+    }
+
+    #[test]
+    fn histogram_overflow_reports_max() {
+        let mut h = LatencyHistogram::default();
+        h.record(2.0);
+        h.record(500.0);
+        assert_eq!(h.percentile(1.0), Some(500.0));
+        assert!(LatencyHistogram::default().percentile(0.5).is_none());
+    }
+
+    #[test]
+    fn clock_sync_prefers_lowest_rtt() {
+        let mut s = ClockSync::default();
+        // remote clock is 1000 ms ahead; first sample has an asymmetric slow leg
+        s.add_sample(0.0, 1008.0, 10.0);
+        s.add_sample(20.0, 1021.0, 22.0);
+        assert_eq!(s.best_rtt(), Some(2.0));
+        assert_eq!(s.offset(), Some(1000.0));
+    }
+
+    #[test]
+    fn tracker_records_bridge_and_frame() {
+        let mut t = LatencyTracker::default();
+        t.record_report(1000.0, 5.0);
+        assert_eq!(t.report().bridge.count(), 0, "no samples before sync");
+
+        t.sync_mut().add_sample(0.0, 1000.0, 0.0);
+        t.record_report(1000.0, 3.0);
+        t.mark_frame(10.0);
+        let r = t.report();
+        assert_eq!(r.bridge.max(), Some(3.0));
+        assert_eq!(r.frame.max(), Some(10.0));
+    }
+}
@@ -0,0 +1,366 @@
+//! Keyboard layouts: where each key is, its size, label and scancode.
+//!
+//! Shared by the visualizer, switch QA and the browser overlay, which fetches
+//! them in pixels from `/overlay.json` (see `http.rs`). Positions and sizes
+//! are in key units (1.0 = one standard key) from the top-left of the board.
+//!
+//! Layouts are loaded from JSON files of rows, top to bottom:
+//!
+//! ```json
+//! { "name": "Numpad",
+//!   "rows": [
+//!     [{ "sc": "0x53", "label": "Num" }, { "sc": 84, "label": "/" }],
+//!     { "y": 0.5, "keys": [{ "sc": "0x5F", "label": "7", "w": 2, "h": 2 }] }
+//!   ] }
+//! ```
+//!
+//! `sc` is the HID scancode, as a number or a `"0x.."` string. `w` and `h`
+//! default to 1. `x` leaves a gap before the key; the next key follows it on
+//! the same row. A row is a list of keys or an object whose `y` moves it (and
+//! the rows below it) down.
+//!
+//! L-shaped and stepped keys such as ISO Enter add a second rectangle,
+//! `"rect2": [x, y, w, h]` relative to the key; `r` rotates a key clockwise
+//! by that many degrees about the board point `rx`, `ry`.
+
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{kle, qmk};
+
+/// One key, in key units.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Key {
+    #[serde(rename = "sc")]
+    pub scancode: u8,
+    pub label: String,
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+    /// Second rectangle `[x, y, w, h]` of stepped and L-shaped keys (ISO
+    /// Enter), `x` and `y` relative to the key's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rect2: Option<[f32; 4]>,
+    /// Clockwise rotation in degrees about `(rx, ry)`.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub r: f32,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub rx: f32,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub ry: f32,
+    /// Legends on the keymap's layers above the base one (layer 1 first).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<String>,
+}
+
+fn is_zero(v: &f32) -> bool {
+    *v == 0.0
+}
+
+impl Key {
+    /// The key's legend on keymap layer `layer`; 0 is the base layer.
+    pub fn legend(&self, layer: usize) -> &str {
+        match layer {
+            0 => &self.label,
+            n => self.layers.get(n - 1).unwrap_or(&self.label),
+        }
+    }
+
+    /// The key's rectangles `[x, y, w, h]`, before rotation.
+    pub fn rects(&self) -> impl Iterator<Item = [f32; 4]> + '_ {
+        let main = [self.x, self.y, self.w, self.h];
+        let second = self
+            .rect2
+            .map(|[x, y, w, h]| [self.x + x, self.y + y, w, h]);
+        std::iter::once(main).chain(second)
+    }
+
+    /// Rotate a point by the key's rotation.
+    pub fn rotate(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        if self.r == 0.0 {
+            return (x, y);
+        }
+        let (sin, cos) = self.r.to_radians().sin_cos();
+        let (dx, dy) = (x - self.rx, y - self.ry);
+        (self.rx + dx * cos - dy * sin, self.ry + dx * sin + dy * cos)
+    }
+
+    /// Corners of all the key's rectangles, rotated.
+    pub fn corners(&self) -> Vec<(f32, f32)> {
+        self.rects()
+            .flat_map(|[x, y, w, h]| [(x, y), (x + w, y), (x, y + h), (x + w, y + h)])
+            .map(|p| self.rotate(p))
+            .collect()
+    }
+}
+
+/// Pixel size of a 1x1 key as the visualizer and overlay draw it; keys of
+/// `n` units span `n - 1` gaps as well.
+pub const KEY_UNIT: f32 = 46.0;
+pub const KEY_H: f32 = 42.0;
+pub const KEY_GAP: f32 = 4.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    pub name: String,
+    pub keys: Vec<Key>,
+    /// The keymap layer active while Fn (`0xFF`) is held.
+    pub fn_layer: Option<usize>,
+}
+
+/// Built-in layouts by name, as shipped JSON.
+pub const BUILTIN: &[(&str, &str)] = &[
+    ("60", include_str!("layouts/60.json")),
+    ("65", include_str!("layouts/65.json")),
+    ("75", include_str!("layouts/75.json")),
+    ("tkl", include_str!("layouts/tkl.json")),
+    ("full", include_str!("layouts/full.json")),
+];
+
+/// Look up a built-in layout by name.
+pub fn by_name(name: &str) -> Option<Layout> {
+    let (_, json) = BUILTIN.iter().find(|(n, _)| *n == name)?;
+    Layout::from_json(json).ok()
+}
+
+/// 60% ANSI with a Fn key (Ace 60 Pro).
+pub fn ansi_60() -> Layout {
+    by_name("60").expect("built-in layout")
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scancode {
+    Num(u8),
+    Hex(String),
+}
+
+#[derive(Deserialize)]
+struct KeyDef {
+    sc: Scancode,
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    x: f32,
+    #[serde(default = "one")]
+    w: f32,
+    #[serde(default = "one")]
+    h: f32,
+    #[serde(default)]
+    rect2: Option<[f32; 4]>,
+    #[serde(default)]
+    r: f32,
+    #[serde(default)]
+    rx: f32,
+    #[serde(default)]
+    ry: f32,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RowDef {
+    Keys(Vec<KeyDef>),
+    Offset {
+        #[serde(default)]
+        y: f32,
+        keys: Vec<KeyDef>,
+    },
+}
+
+#[derive(Deserialize)]
+struct LayoutDef {
+    #[serde(default)]
+    name: String,
+    rows: Vec<RowDef>,
+}
+
+impl Layout {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let def: LayoutDef = serde_json::from_str(json)?;
+        let mut keys = Vec::new();
+        let mut y = 0.0;
+        for row in def.rows {
+            let defs = match row {
+                RowDef::Keys(k) => k,
+                RowDef::Offset { y: dy, keys } => {
+                    y += dy;
+                    keys
+                }
+            };
+            let mut x = 0.0;
+            for k in defs {
+                let scancode = match k.sc {
+                    Scancode::Num(n) => n,
+                    Scancode::Hex(s) => s
+                        .strip_prefix("0x")
+                        .and_then(|h| u8::from_str_radix(h, 16).ok())
+                        .ok_or_else(|| serde::de::Error::custom(format!("bad scancode {s:?}")))?,
+                };
+                x += k.x;
+                keys.push(Key {
+                    scancode,
+                    label: k.label,
+                    x,
+                    y,
+                    w: k.w,
+                    h: k.h,
+                    rect2: k.rect2,
+                    r: k.r,
+                    rx: k.rx,
+                    ry: k.ry,
+                    ..Key::default()
+                });
+                x += k.w;
+            }
+            y += 1.0;
+        }
+        Ok(Self {
+            name: def.name,
+            keys,
+            fn_layer: None,
+        })
+    }
+
+    /// Load a layout file: our format, a QMK `info.json` or VIA definition
+    /// (see [`qmk`]), or KLE data (see [`kle`]).
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::load_with_keymap(path, None::<&Path>)
+    }
+
+    /// Load a QMK `info.json` or VIA definition with the legends and
+    /// scancodes of a keymap (`keymap.json` or a VIA backup).
+    pub fn load_with_keymap(
+        path: impl AsRef<Path>,
+        keymap: Option<impl AsRef<Path>>,
+    ) -> io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        let keymap = keymap.map(std::fs::read_to_string).transpose()?;
+        let native = match keymap {
+            Some(_) => None,
+            None => match Self::from_json(&json) {
+                Ok(l) => return Ok(l),
+                Err(e) => Some(e),
+            },
+        };
+        let import = if qmk::is_definition(&json) {
+            qmk::import(&json, keymap.as_deref()).map_err(io::Error::other)?
+        } else if keymap.is_some() {
+            return Err(io::Error::other(
+                "a keymap needs a QMK info.json or VIA definition",
+            ));
+        } else {
+            kle::import(&json).map_err(|e| match native {
+                Some(n) => io::Error::other(format!("not a layout file ({n}) nor KLE data ({e})")),
+                None => io::Error::other(e),
+            })?
+        };
+        for legend in &import.unmapped {
+            log::warn!("[layout] no scancode for key {legend:?}, skipped");
+        }
+        Ok(import.layout)
+    }
+
+    /// A built-in layout name, else a path to a layout file.
+    pub fn by_name_or_path(s: &str) -> io::Result<Self> {
+        match by_name(s) {
+            Some(l) => Ok(l),
+            None => Self::load(s),
+        }
+    }
+
+    /// Board width in key units.
+    pub fn width(&self) -> f32 {
+        self.corners().map(|p| p.0).fold(0.0, f32::max)
+    }
+
+    /// Board height in key units.
+    pub fn height(&self) -> f32 {
+        self.corners().map(|p| p.1).fold(0.0, f32::max)
+    }
+
+    fn corners(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        self.keys.iter().flat_map(|k| k.corners())
+    }
+
+    pub fn key(&self, scancode: u8) -> Option<&Key> {
+        self.keys.iter().find(|k| k.scancode == scancode)
+    }
+
+    pub fn scancodes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.keys.iter().map(|k| k.scancode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtins_parse_with_expected_sizes() {
+        let sizes = [
+            ("60", 15.0, 5.0, 61),
+            ("65", 16.0, 5.0, 68),
+            ("75", 16.0, 6.0, 83),
+            ("tkl", 18.25, 6.5, 87),
+            ("full", 22.5, 6.5, 104),
+        ];
+        for (name, w, h, n) in sizes {
+            let l = by_name(name).unwrap();
+            assert_eq!((l.width(), l.height(), l.keys.len()), (w, h, n), "{name}");
+        }
+        assert!(by_name("40").is_none());
+    }
+
+    #[test]
+    fn offsets_and_sizes() {
+        let l = Layout::from_json(
+            r#"{ "name": "t", "rows": [
+                [{ "sc": 4, "label": "A" }, { "sc": "0x05", "x": 0.5, "w": 2 }],
+                { "y": 0.25, "keys": [{ "sc": "0x57", "label": "+", "h": 2 }] }
+            ] }"#,
+        )
+        .unwrap();
+        assert_eq!(l.key(5).map(|k| (k.x, k.y, k.w)), Some((1.5, 0.0, 2.0)));
+        assert_eq!(l.key(0x57).map(|k| (k.y, k.h)), Some((1.25, 2.0)));
+        assert_eq!((l.width(), l.height()), (3.5, 3.25));
+        assert!(Layout::from_json(r#"{ "rows": [[{ "sc": "F1" }]] }"#).is_err());
+    }
+
+    #[test]
+    fn load_reports_both_formats() {
+        let path = std::env::temp_dir().join(format!("kb-hall-layout-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{ "rows": [["#).unwrap();
+        let e = Layout::load(&path).unwrap_err().to_string();
+        std::fs::remove_file(path).unwrap();
+        assert!(e.starts_with("not a layout file ("), "{e}");
+        assert!(e.contains(") nor KLE data ("), "{e}");
+    }
+
+    #[test]
+    fn shaped_and_rotated_keys() {
+        let l = Layout::from_json(
+            r#"{ "rows": [
+                [{ "sc": "0x28", "w": 1.25, "h": 2, "rect2": [-0.25, 0, 1.5, 1] }],
+                [],
+                [{ "sc": 4, "r": 90, "rx": 1, "ry": 2 }]
+            ] }"#,
+        )
+        .unwrap();
+        let enter = l.key(0x28).unwrap();
+        assert_eq!(
+            enter.rects().collect::<Vec<_>>(),
+            [[0.0, 0.0, 1.25, 2.0], [-0.25, 0.0, 1.5, 1.0]]
+        );
+        // A quarter turn about its top-right corner swings A up into the empty row.
+        let a = l.key(4).unwrap();
+        assert_eq!(a.rotate((0.0, 2.0)), (1.0, 1.0));
+        assert!((l.width() - 1.25).abs() < 1e-5 && (l.height() - 2.0).abs() < 1e-5);
+    }
+}
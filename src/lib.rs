@@ -1,10 +1,35 @@
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
-use tungstenite::Message as WsMessage;
+
+pub mod analytics;
+pub mod broadcast;
+mod bridge;
+pub mod dynamics;
+pub mod heatmap;
+pub mod history;
+mod http;
+pub mod kle;
+mod latency;
+pub mod layout;
+pub mod midi;
+pub mod osc;
+pub mod protocol;
+pub mod qa;
+pub mod qmk;
+pub mod sensor;
+pub mod smoothing;
+pub mod theme;
+
+pub use bridge::SendError;
+pub use dynamics::{KeyDynamics, Keystroke};
+pub use latency::{now_ms, ClockSync, LatencyHistogram, LatencyReport, LatencyTracker};
+pub use protocol::DeviceInfo;
+pub use sensor::{KeyHealth, SensorWarning};
 
 const ANALOG_DEADZONE: u16 = 10;
 const ANALOG_MAX: f32 = 1550.0;
+const SEND_TIMEOUT: Duration = Duration::from_secs(2);
 
 /// Thread-safe analog keyboard state.
 /// Provides 0.0..1.0 values for each HID scancode (256 slots).
@@ -15,6 +40,14 @@ pub struct AnalogKeyboard {
     values: Arc<Mutex<[f32; 256]>>,
     active: Arc<Mutex<bool>>,
     status: Arc<Mutex<String>>,
+    latency: Arc<Mutex<LatencyTracker>>,
+    dynamics: Arc<Mutex<dynamics::DynamicsTracker>>,
+    history: Arc<Mutex<history::KeyHistory>>,
+    device: Arc<Mutex<Option<DeviceInfo>>>,
+    outbox: Arc<Mutex<bridge::Outbox>>,
+    qa: Arc<Mutex<Option<qa::QaSession>>>,
+    sensors: Arc<Mutex<sensor::SensorMonitor>>,
+    layout: Arc<Mutex<Option<layout::Layout>>>,
 }
 
 impl AnalogKeyboard {
@@ -25,6 +58,14 @@ impl AnalogKeyboard {
             values: Arc::new(Mutex::new([0.0f32; 256])),
             active: Arc::new(Mutex::new(false)),
             status: Arc::new(Mutex::new("Starting...".into())),
+            latency: Arc::new(Mutex::new(LatencyTracker::default())),
+            dynamics: Arc::new(Mutex::new(dynamics::DynamicsTracker::default())),
+            history: Arc::new(Mutex::new(history::KeyHistory::default())),
+            device: Arc::new(Mutex::new(None)),
+            outbox: Arc::new(Mutex::new(bridge::Outbox::default())),
+            qa: Arc::new(Mutex::new(None)),
+            sensors: Arc::new(Mutex::new(sensor::SensorMonitor::default())),
+            layout: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -34,6 +75,13 @@ impl AnalogKeyboard {
         thread::spawn(move || hid_thread(&kb));
     }
 
+    /// Serve the bridge page and its WebSocket now, without waiting for the
+    /// keyboard or opening a browser; returns the page URL with the session
+    /// token. [`Self::start`] does this by itself once the keyboard is found.
+    pub fn start_bridge(&self) -> std::io::Result<String> {
+        bridge::start_bridge(self)
+    }
+
     /// Snapshot of all 256 analog values (0.0 = released, 1.0 = fully pressed).
     pub fn values(&self) -> [f32; 256] {
         self.values.lock().map(|v| *v).unwrap_or([0.0; 256])
@@ -47,11 +95,22 @@ impl AnalogKeyboard {
             .unwrap_or(0.0)
     }
 
-    /// Set values directly (for fallback digital input).
+    /// Set values directly (for fallback digital input). Keys that change
+    /// are added to the report history on the local clock, so the scope
+    /// works without the bridge too.
     pub fn set_values(&self, vals: &[f32; 256]) {
-        if let Ok(mut v) = self.values.lock() {
-            *v = *vals;
+        let Ok(mut v) = self.values.lock() else {
+            return;
+        };
+        if let Ok(mut h) = self.history.lock() {
+            let t = now_ms();
+            for (sc, (&old, &new)) in v.iter().zip(vals).enumerate() {
+                if old != new {
+                    h.record(sc as u8, new, t);
+                }
+            }
         }
+        *v = *vals;
     }
 
     /// True when analog HID data is streaming.
@@ -64,6 +123,214 @@ impl AnalogKeyboard {
         self.status.lock().map(|s| s.clone()).unwrap_or_default()
     }
 
+    /// Device reported by the bridge page, once it has opened the keyboard.
+    pub fn device_info(&self) -> Option<DeviceInfo> {
+        self.device.lock().ok().and_then(|d| d.clone())
+    }
+
+    /// Send an output report to the keyboard via the bridge page's
+    /// `sendReport`, blocking until the page acknowledges it.
+    pub fn send_report(&self, report_id: u8, data: &[u8]) -> Result<(), SendError> {
+        bridge::send_report(self, report_id, data, SEND_TIMEOUT)
+    }
+
+    /// Velocity, acceleration and keystroke peak of one key, from the
+    /// timestamped HID reports.
+    pub fn dynamics(&self, scancode: u8) -> KeyDynamics {
+        self.dynamics
+            .lock()
+            .map(|d| d.key(scancode))
+            .unwrap_or_default()
+    }
+
+    /// [`dynamics`](Self::dynamics) for all 256 scancodes.
+    pub fn all_dynamics(&self) -> [KeyDynamics; 256] {
+        self.dynamics
+            .lock()
+            .map(|d| d.all())
+            .unwrap_or([KeyDynamics::default(); 256])
+    }
+
+    /// Keystrokes completed after sequence number `seq` (start with 0) and
+    /// the sequence number to pass next time.
+    pub fn keystrokes_since(&self, seq: u64) -> (Vec<Keystroke>, u64) {
+        self.dynamics
+            .lock()
+            .map(|d| d.keystrokes_since(seq))
+            .unwrap_or((Vec::new(), seq))
+    }
+
+    /// Depth reports of every key after the sequence numbers in `seqs` (one
+    /// per scancode, start with 0), as `(scancode, depth)`, each key's oldest
+    /// first; `seqs` is advanced past them. Reports older than
+    /// [`history::HISTORY_MS`] are skipped.
+    pub fn reports_since(&self, seqs: &mut [u64; 256]) -> Vec<(u8, f32)> {
+        let Ok(h) = self.history.lock() else {
+            return Vec::new();
+        };
+        let mut out = Vec::new();
+        for (sc, seq) in seqs.iter_mut().enumerate() {
+            let (depths, next) = h.depths_since(sc as u8, *seq);
+            out.extend(depths.into_iter().map(|d| (sc as u8, d)));
+            *seq = next;
+        }
+        out
+    }
+
+    /// Press time of the earliest keystroke not yet released, if any key is
+    /// down; keystrokes pressed later may complete before it.
+    pub fn held_since(&self) -> Option<f64> {
+        self.dynamics.lock().ok()?.held_since()
+    }
+
+    /// The current time on the clock reports and keystrokes are stamped
+    /// with: the bridge page's clock once it is synced, the local clock
+    /// while nothing streams (fallback input, see [`set_values`]), else the
+    /// newest report of any key.
+    ///
+    /// [`set_values`]: Self::set_values
+    pub fn report_now_ms(&self) -> Option<f64> {
+        let offset = self.latency.lock().ok().and_then(|l| l.sync().offset());
+        let local = (!self.is_active()).then(now_ms);
+        offset
+            .map(|o| now_ms() + o)
+            .or(local)
+            .or_else(|| self.history.lock().ok()?.latest_ms())
+    }
+
+    /// Every report of one key from the last `window_ms`, as
+    /// `(ms before now, depth)`, oldest first, "now" being
+    /// [`report_now_ms`](Self::report_now_ms). The first entry may be older
+    /// than the window: it is the depth the key had when the window starts.
+    pub fn key_history(&self, scancode: u8, window_ms: f64) -> Vec<(f64, f32)> {
+        let Some(now) = self.report_now_ms() else {
+            return Vec::new();
+        };
+        let Ok(h) = self.history.lock() else {
+            return Vec::new();
+        };
+        h.since(scancode, now - window_ms)
+            .into_iter()
+            .map(|(t, d)| (now - t, d))
+            .collect()
+    }
+
+    /// Call once per rendered frame to record HID-to-frame latency.
+    pub fn mark_frame(&self) {
+        if let Ok(mut l) = self.latency.lock() {
+            l.mark_frame(now_ms());
+        }
+    }
+
+    /// Latency histograms collected since start (or the last clear).
+    pub fn latency_report(&self) -> LatencyReport {
+        self.latency
+            .lock()
+            .map(|l| l.report())
+            .unwrap_or_else(|_| LatencyTracker::default().report())
+    }
+
+    /// Drop collected latency samples, keeping the clock offset.
+    pub fn clear_latency(&self) {
+        if let Ok(mut l) = self.latency.lock() {
+            l.clear();
+        }
+    }
+
+    /// Stream the analog state to other programs over WebSocket or TCP
+    /// (see [`broadcast`]). Returns the bound address.
+    pub fn start_broadcast(
+        &self,
+        addr: impl std::net::ToSocketAddrs,
+    ) -> std::io::Result<std::net::SocketAddr> {
+        broadcast::serve(self, addr)
+    }
+
+    /// Publish per-key depth as OSC over UDP (see [`osc`]) until the handle
+    /// is stopped.
+    pub fn start_osc(&self, config: osc::OscConfig) -> std::io::Result<osc::OscHandle> {
+        osc::start(self, config)
+    }
+
+    /// Map the analog stream to MIDI `rate` times per second and send it to
+    /// `sink` (see [`midi`]). Stop the handle to get the sink back.
+    pub fn start_midi<S: midi::MidiSink + Send + 'static>(
+        &self,
+        map: midi::MidiMap,
+        sink: S,
+        rate: u32,
+    ) -> midi::MidiHandle<S> {
+        midi::start(self, map, sink, rate)
+    }
+
+    /// Tell the library which layout the application shows; the OBS overlay
+    /// draws it unless the page asks for another.
+    pub fn set_layout(&self, layout: &layout::Layout) {
+        if let Ok(mut l) = self.layout.lock() {
+            *l = Some(layout.clone());
+        }
+    }
+
+    /// The layout set with [`Self::set_layout`].
+    pub fn layout(&self) -> Option<layout::Layout> {
+        self.layout.lock().ok()?.clone()
+    }
+
+    /// Start a switch QA run over the keys of `layout` (see [`qa`]),
+    /// replacing any run in progress.
+    pub fn start_qa(&self, layout: &layout::Layout, tolerances: qa::Tolerances) {
+        if let Ok(mut q) = self.qa.lock() {
+            *q = Some(qa::QaSession::new(layout, tolerances));
+        }
+    }
+
+    /// Results of the QA run so far; `None` when none was started.
+    pub fn qa_report(&self) -> Option<qa::QaReport> {
+        self.qa.lock().ok()?.as_ref().map(|q| q.report())
+    }
+
+    /// Keys of the QA run pressed to full travel, and how many it covers.
+    pub fn qa_coverage(&self) -> Option<(usize, usize)> {
+        self.qa.lock().ok()?.as_ref().map(|q| q.coverage())
+    }
+
+    /// Resting floor, noise, zero offset and any drift or stuck warning of
+    /// one key's sensor (see [`sensor`]).
+    pub fn sensor_health(&self, scancode: u8) -> KeyHealth {
+        self.sensors
+            .lock()
+            .map(|s| s.key(scancode))
+            .unwrap_or_default()
+    }
+
+    /// Keys whose sensor drifted or got stuck, by scancode.
+    pub fn sensor_warnings(&self) -> Vec<(u8, SensorWarning)> {
+        self.sensors
+            .lock()
+            .map(|s| s.warnings())
+            .unwrap_or_default()
+    }
+
+    /// Re-zero every key with a sensor warning, stuck keys included; returns
+    /// the scancodes that moved.
+    pub fn rezero(&self) -> Vec<u8> {
+        let rezeroed = self
+            .sensors
+            .lock()
+            .map(|mut s| s.rezero_flagged(true))
+            .unwrap_or_default();
+        apply_rezero(self, &rezeroed);
+        rezeroed.into_iter().map(|(sc, _)| sc).collect()
+    }
+
+    /// Re-zero drifted keys automatically as soon as they are flagged; stuck
+    /// keys are left for [`Self::rezero`].
+    pub fn set_auto_rezero(&self, on: bool) {
+        if let Ok(mut s) = self.sensors.lock() {
+            s.auto_rezero = on;
+        }
+    }
+
     pub fn vid(&self) -> u16 {
         self.vid
     }
@@ -98,38 +365,73 @@ fn hid_thread(kb: &AnalogKeyboard) {
         }
 
         set_status(kb, "Keyboard detected - launching Chrome bridge...");
-        start_webhid_bridge(kb);
+        bridge::start_webhid_bridge(kb);
         thread::sleep(Duration::from_secs(2));
     }
 }
 
-fn parse_analog_input(data: &[u8], kb: &AnalogKeyboard) {
-    if data.len() < 6 || data[0] != 0xA0 {
+/// Flag stuck keys, and re-zero drifted keys if auto re-zero is on; run
+/// periodically. Stuck keys are never re-zeroed here.
+fn check_sensors(kb: &AnalogKeyboard) {
+    let Some(now) = kb.report_now_ms() else {
         return;
-    }
+    };
+    let rezeroed = match kb.sensors.lock() {
+        Ok(mut s) => {
+            s.check(now);
+            if !s.auto_rezero {
+                return;
+            }
+            s.rezero_flagged(false)
+        }
+        Err(_) => return,
+    };
+    apply_rezero(kb, &rezeroed);
+}
 
-    let key_idx = data[3] as usize;
-    let raw = ((data[4] as u16) << 8) | (data[5] as u16);
+/// Show re-zeroed keys at their new depth without waiting for a report.
+fn apply_rezero(kb: &AnalogKeyboard, rezeroed: &[(u8, f32)]) {
+    if rezeroed.is_empty() {
+        return;
+    }
+    if let Ok(mut v) = kb.values.lock() {
+        for &(sc, depth) in rezeroed {
+            v[sc as usize] = depth;
+        }
+    }
+    for (sc, _) in rezeroed {
+        log::info!("[HID] re-zeroed key 0x{sc:02X}");
+    }
+}
 
-    let value = if raw <= ANALOG_DEADZONE {
+/// Raw sensor reading of an analog report as depth, 0.0..1.0.
+fn raw_depth(raw: u16) -> f32 {
+    if raw <= ANALOG_DEADZONE {
         0.0
     } else {
         ((raw - ANALOG_DEADZONE) as f32 / ANALOG_MAX).clamp(0.0, 1.0)
-    };
-
-    let Ok(mut tgt) = kb.values.lock() else {
-        return;
-    };
-    if key_idx < 256 {
-        tgt[key_idx] = value;
     }
 }
 
-fn bridge_html(ws_port: u16, vid: u16, pid: u16) -> String {
-    include_str!("bridge.html")
-        .replace("__WS_PORT__", &ws_port.to_string())
-        .replace("__VID__", &format!("0x{:04X}", vid))
-        .replace("__PID__", &format!("0x{:04X}", pid))
+/// Apply an analog report; returns the key it moved, its raw reading and
+/// its new depth.
+fn parse_analog_input(data: &[u8], kb: &AnalogKeyboard) -> Option<(u8, u16, f32)> {
+    if data.len() < 6 || data[0] != 0xA0 {
+        return None;
+    }
+
+    let key_idx = data[3] as usize;
+    let raw = ((data[4] as u16) << 8) | (data[5] as u16);
+    let value = kb
+        .sensors
+        .lock()
+        .map_or(raw_depth(raw), |s| s.depth(key_idx as u8, raw));
+
+    let Ok(mut tgt) = kb.values.lock() else {
+        return None;
+    };
+    tgt[key_idx] = value;
+    Some((key_idx as u8, raw, value))
 }
 
 #[cfg(test)]
@@ -174,6 +476,20 @@ mod tests {
         assert_eq!(kb.value(10), 0.0);
     }
 
+    #[test]
+    fn set_values_feeds_the_scope() {
+        let kb = AnalogKeyboard::new(0, 0);
+        let mut v = [0.0f32; 256];
+        v[10] = 1.0;
+        kb.set_values(&v);
+        kb.set_values(&v);
+        let h = kb.key_history(10, 1000.0);
+        assert_eq!(h.len(), 1, "only changes are recorded");
+        assert_eq!(h[0].1, 1.0);
+        assert!((0.0..1000.0).contains(&h[0].0));
+        assert!(kb.key_history(11, 1000.0).is_empty());
+    }
+
     #[test]
     fn parse_analog_valid_keypress() {
         let kb = AnalogKeyboard::new(0, 0);
@@ -233,121 +549,3 @@ mod tests {
         assert_eq!(kb.values(), [0.0f32; 256]);
     }
 }
-
-fn start_webhid_bridge(kb: &AnalogKeyboard) {
-    use std::net::TcpListener;
-
-    let http_listener = match TcpListener::bind("127.0.0.1:0") {
-        Ok(l) => l,
-        Err(e) => {
-            set_status(kb, &format!("HTTP bind: {e}"));
-            return;
-        }
-    };
-    let ws_listener = match TcpListener::bind("127.0.0.1:0") {
-        Ok(l) => l,
-        Err(e) => {
-            set_status(kb, &format!("WS bind: {e}"));
-            return;
-        }
-    };
-
-    let http_port = http_listener.local_addr().unwrap().port();
-    let ws_port = ws_listener.local_addr().unwrap().port();
-    let html = Arc::new(bridge_html(ws_port, kb.vid, kb.pid));
-
-    let h = html.clone();
-    thread::spawn(move || {
-        use std::io::{Read, Write};
-        for stream in http_listener.incoming().flatten() {
-            let mut s = stream;
-            let _ = s.set_read_timeout(Some(Duration::from_secs(2)));
-            let mut buf = [0u8; 2048];
-            let _ = s.read(&mut buf);
-            let resp = format!(
-                "HTTP/1.1 200 OK\r\nContent-Type: text/html;charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
-                h.len(), &*h
-            );
-            let _ = s.write_all(resp.as_bytes());
-        }
-    });
-
-    let url = format!("http://127.0.0.1:{http_port}");
-    set_status(kb, &format!("Open Chrome -> {url}"));
-
-    if cfg!(target_os = "macos") {
-        let _ = std::process::Command::new("open")
-            .args(["-a", "Google Chrome", &url])
-            .stdout(std::process::Stdio::null())
-            .stderr(std::process::Stdio::null())
-            .spawn();
-    } else {
-        let _ = std::process::Command::new("xdg-open").arg(&url).spawn();
-    }
-
-    ws_listener.set_nonblocking(true).ok();
-
-    loop {
-        set_status(kb, "Waiting for Chrome connection...");
-        if let Ok(mut h) = kb.active.lock() {
-            *h = false;
-        }
-
-        let stream = loop {
-            match ws_listener.accept() {
-                Ok((s, _)) => {
-                    s.set_nonblocking(false).ok();
-                    break s;
-                }
-                Err(_) => thread::sleep(Duration::from_millis(100)),
-            }
-        };
-
-        let mut websocket = match tungstenite::accept(stream) {
-            Ok(ws) => ws,
-            Err(_) => continue,
-        };
-
-        set_status(kb, "Chrome connected - click Connect in browser");
-        let mut got_analog = false;
-
-        loop {
-            match websocket.read() {
-                Ok(WsMessage::Binary(data)) => {
-                    if data.len() < 3 {
-                        continue;
-                    }
-                    let payload = &data[2..];
-
-                    if data[0] == 0x03 {
-                        if !got_analog {
-                            got_analog = true;
-                            if let Ok(mut h) = kb.active.lock() {
-                                *h = true;
-                            }
-                            set_status(kb, "Analog active!");
-                        }
-                        parse_analog_input(payload, kb);
-
-                        let pressed = kb
-                            .values
-                            .lock()
-                            .map(|t| t.iter().filter(|&&v| v > 0.01).count())
-                            .unwrap_or(0);
-                        if let Ok(mut m) = kb.status.lock() {
-                            *m = format!("Analog active! ({pressed} keys)");
-                        }
-                    }
-                }
-                Ok(WsMessage::Close(_)) | Err(_) => break,
-                _ => {}
-            }
-        }
-
-        if let Ok(mut h) = kb.active.lock() {
-            *h = false;
-        }
-        set_status(kb, "Chrome disconnected - reconnecting...");
-        thread::sleep(Duration::from_millis(500));
-    }
-}
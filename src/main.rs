@@ -1,130 +1,601 @@
+use bevy::core_pipeline::bloom::Bloom;
+use bevy::ecs::system::SystemParam;
+use bevy::input::mouse::MouseWheel;
 use bevy::prelude::*;
-use kb_hall::AnalogKeyboard;
+use bevy::render::view::RenderLayers;
+use bevy::window::{CursorOptions, MonitorSelection, WindowMode};
+use kb_hall::analytics::Analytics;
+use kb_hall::dynamics::REST;
+use kb_hall::heatmap::{self, Heatmap, Metric};
+use kb_hall::layout::{self, Layout, KEY_GAP, KEY_H, KEY_UNIT};
+use kb_hall::midi::{MidiHandle, MidiMap, MidiSink, SmfWriter};
+use kb_hall::smoothing::{Smoother, Smoothing};
+use kb_hall::theme::{self, Rgb, Theme};
+use kb_hall::{AnalogKeyboard, SensorWarning};
+use std::sync::{Arc, Mutex};
 
 const VID: u16 = 0x41e4;
 const PID: u16 = 0x2103;
+/// Analog snapshots mapped to MIDI per second.
+const MIDI_RATE: u32 = 500;
+/// Base content size is the board plus `MARGIN`; the stats panel widens it
+/// by `PANEL_W`, the scope makes it taller by `SCOPE_H`. The content is
+/// scaled to the window (`fit_window`).
+const MARGIN: Vec2 = Vec2::new(154.0, 194.0);
+const PANEL_W: f32 = 260.0;
+const SCOPE_H: f32 = 220.0;
 
-const LAYOUT: &[&[(u8, &str, f32)]] = &[
-    &[
-        (0x29, "Esc", 1.0),
-        (0x1E, "1", 1.0),
-        (0x1F, "2", 1.0),
-        (0x20, "3", 1.0),
-        (0x21, "4", 1.0),
-        (0x22, "5", 1.0),
-        (0x23, "6", 1.0),
-        (0x24, "7", 1.0),
-        (0x25, "8", 1.0),
-        (0x26, "9", 1.0),
-        (0x27, "0", 1.0),
-        (0x2D, "-", 1.0),
-        (0x2E, "=", 1.0),
-        (0x2A, "Bksp", 2.0),
-    ],
-    &[
-        (0x2B, "Tab", 1.5),
-        (0x14, "Q", 1.0),
-        (0x1A, "W", 1.0),
-        (0x08, "E", 1.0),
-        (0x15, "R", 1.0),
-        (0x17, "T", 1.0),
-        (0x1C, "Y", 1.0),
-        (0x18, "U", 1.0),
-        (0x0C, "I", 1.0),
-        (0x12, "O", 1.0),
-        (0x13, "P", 1.0),
-        (0x2F, "[", 1.0),
-        (0x30, "]", 1.0),
-        (0x31, "\\", 1.5),
-    ],
-    &[
-        (0x39, "Caps", 1.75),
-        (0x04, "A", 1.0),
-        (0x16, "S", 1.0),
-        (0x07, "D", 1.0),
-        (0x09, "F", 1.0),
-        (0x0A, "G", 1.0),
-        (0x0B, "H", 1.0),
-        (0x0D, "J", 1.0),
-        (0x0E, "K", 1.0),
-        (0x0F, "L", 1.0),
-        (0x33, ";", 1.0),
-        (0x34, "'", 1.0),
-        (0x28, "Enter", 2.25),
-    ],
-    &[
-        (0xE1, "Shift", 2.25),
-        (0x1D, "Z", 1.0),
-        (0x1B, "X", 1.0),
-        (0x06, "C", 1.0),
-        (0x19, "V", 1.0),
-        (0x05, "B", 1.0),
-        (0x11, "N", 1.0),
-        (0x10, "M", 1.0),
-        (0x36, ",", 1.0),
-        (0x37, ".", 1.0),
-        (0x38, "/", 1.0),
-        (0xE5, "Shift", 2.75),
-    ],
-    &[
-        (0xE0, "Ctrl", 1.25),
-        (0xE3, "Win", 1.25),
-        (0xE2, "Alt", 1.25),
-        (0x2C, "Space", 6.25),
-        (0xE6, "Alt", 1.25),
-        (0xFF, "Fn", 1.25),
-        (0x65, "Menu", 1.25),
-        (0xE4, "Ctrl", 1.25),
-    ],
-];
+/// Height of a 3D keycap and how far it sinks at full press.
+const CAP_3D: f32 = 16.0;
+const SINK_3D: f32 = 12.0;
+/// Brightness of a fully pressed key's fill in the glow view, over 1 so it
+/// blooms.
+const GLOW: f32 = 4.0;
+
+/// The layout on screen (`--layout=NAME|PATH`); F10 cycles the built-in
+/// layouts.
+#[derive(Resource)]
+struct Board {
+    layout: Layout,
+    /// Base content size for this layout, before scaling to the window.
+    win: Vec2,
+}
+
+impl Board {
+    fn new(layout: Layout) -> Self {
+        let win = (board_px(&layout) + MARGIN).max(Vec2::new(900.0, 420.0));
+        Self { layout, win }
+    }
+}
+
+/// Board size in pixels.
+fn board_px(layout: &Layout) -> Vec2 {
+    Vec2::new(
+        layout.width() * (KEY_UNIT + KEY_GAP) - KEY_GAP,
+        layout.height() * (KEY_H + KEY_GAP) - KEY_GAP,
+    )
+}
 
-const KEY_UNIT: f32 = 46.0;
-const KEY_H: f32 = 42.0;
-const KEY_GAP: f32 = 4.0;
-const ROW_W: f32 = 15.0;
+/// Everything `spawn_board` creates, despawned when the layout changes.
+#[derive(Component)]
+struct BoardItem;
 
+/// `display` is `kb.values()` passed through `smoother` once per frame
+/// (`--smoothing=SPEC`; Shift+F1 cycles the models, Shift+Up/Down changes
+/// their speed).
 #[derive(Resource)]
 struct AppState {
     kb: AnalogKeyboard,
+    smoother: Smoother,
     display: [f32; 256],
 }
 
+/// `kb-hall latency`: show live percentiles and print a report on exit.
+#[derive(Resource)]
+struct LatencyMode(bool);
+
+/// `--qa=PATH`: colour keys by their QA result and write the report on exit.
+#[derive(Resource)]
+struct QaMode(bool);
+
+/// Built-in themes plus the one from `--theme=PATH`; F12 cycles them.
+#[derive(Resource)]
+struct Themes {
+    list: Vec<Theme>,
+    current: usize,
+}
+
+impl Themes {
+    fn get(&self) -> &Theme {
+        &self.list[self.current]
+    }
+}
+
+fn color(c: Rgb) -> Color {
+    Color::srgb(c.0[0], c.0[1], c.0[2])
+}
+
+/// Theme colour of a text that keeps its colour; re-applied on theme change.
+#[derive(Component, Clone, Copy)]
+enum Tone {
+    Label,
+    Text,
+    Dim,
+}
+
+impl Tone {
+    fn color(self, t: &Theme) -> Color {
+        color(match self {
+            Tone::Label => t.label,
+            Tone::Text => t.text,
+            Tone::Dim => t.dim,
+        })
+    }
+}
+
+/// `--kiosk`: fullscreen without a cursor, and F11 locked.
+#[derive(Resource)]
+struct Kiosk(bool);
+
 #[derive(Component)]
 struct Cap(u8);
+/// Depth fill of one of a key's rectangles. `travel` is the height of the
+/// key's fill at full press; this rectangle's part starts `lo` above the
+/// key's bottom and is at most `max` high.
 #[derive(Component)]
-struct Fill(u8);
+struct Fill {
+    sc: u8,
+    travel: f32,
+    lo: f32,
+    max: f32,
+}
+/// Legend of the layout's key at this index.
 #[derive(Component)]
-struct Lbl;
+struct Lbl(usize);
 #[derive(Component)]
 struct StatusTxt;
 #[derive(Component)]
 struct PctTxt(u8);
+/// Which peak a `PeakMark` shows.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Peak {
+    /// Held peak of the peak-hold smoothing.
+    Smoothing,
+    /// Deepest point of the key's latest keystroke, fading out.
+    Stroke,
+    /// Deepest point of the session.
+    Session,
+}
+
+/// Depth marker; `bottom` is the fill's base and `up` the direction it
+/// rises in (the key may be rotated).
+#[derive(Component)]
+struct PeakMark {
+    kind: Peak,
+    sc: u8,
+    bottom: Vec2,
+    up: Vec2,
+    travel: f32,
+}
+/// Keystroke peak velocity bar at the right edge of a key (F5).
+#[derive(Component)]
+struct VelBar {
+    sc: u8,
+    travel: f32,
+}
+
+/// How key depth is drawn, all from `AppState::display`; F1 cycles the
+/// views and `--view=NAME` picks the first.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+enum ViewMode {
+    /// Flat caps with a rising fill.
+    #[default]
+    Fill,
+    /// 3D keycaps that sink into the plate.
+    Keycaps,
+    /// A bar per key, in layout order.
+    Bars,
+    /// The flat board with fills that glow.
+    Glow,
+}
+
+impl ViewMode {
+    const ALL: [ViewMode; 4] = [
+        ViewMode::Fill,
+        ViewMode::Keycaps,
+        ViewMode::Bars,
+        ViewMode::Glow,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ViewMode::Fill => "fill",
+            ViewMode::Keycaps => "3d",
+            ViewMode::Bars => "bars",
+            ViewMode::Glow => "glow",
+        }
+    }
+
+    fn by_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.name() == name)
+    }
+
+    fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&v| v == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+/// Parent of the flat board's caps, fills, labels and markers, shown in the
+/// fill and glow views.
+#[derive(Component)]
+struct FlatBoard;
+/// Parent of the bar chart.
+#[derive(Component)]
+struct BarChart;
+/// A key's bar in the bar chart, `max` high at full press.
+#[derive(Component)]
+struct Bar {
+    sc: u8,
+    max: f32,
+}
+/// The full-height track behind a bar.
+#[derive(Component)]
+struct BarTrack;
+/// One of a key's rectangles as a 3D keycap.
+#[derive(Component)]
+struct Keycap(u8);
+
+/// Meshes and materials for the 3D view.
+#[derive(SystemParam)]
+struct Assets3d<'w> {
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+}
+
+/// The 2D camera, which draws everything but the 3D keycaps.
+type Cam2d<'w, 's> = Query<'w, 's, (&'static Camera, &'static GlobalTransform), With<Camera2d>>;
+
+/// Keystroke depth peaks from the HID reports, so a tap shorter than a
+/// frame still shows how deep it went. `last` holds each key's latest peak
+/// and the app time (s) it was last reached; its marker fades out over
+/// `fade` seconds (`--peak-fade=SECS`, 0 never fades). Shift+F5 toggles the
+/// markers.
+#[derive(Resource)]
+struct KeyPeaks {
+    visible: bool,
+    fade: f32,
+    seq: u64,
+    last: [(f32, f32); 256],
+}
+
+/// F5 toggles the velocity bars.
+#[derive(Resource, Default)]
+struct ShowVelocity(bool);
+
+/// Typing analytics, fed every frame; F6 shows the panel. Shared with
+/// `main` so `--stats=DIR` can export after the window closes.
+#[derive(Resource)]
+struct Stats {
+    visible: bool,
+    analytics: Arc<Mutex<Analytics>>,
+    refresh: Timer,
+}
+#[derive(Component)]
+struct StatsTxt;
+
+/// Scope: depth reports of the clicked keys over the last `window_ms`.
+#[derive(Resource)]
+struct Scope {
+    keys: Vec<u8>,
+    window_ms: f64,
+}
+/// Legend entry `i` of the scope, in trace `i`'s colour.
+#[derive(Component)]
+struct ScopeLegend(usize);
+#[derive(Component)]
+struct ScopeTxt;
+
+/// Trace colours; also the most keys the scope overlays.
+const SCOPE_COLORS: [Color; 4] = [
+    Color::srgb(0.2, 0.7, 1.0),
+    Color::srgb(1.0, 0.55, 0.2),
+    Color::srgb(0.45, 0.9, 0.35),
+    Color::srgb(0.9, 0.4, 0.9),
+];
+const SCOPE_MS: (f64, f64) = (250.0, 10_000.0);
+/// Actuation and release points drawn on the scope, in mm. The keyboard's
+/// per-key settings cannot be read, so these are fixed.
+const ACTUATION_MM: f32 = 1.2;
+const RELEASE_MM: f32 = 1.0;
+
+/// Cumulative usage heatmap. Always accumulating; F7 cycles what the caps
+/// show (live depth, press count, travel) and F8 resets the counts.
+#[derive(Resource)]
+struct Usage {
+    metric: Option<Metric>,
+    heatmap: Arc<Mutex<Heatmap>>,
+    /// Keystrokes and reports already counted.
+    next_seq: u64,
+    report_seqs: [u64; 256],
+}
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let latency_mode = args.iter().any(|a| a == "latency");
+    // `--fullscreen`, or `--kiosk` for unattended demos.
+    let kiosk = args.iter().any(|a| a == "--kiosk");
+    let fullscreen = kiosk || args.iter().any(|a| a == "--fullscreen");
+    // `--broadcast` or `--broadcast=PORT`: serve the analog state to other programs.
+    let broadcast_port = match args.iter().find_map(|a| a.strip_prefix("--broadcast")) {
+        Some("") => Some(kb_hall::broadcast::DEFAULT_PORT),
+        Some(arg) => match arg.strip_prefix('=').map(str::parse::<u16>) {
+            Some(Ok(port)) => Some(port),
+            _ => {
+                eprintln!("bad --broadcast{arg}, expected --broadcast=PORT");
+                return;
+            }
+        },
+        None => None,
+    };
+    // `--osc=HOST:PORT[,rate=N][,changes]`: publish key depth as OSC messages.
+    let osc_target = args.iter().find_map(|a| a.strip_prefix("--osc="));
+    // `--midi-file=PATH` records a Standard MIDI File, `--midi` plays through
+    // ALSA (with the `alsa` feature); `--midi-map=PATH` overrides the mapping.
+    let midi_file = args.iter().find_map(|a| a.strip_prefix("--midi-file="));
+    let midi_live = args.iter().any(|a| a == "--midi");
+    let midi_map = match args.iter().find_map(|a| a.strip_prefix("--midi-map=")) {
+        Some(path) => match std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|j| MidiMap::from_json(&j).map_err(|e| e.to_string()))
+        {
+            Ok(map) => map,
+            Err(e) => {
+                eprintln!("MIDI map {path}: {e}");
+                return;
+            }
+        },
+        None => MidiMap::musical_typing(),
+    };
+
+    // `--keymap=PATH`: QMK keymap.json or VIA backup for a `--layout=PATH`
+    // info.json or VIA definition.
+    let keymap = args.iter().find_map(|a| a.strip_prefix("--keymap="));
+    let layout = match (
+        args.iter().find_map(|a| a.strip_prefix("--layout=")),
+        keymap,
+    ) {
+        (Some(name), None) => Layout::by_name_or_path(name),
+        (Some(name), Some(km)) => Layout::load_with_keymap(name, Some(km)),
+        (None, None) => Ok(layout::ansi_60()),
+        (None, Some(_)) => Err(std::io::Error::other("--keymap needs --layout=PATH")),
+    };
+    let layout = match layout {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("layout: {e}");
+            return;
+        }
+    };
+    let board = Board::new(layout);
+
+    // `--theme=NAME|PATH`: a built-in theme or a theme file, added to the F12 cycle.
+    let builtin = theme::BUILTIN.iter().filter_map(|n| theme::by_name(n));
+    let mut themes = Themes {
+        list: builtin.collect(),
+        current: 0,
+    };
+    if let Some(arg) = args.iter().find_map(|a| a.strip_prefix("--theme=")) {
+        match Theme::by_name_or_path(arg) {
+            Ok(t) => {
+                themes.current = match themes.list.iter().position(|b| *b == t) {
+                    Some(i) => i,
+                    None => {
+                        themes.list.push(t);
+                        themes.list.len() - 1
+                    }
+                };
+            }
+            Err(e) => {
+                eprintln!("theme {arg}: {e}");
+                return;
+            }
+        }
+    }
+
+    // `--view=fill|3d|bars|glow`: how key depth is drawn; F1 cycles.
+    let view = match args.iter().find_map(|a| a.strip_prefix("--view=")) {
+        Some(name) => match ViewMode::by_name(name) {
+            Some(v) => v,
+            None => {
+                eprintln!("unknown view {name:?}, expected fill, 3d, bars or glow");
+                return;
+            }
+        },
+        None => ViewMode::default(),
+    };
+
+    // `--smoothing=NAME[:PARAMS]`: how the shown depth follows the keys.
+    let smoothing = match args.iter().find_map(|a| a.strip_prefix("--smoothing=")) {
+        Some(spec) => match Smoothing::parse(spec) {
+            Some(s) => s,
+            None => {
+                eprintln!(
+                    "bad smoothing {spec:?}, expected one of {} with optional :PARAMS",
+                    kb_hall::smoothing::MODELS.join(", ")
+                );
+                return;
+            }
+        },
+        None => Smoothing::default(),
+    };
+
+    // `--peak-fade=SECS`: how long the keystroke peak markers stay.
+    let peak_fade = match args.iter().find_map(|a| a.strip_prefix("--peak-fade=")) {
+        Some(secs) => match secs.parse::<f32>() {
+            Ok(f) if f >= 0.0 => f,
+            _ => {
+                eprintln!("bad peak fade {secs:?}, expected seconds");
+                return;
+            }
+        },
+        None => 2.0,
+    };
+
     let kb = AnalogKeyboard::new(VID, PID);
+    // `--auto-rezero`: re-zero keys whose sensor drifts.
+    kb.set_auto_rezero(args.iter().any(|a| a == "--auto-rezero"));
+    kb.set_layout(&board.layout);
     kb.start();
+    if let Some(port) = broadcast_port {
+        if let Err(e) = kb.start_broadcast(("127.0.0.1", port)) {
+            eprintln!("broadcast on port {port}: {e}");
+        }
+    }
+    let osc = osc_target.and_then(|spec| {
+        match kb_hall::osc::OscConfig::parse(spec).and_then(|c| kb.start_osc(c)) {
+            Ok(h) => Some(h),
+            Err(e) => {
+                eprintln!("OSC to {spec}: {e}");
+                None
+            }
+        }
+    });
+    // `--stats=DIR`: write keys.csv, bigrams.csv and stats.json on exit.
+    let stats_dir = args.iter().find_map(|a| a.strip_prefix("--stats="));
+    let analytics = Arc::new(Mutex::new(Analytics::new()));
+    // `--heatmap=PATH`: continue the usage heatmap saved there and save it on exit.
+    let heatmap_path = args.iter().find_map(|a| a.strip_prefix("--heatmap="));
+    let heatmap = match heatmap_path.map(Heatmap::load).transpose() {
+        Ok(h) => Arc::new(Mutex::new(h.unwrap_or_default())),
+        Err(e) => {
+            eprintln!("heatmap {}: {e}", heatmap_path.unwrap_or_default());
+            return;
+        }
+    };
+    // `--qa=PATH`: switch QA run over the layout, report written there on exit.
+    let qa_path = args.iter().find_map(|a| a.strip_prefix("--qa="));
+    if qa_path.is_some() {
+        kb.start_qa(&board.layout, kb_hall::qa::Tolerances::default());
+    }
+    let midi_recorder =
+        midi_file.map(|_| kb.start_midi(midi_map.clone(), SmfWriter::new(), MIDI_RATE));
+    let midi_live = midi_live.then(|| start_live_midi(&kb, midi_map)).flatten();
 
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "KB Hall".into(),
-                resolution: (900.0, 420.0).into(),
-                resizable: false,
+                resolution: (board.win.x, board.win.y).into(),
+                mode: if fullscreen {
+                    WindowMode::BorderlessFullscreen(MonitorSelection::Current)
+                } else {
+                    WindowMode::Windowed
+                },
+                cursor_options: CursorOptions {
+                    visible: !kiosk,
+                    ..default()
+                },
                 ..default()
             }),
             ..default()
         }))
-        .insert_resource(ClearColor(Color::srgb(0.09, 0.09, 0.09)))
+        .insert_resource(ClearColor(color(themes.get().background)))
+        .insert_resource(board)
         .insert_resource(AppState {
-            kb,
+            kb: kb.clone(),
+            smoother: Smoother::new(smoothing),
             display: [0.0; 256],
         })
+        .insert_resource(LatencyMode(latency_mode))
+        .insert_resource(QaMode(qa_path.is_some()))
+        .insert_resource(Kiosk(kiosk))
+        .insert_resource(themes)
+        .insert_resource(view)
+        .init_resource::<ShowVelocity>()
+        .insert_resource(KeyPeaks {
+            visible: true,
+            fade: peak_fade,
+            seq: 0,
+            last: [(0.0, 0.0); 256],
+        })
+        .insert_resource(Stats {
+            visible: false,
+            analytics: analytics.clone(),
+            refresh: Timer::from_seconds(0.25, TimerMode::Repeating),
+        })
+        .insert_resource(Usage {
+            metric: None,
+            heatmap: heatmap.clone(),
+            next_seq: 0,
+            report_seqs: [0; 256],
+        })
+        .insert_resource(Scope {
+            keys: Vec::new(),
+            window_ms: 2000.0,
+        })
         .add_systems(Startup, setup)
         .add_systems(
             Update,
-            (read_bevy_keys, animate_values, update_vis, update_hud).chain(),
+            (
+                switch_layout,
+                switch_theme,
+                (read_bevy_keys, smoothing_input, animate_values).chain(),
+                update_usage,
+                update_vis,
+                (switch_view, update_keycaps, update_bars).chain(),
+                update_labels,
+                update_velocity_vis,
+                update_peak_vis,
+                update_qa_vis,
+                update_hud,
+                update_stats,
+                scope_input,
+                draw_scope,
+                toggle_fullscreen,
+                fit_window,
+                mark_frame,
+            )
+                .chain(),
         )
         .run();
+
+    if latency_mode {
+        print!("{}", kb.latency_report());
+    }
+    if let (Some(path), Some(report)) = (qa_path, kb.qa_report()) {
+        let failed = report.failures().count();
+        println!(
+            "QA {}: {} of {} keys out of tolerance, report in {path}",
+            if report.passed() { "PASS" } else { "FAIL" },
+            failed,
+            report.keys.len()
+        );
+        if let Err(e) = report.save(path) {
+            eprintln!("QA report {path}: {e}");
+        }
+    }
+    if let Some(dir) = stats_dir {
+        let saved = analytics.lock().map(|a| a.save(dir));
+        if let Ok(Err(e)) = saved {
+            eprintln!("stats {dir}: {e}");
+        }
+    }
+    if let Some(path) = heatmap_path {
+        let saved = heatmap.lock().map(|h| h.save(path));
+        if let Ok(Err(e)) = saved {
+            eprintln!("heatmap {path}: {e}");
+        }
+    }
+    if let Some(osc) = osc {
+        osc.stop();
+    }
+    if let Some(live) = midi_live {
+        // Releases held notes.
+        live.stop();
+    }
+    if let (Some(rec), Some(path)) = (midi_recorder, midi_file) {
+        if let Err(e) = rec.stop().save(path) {
+            eprintln!("MIDI file {path}: {e}");
+        }
+    }
+}
+
+#[cfg(feature = "alsa")]
+fn start_live_midi(kb: &AnalogKeyboard, map: MidiMap) -> Option<MidiHandle<impl MidiSink>> {
+    match kb_hall::midi::AlsaSink::new() {
+        Ok(sink) => Some(kb.start_midi(map, sink, MIDI_RATE)),
+        Err(e) => {
+            eprintln!("ALSA MIDI: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "alsa"))]
+fn start_live_midi(_kb: &AnalogKeyboard, _map: MidiMap) -> Option<MidiHandle<impl MidiSink>> {
+    eprintln!("--midi needs a build with `--features alsa`");
+    None::<MidiHandle<Vec<(f64, kb_hall::midi::MidiMessage)>>>
 }
 
 fn keycode_to_sc(k: KeyCode) -> Option<u8> {
@@ -182,6 +653,37 @@ fn keycode_to_sc(k: KeyCode) -> Option<u8> {
         KeyCode::Period => 0x37,
         KeyCode::Slash => 0x38,
         KeyCode::CapsLock => 0x39,
+        // F1..F12 are the app's hotkeys, not keystrokes: left unmapped.
+        KeyCode::PrintScreen => 0x46,
+        KeyCode::ScrollLock => 0x47,
+        KeyCode::Pause => 0x48,
+        KeyCode::Insert => 0x49,
+        KeyCode::Home => 0x4A,
+        KeyCode::PageUp => 0x4B,
+        KeyCode::Delete => 0x4C,
+        KeyCode::End => 0x4D,
+        KeyCode::PageDown => 0x4E,
+        KeyCode::ArrowRight => 0x4F,
+        KeyCode::ArrowLeft => 0x50,
+        KeyCode::ArrowDown => 0x51,
+        KeyCode::ArrowUp => 0x52,
+        KeyCode::NumLock => 0x53,
+        KeyCode::NumpadDivide => 0x54,
+        KeyCode::NumpadMultiply => 0x55,
+        KeyCode::NumpadSubtract => 0x56,
+        KeyCode::NumpadAdd => 0x57,
+        KeyCode::NumpadEnter => 0x58,
+        KeyCode::Numpad1 => 0x59,
+        KeyCode::Numpad2 => 0x5A,
+        KeyCode::Numpad3 => 0x5B,
+        KeyCode::Numpad4 => 0x5C,
+        KeyCode::Numpad5 => 0x5D,
+        KeyCode::Numpad6 => 0x5E,
+        KeyCode::Numpad7 => 0x5F,
+        KeyCode::Numpad8 => 0x60,
+        KeyCode::Numpad9 => 0x61,
+        KeyCode::Numpad0 => 0x62,
+        KeyCode::NumpadDecimal => 0x63,
         KeyCode::ShiftLeft => 0xE1,
         KeyCode::ShiftRight => 0xE5,
         KeyCode::ControlLeft => 0xE0,
@@ -208,77 +710,347 @@ fn read_bevy_keys(keys: Res<ButtonInput<KeyCode>>, state: Res<AppState>) {
     state.kb.set_values(&vals);
 }
 
+/// Shift+F1 switches to the next smoothing model, Shift+Up/Down makes it
+/// follow the keys faster or slower.
+fn smoothing_input(keys: Res<ButtonInput<KeyCode>>, mut state: ResMut<AppState>) {
+    if !keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        return;
+    }
+    let model = state.smoother.model();
+    let next = if keys.just_pressed(KeyCode::F1) {
+        model.next()
+    } else if keys.just_pressed(KeyCode::ArrowUp) {
+        model.faster(1.25)
+    } else if keys.just_pressed(KeyCode::ArrowDown) {
+        model.faster(0.8)
+    } else {
+        return;
+    };
+    state.smoother.set_model(next);
+}
+
 fn animate_values(mut state: ResMut<AppState>, time: Res<Time>) {
-    let dt = time.delta_secs();
     let target = state.kb.values();
-    for i in 0..256 {
-        let t = target[i];
-        let d = &mut state.display[i];
-        if t > *d {
-            *d = (*d + 25.0 * dt).min(t);
-        } else {
-            *d = (*d - 8.0 * dt).max(t);
-        }
+    let state = &mut *state;
+    state.smoother.update(&target, time.delta_secs());
+    state.display = *state.smoother.values();
+}
+
+fn setup(mut commands: Commands, board: Res<Board>, themes: Res<Themes>, mut assets: Assets3d) {
+    // Both cameras are HDR: bloom needs it, and the 2D camera draws the HUD
+    // over the 3D view into the same target.
+    commands.spawn((
+        Camera2d,
+        Camera {
+            hdr: true,
+            ..default()
+        },
+    ));
+    commands.spawn((
+        Camera3d::default(),
+        Camera {
+            order: -1,
+            is_active: false,
+            hdr: true,
+            ..default()
+        },
+        RenderLayers::layer(1),
+    ));
+    spawn_board(&mut commands, &board, themes.get(), &mut assets);
+}
+
+/// F12 switches to the next theme; the background and text colours follow.
+fn switch_theme(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut themes: ResMut<Themes>,
+    mut clear: ResMut<ClearColor>,
+    mut texts: Query<(&Tone, &mut TextColor)>,
+) {
+    if keys.just_pressed(KeyCode::F12) {
+        themes.current = (themes.current + 1) % themes.list.len();
+    }
+    if !themes.is_changed() {
+        return;
+    }
+    let t = themes.get();
+    clear.0 = color(t.background);
+    for (tone, mut c) in texts.iter_mut() {
+        c.0 = tone.color(t);
     }
 }
 
-fn setup(mut commands: Commands) {
-    commands.spawn(Camera2d);
+/// F10 switches to the next built-in layout.
+#[allow(clippy::too_many_arguments)]
+fn switch_layout(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut board: ResMut<Board>,
+    themes: Res<Themes>,
+    mut assets: Assets3d,
+    items: Query<Entity, With<BoardItem>>,
+    state: Res<AppState>,
+    qa: Res<QaMode>,
+) {
+    if !keys.just_pressed(KeyCode::F10) {
+        return;
+    }
+    let builtin = layout::BUILTIN;
+    let current = builtin
+        .iter()
+        .position(|&(name, _)| layout::by_name(name).as_ref() == Some(&board.layout));
+    let next = current.map_or(0, |i| (i + 1) % builtin.len());
+    let Some(l) = layout::by_name(builtin[next].0) else {
+        return;
+    };
+    for e in items.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    *board = Board::new(l);
+    spawn_board(&mut commands, &board, themes.get(), &mut assets);
+    state.kb.set_layout(&board.layout);
+    // A QA run covers the keys of one layout; start over on the new one.
+    if qa.0 {
+        state
+            .kb
+            .start_qa(&board.layout, kb_hall::qa::Tolerances::default());
+    }
+}
 
-    let bw = ROW_W * (KEY_UNIT + KEY_GAP) - KEY_GAP;
-    let bh = 5.0 * (KEY_H + KEY_GAP) - KEY_GAP;
-    let ox = -bw / 2.0;
-    let oy = bh / 2.0 + 20.0;
+/// Key caps, labels and the HUD texts for the board's layout, plus the
+/// bar chart and 3D keycaps of the other views.
+fn spawn_board(commands: &mut Commands, board: &Board, theme: &Theme, assets: &mut Assets3d) {
+    let size = board_px(&board.layout);
+    let ox = -size.x / 2.0;
+    let oy = size.y / 2.0 + 20.0;
 
-    for (ri, row) in LAYOUT.iter().enumerate() {
-        let mut xo = 0.0f32;
-        for &(sc, label, wu) in *row {
-            let kw = wu * KEY_UNIT + (wu - 1.0) * KEY_GAP;
-            let cx = ox + xo + kw / 2.0;
-            let cy = oy - ri as f32 * (KEY_H + KEY_GAP) - KEY_H / 2.0;
+    let pitch = Vec2::new(KEY_UNIT + KEY_GAP, KEY_H + KEY_GAP);
+    // Key units (y down) to pixels (y up).
+    let px = |x: f32, y: f32| Vec2::new(ox + x * pitch.x, oy - y * pitch.y);
 
-            commands.spawn((
-                Sprite {
-                    color: Color::srgb(0.20, 0.20, 0.20),
-                    custom_size: Some(Vec2::new(kw, KEY_H)),
-                    ..default()
-                },
-                Transform::from_xyz(cx, cy, 0.0),
-                Cap(sc),
-            ));
-            commands.spawn((
-                Sprite {
-                    color: Color::srgb(0.2, 0.7, 1.0),
-                    custom_size: Some(Vec2::new(kw - 4.0, 0.0)),
-                    anchor: bevy::sprite::Anchor::BottomCenter,
-                    ..default()
-                },
-                Transform::from_xyz(cx, cy - KEY_H / 2.0 + 2.0, 1.0),
-                Fill(sc),
-            ));
-            commands.spawn((
-                Text2d::new(label),
+    let flat = commands
+        .spawn((
+            Transform::default(),
+            Visibility::default(),
+            FlatBoard,
+            BoardItem,
+        ))
+        .id();
+    for (i, key) in board.layout.keys.iter().enumerate() {
+        let sc = key.scancode;
+        // Parts are laid out unrotated, then turned about the key's pivot.
+        let rot = Quat::from_rotation_z(-key.r.to_radians());
+        let pivot = px(key.rx, key.ry);
+        let place = |p: Vec2, z: f32| {
+            let p = pivot + (rot * (p - pivot).extend(0.0)).truncate();
+            Transform::from_translation(p.extend(z)).with_rotation(rot)
+        };
+        let rects: Vec<Rect> = key
+            .rects()
+            .map(|[x, y, w, h]| {
+                let top_left = px(x, y);
+                let size = Vec2::new(w * pitch.x, h * pitch.y) - KEY_GAP;
+                Rect::from_corners(top_left, top_left + Vec2::new(size.x, -size.y))
+            })
+            .collect();
+        let main = rects[0];
+        // The fill rises from the lowest edge of the key to its highest.
+        let bottom = rects.iter().map(|r| r.min.y).fold(f32::MAX, f32::min);
+        let top = rects.iter().map(|r| r.max.y).fold(f32::MIN, f32::max);
+        let travel = top - bottom - 4.0;
+
+        for r in &rects {
+            commands
+                .spawn((
+                    Sprite {
+                        color: color(theme.cap),
+                        custom_size: Some(r.size()),
+                        ..default()
+                    },
+                    place(r.center(), 0.0),
+                    Cap(sc),
+                ))
+                .set_parent(flat);
+            commands
+                .spawn((
+                    Sprite {
+                        color: color(theme.fill.sample(0.0)),
+                        custom_size: Some(Vec2::new(r.width() - 4.0, 0.0)),
+                        anchor: bevy::sprite::Anchor::BottomCenter,
+                        ..default()
+                    },
+                    place(Vec2::new(r.center().x, r.min.y + 2.0), 1.0),
+                    Fill {
+                        sc,
+                        travel,
+                        lo: r.min.y - bottom,
+                        max: r.height() - 4.0,
+                    },
+                ))
+                .set_parent(flat);
+        }
+        commands
+            .spawn((
+                Text2d::new(key.label.clone()),
                 TextFont {
-                    font_size: if wu > 1.5 { 10.0 } else { 13.0 },
+                    font_size: if key.w > 1.5 { 10.0 } else { 13.0 },
                     ..default()
                 },
-                TextColor(Color::srgb(0.63, 0.63, 0.63)),
-                Transform::from_xyz(cx, cy + 6.0, 2.0),
-                Lbl,
-            ));
-            commands.spawn((
+                TextColor(Tone::Label.color(theme)),
+                Tone::Label,
+                place(main.center() + Vec2::Y * 6.0, 2.0),
+                Lbl(i),
+            ))
+            .set_parent(flat);
+        commands
+            .spawn((
                 Text2d::new(""),
                 TextFont {
                     font_size: 8.0,
                     ..default()
                 },
                 TextColor(Color::srgba(1.0, 1.0, 1.0, 0.0)),
-                Transform::from_xyz(cx, cy - 8.0, 3.0),
+                place(main.center() - Vec2::Y * 8.0, 3.0),
                 PctTxt(sc),
+            ))
+            .set_parent(flat);
+        let base = place(Vec2::new(main.center().x, bottom + 2.0), 1.5);
+        // The session peak is a short dash, to tell it from the others.
+        for (kind, width, z) in [
+            (Peak::Smoothing, 1.0, 1.4),
+            (Peak::Session, 0.4, 1.42),
+            (Peak::Stroke, 1.0, 1.44),
+        ] {
+            commands
+                .spawn((
+                    Sprite {
+                        color: color(theme.value),
+                        custom_size: Some(Vec2::new((main.width() - 4.0) * width, 2.0)),
+                        ..default()
+                    },
+                    base.with_translation(base.translation.with_z(z)),
+                    Visibility::Hidden,
+                    PeakMark {
+                        kind,
+                        sc,
+                        bottom: base.translation.truncate(),
+                        up: (rot * Vec3::Y).truncate(),
+                        travel,
+                    },
+                ))
+                .set_parent(flat);
+        }
+        commands
+            .spawn((
+                Sprite {
+                    color: Color::srgb(1.0, 0.85, 0.3),
+                    custom_size: Some(Vec2::new(3.0, 0.0)),
+                    anchor: bevy::sprite::Anchor::BottomCenter,
+                    ..default()
+                },
+                place(Vec2::new(main.max.x - 4.0, bottom + 2.0), 1.2),
+                Visibility::Hidden,
+                VelBar { sc, travel },
+            ))
+            .set_parent(flat);
+
+        // The 3D view lays the board flat (x, -y) and stands the caps on it.
+        let material = assets.materials.add(StandardMaterial {
+            base_color: color(theme.cap),
+            perceptual_roughness: 0.7,
+            ..default()
+        });
+        for r in &rects {
+            let p = place(r.center(), 0.0).translation;
+            commands.spawn((
+                Mesh3d(
+                    assets
+                        .meshes
+                        .add(Cuboid::new(r.width(), CAP_3D, r.height())),
+                ),
+                MeshMaterial3d(material.clone()),
+                Transform::from_xyz(p.x, CAP_3D / 2.0, -p.y)
+                    .with_rotation(Quat::from_rotation_y(-key.r.to_radians())),
+                RenderLayers::layer(1),
+                Keycap(sc),
+                BoardItem,
             ));
-            xo += kw + KEY_GAP;
         }
     }
+    commands.spawn((
+        Mesh3d(
+            assets
+                .meshes
+                .add(Cuboid::new(size.x + 16.0, 8.0, size.y + 16.0)),
+        ),
+        MeshMaterial3d(assets.materials.add(Color::srgb(0.12, 0.12, 0.14))),
+        Transform::from_xyz(0.0, -SINK_3D - 4.0, size.y / 2.0 - oy),
+        RenderLayers::layer(1),
+        BoardItem,
+    ));
+    commands.spawn((
+        DirectionalLight {
+            illuminance: 6000.0,
+            shadows_enabled: true,
+            ..default()
+        },
+        Transform::from_xyz(-300.0, 800.0, 500.0).looking_at(Vec3::ZERO, Vec3::Y),
+        RenderLayers::layer(1),
+        BoardItem,
+    ));
+
+    // Bar chart: a bar per key in layout order, over a track of full height,
+    // with the legends turned upright below.
+    let chart = commands
+        .spawn((
+            Transform::default(),
+            Visibility::Hidden,
+            BarChart,
+            BoardItem,
+        ))
+        .id();
+    let slot = size.x / board.layout.keys.len().max(1) as f32;
+    let base = oy - size.y + 28.0;
+    let max = size.y - 28.0;
+    for (i, key) in board.layout.keys.iter().enumerate() {
+        let x = ox + (i as f32 + 0.5) * slot;
+        let bar = |c: Color, height: f32| Sprite {
+            color: c,
+            custom_size: Some(Vec2::new(slot * 0.7, height)),
+            anchor: bevy::sprite::Anchor::BottomCenter,
+            ..default()
+        };
+        commands
+            .spawn((
+                bar(color(theme.cap), max),
+                Transform::from_xyz(x, base, 0.0),
+                BarTrack,
+            ))
+            .set_parent(chart);
+        commands
+            .spawn((
+                bar(color(theme.fill.sample(0.0)), 0.0),
+                Transform::from_xyz(x, base, 1.0),
+                Bar {
+                    sc: key.scancode,
+                    max,
+                },
+            ))
+            .set_parent(chart);
+        commands
+            .spawn((
+                Text2d::new(key.label.replace('\n', " ")),
+                TextFont {
+                    font_size: 8.0,
+                    ..default()
+                },
+                TextColor(Tone::Label.color(theme)),
+                Tone::Label,
+                bevy::sprite::Anchor::CenterRight,
+                Transform::from_xyz(x, base - 4.0, 2.0)
+                    .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2)),
+            ))
+            .set_parent(chart);
+    }
 
     commands.spawn((
         Text2d::new("Starting..."),
@@ -286,59 +1058,740 @@ fn setup(mut commands: Commands) {
             font_size: 13.0,
             ..default()
         },
-        TextColor(Color::srgb(0.53, 0.53, 0.53)),
-        Transform::from_xyz(0.0, oy - bh - 18.0, 2.0),
+        TextColor(Tone::Dim.color(theme)),
+        Transform::from_xyz(0.0, oy - size.y - 18.0, 2.0),
         StatusTxt,
+        BoardItem,
+    ));
+    // Scope legend and help, in the strip below the window it grows by.
+    let (sl, st) = scope_rect(board.win);
+    for (i, color) in SCOPE_COLORS.into_iter().enumerate() {
+        commands.spawn((
+            Text2d::new(""),
+            TextFont {
+                font_size: 12.0,
+                ..default()
+            },
+            TextColor(color),
+            bevy::sprite::Anchor::BottomLeft,
+            Transform::from_xyz(sl.x + i as f32 * 70.0, st.y + 4.0, 2.0),
+            ScopeLegend(i),
+            BoardItem,
+        ));
+    }
+    commands.spawn((
+        Text2d::new(""),
+        TextFont {
+            font_size: 11.0,
+            ..default()
+        },
+        TextColor(Tone::Dim.color(theme)),
+        Tone::Dim,
+        bevy::sprite::Anchor::BottomRight,
+        Transform::from_xyz(st.x, st.y + 4.0, 2.0),
+        ScopeTxt,
+        BoardItem,
+    ));
+    // Stats panel, in the strip the window grows by when it is shown.
+    commands.spawn((
+        Text2d::new(""),
+        TextFont {
+            font_size: 12.0,
+            ..default()
+        },
+        TextLayout::new_with_justify(JustifyText::Left),
+        TextColor(Tone::Text.color(theme)),
+        Tone::Text,
+        bevy::sprite::Anchor::TopLeft,
+        Transform::from_xyz(board.win.x / 2.0 + 8.0, board.win.y / 2.0 - 16.0, 2.0),
+        Visibility::Hidden,
+        StatsTxt,
+        BoardItem,
     ));
 }
 
+/// Show the keymap's Fn layer legends while Fn (0xFF) is held.
+fn update_labels(state: Res<AppState>, board: Res<Board>, mut labels: Query<(&Lbl, &mut Text2d)>) {
+    let layer = match board.layout.fn_layer {
+        Some(n) if state.kb.value(0xFF) > REST => n,
+        _ => 0,
+    };
+    for (l, mut txt) in labels.iter_mut() {
+        let Some(key) = board.layout.keys.get(l.0) else {
+            continue;
+        };
+        if txt.0 != key.legend(layer) {
+            txt.0 = key.legend(layer).to_string();
+        }
+    }
+}
+
+fn update_usage(keys: Res<ButtonInput<KeyCode>>, state: Res<AppState>, mut usage: ResMut<Usage>) {
+    if keys.just_pressed(KeyCode::F7) {
+        usage.metric = match usage.metric {
+            None => Some(Metric::Presses),
+            Some(Metric::Presses) => Some(Metric::Travel),
+            Some(Metric::Travel) => None,
+        };
+    }
+    let (strokes, next) = state.kb.keystrokes_since(usage.next_seq);
+    usage.next_seq = next;
+    // The fallback also adds to the report history; its travel comes from
+    // the frames instead.
+    let reports = state.kb.reports_since(&mut usage.report_seqs);
+    if let Ok(mut h) = usage.heatmap.lock() {
+        if keys.just_pressed(KeyCode::F8) {
+            h.reset();
+        }
+        if state.kb.is_active() {
+            for (sc, depth) in reports {
+                h.record(sc, depth);
+            }
+            for s in &strokes {
+                h.add_keystroke(s);
+            }
+        } else {
+            h.update(&state.kb.values());
+        }
+    }
+}
+
+type CapFilter = (Without<Fill>, Without<PctTxt>);
+
 fn update_vis(
     state: Res<AppState>,
+    usage: Res<Usage>,
+    themes: Res<Themes>,
+    view: Res<ViewMode>,
     mut fills: Query<(&Fill, &mut Sprite), Without<Cap>>,
-    mut caps: Query<(&Cap, &mut Sprite), (Without<Fill>, Without<PctTxt>)>,
+    mut caps: Query<(&Cap, &mut Sprite), CapFilter>,
     mut pcts: Query<(&PctTxt, &mut Text2d, &mut TextColor)>,
 ) {
+    let theme = themes.get();
     let disp = &state.display;
+    let heat = usage
+        .metric
+        .and_then(|m| usage.heatmap.lock().ok().map(|h| (m, h.clone())));
+    let heat_max = heat.as_ref().map_or(0.0, |(m, h)| h.max(*m));
 
     for (f, mut sp) in fills.iter_mut() {
-        let v = disp[f.0 as usize].clamp(0.0, 1.0);
+        let v = disp[f.sc as usize].clamp(0.0, 1.0);
         let w = sp.custom_size.map(|s| s.x).unwrap_or(42.0);
-        sp.custom_size = Some(Vec2::new(w, v * (KEY_H - 4.0)));
-        let r = v * v;
-        let g = 0.4 + 0.6 * (1.0 - (v - 0.5).abs() * 2.0).max(0.0);
-        let b = 1.0 - v * 0.4;
-        sp.color = Color::srgb(r, g, b);
+        sp.custom_size = Some(Vec2::new(w, (v * f.travel - f.lo).clamp(0.0, f.max)));
+        sp.color = color(theme.fill.sample(v));
+        if *view == ViewMode::Glow {
+            let c = sp.color.to_linear();
+            let k = 1.0 + GLOW * v;
+            sp.color = Color::linear_rgb(c.red * k, c.green * k, c.blue * k);
+        }
     }
 
     for (c, mut sp) in caps.iter_mut() {
+        if let Some((m, h)) = &heat {
+            // Unused keys keep the cap colour.
+            let n = h.get(c.0, *m);
+            sp.color = color(if n > 0.0 && heat_max > 0.0 {
+                theme.heatmap.sample((n / heat_max) as f32)
+            } else {
+                theme.cap
+            });
+            continue;
+        }
         let v = disp[c.0 as usize].clamp(0.0, 1.0);
-        let g = v * 0.2;
-        sp.color = Color::srgb(0.20 + g, 0.20 + g, 0.20 + g);
+        sp.color = color(theme.cap.lerp(theme.cap_pressed, v));
     }
 
     for (p, mut txt, mut col) in pcts.iter_mut() {
+        if let Some((m, h)) = &heat {
+            let n = h.get(p.0, *m);
+            **txt = match m {
+                _ if n == 0.0 => String::new(),
+                Metric::Presses => format!("{n:.0}"),
+                Metric::Travel => format!("{:.0}mm", n * heatmap::TRAVEL_MM as f64),
+            };
+            *col = TextColor(color(theme.value).with_alpha(0.8));
+            continue;
+        }
         let v = disp[p.0 as usize].clamp(0.0, 1.0);
         if v > 0.01 {
             let pct = (v * 100.0).round();
             **txt = format!("{pct:.0}%");
-            *col = TextColor(Color::srgba(1.0, 1.0, 1.0, (v * 2.0).min(0.9)));
+            *col = TextColor(color(theme.value).with_alpha((v * 2.0).min(0.9)));
         } else {
             **txt = String::new();
-            *col = TextColor(Color::srgba(1.0, 1.0, 1.0, 0.0));
+            *col = TextColor(color(theme.value).with_alpha(0.0));
+        }
+    }
+}
+
+type ViewParts = Or<(With<FlatBoard>, With<BarChart>)>;
+type Only2d = (With<Camera2d>, Without<Camera3d>);
+
+/// F1 switches to the next view: shows the flat board or the bar chart,
+/// and turns on the 3D camera or bloom when the view needs them.
+fn switch_view(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut view: ResMut<ViewMode>,
+    board: Res<Board>,
+    mut parts: Query<(&mut Visibility, Has<BarChart>), ViewParts>,
+    mut cam2d: Query<(Entity, &mut Camera), Only2d>,
+    mut cam3d: Query<(&mut Camera, &mut Transform), With<Camera3d>>,
+) {
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keys.just_pressed(KeyCode::F1) && !shift {
+        *view = view.next();
+    }
+    // A new layout spawns its parts with the defaults.
+    if !view.is_changed() && !board.is_changed() {
+        return;
+    }
+    let v = *view;
+    for (mut vis, chart) in parts.iter_mut() {
+        let shown = if chart {
+            v == ViewMode::Bars
+        } else {
+            matches!(v, ViewMode::Fill | ViewMode::Glow)
+        };
+        *vis = if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+    for (e, mut cam) in cam2d.iter_mut() {
+        // Over the 3D view, the HUD is drawn without clearing it.
+        cam.clear_color = if v == ViewMode::Keycaps {
+            ClearColorConfig::None
+        } else {
+            ClearColorConfig::Default
+        };
+        if v == ViewMode::Glow {
+            commands.entity(e).insert(Bloom::NATURAL);
+        } else {
+            commands.entity(e).remove::<Bloom>();
+        }
+    }
+    // Look down at the board from the front, far enough to see all of it.
+    let size = board_px(&board.layout);
+    let centre = Vec3::new(0.0, 0.0, -20.0);
+    let d = (size.x * 0.75).max(size.y * 2.0);
+    for (mut cam, mut tf) in cam3d.iter_mut() {
+        cam.is_active = v == ViewMode::Keycaps;
+        *tf = Transform::from_translation(centre + Vec3::new(0.0, d * 0.7, d * 0.55))
+            .looking_at(centre, Vec3::Y);
+    }
+}
+
+/// 3D keycaps sink by their depth and take the fill colour.
+fn update_keycaps(
+    state: Res<AppState>,
+    themes: Res<Themes>,
+    view: Res<ViewMode>,
+    mut caps: Query<(&Keycap, &mut Transform, &MeshMaterial3d<StandardMaterial>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if *view != ViewMode::Keycaps {
+        return;
+    }
+    let theme = themes.get();
+    for (k, mut tf, mat) in caps.iter_mut() {
+        let v = state.display[k.0 as usize].clamp(0.0, 1.0);
+        tf.translation.y = CAP_3D / 2.0 - v * SINK_3D;
+        if let Some(m) = materials.get_mut(&mat.0) {
+            m.base_color = color(theme.cap.lerp(theme.fill.sample(v), v));
+        }
+    }
+}
+
+fn update_bars(
+    state: Res<AppState>,
+    themes: Res<Themes>,
+    view: Res<ViewMode>,
+    mut bars: Query<(&Bar, &mut Sprite), Without<BarTrack>>,
+    mut tracks: Query<&mut Sprite, With<BarTrack>>,
+) {
+    if *view != ViewMode::Bars {
+        return;
+    }
+    let theme = themes.get();
+    for (b, mut sp) in bars.iter_mut() {
+        let v = state.display[b.sc as usize].clamp(0.0, 1.0);
+        let w = sp.custom_size.map_or(0.0, |s| s.x);
+        sp.custom_size = Some(Vec2::new(w, v * b.max));
+        sp.color = color(theme.fill.sample(v));
+    }
+    for mut sp in tracks.iter_mut() {
+        sp.color = color(theme.cap);
+    }
+}
+
+/// Press velocity (depth per ms) drawn as a full-height bar.
+const VEL_FULL: f32 = 0.02;
+
+fn update_velocity_vis(
+    keys: Res<ButtonInput<KeyCode>>,
+    state: Res<AppState>,
+    mut show: ResMut<ShowVelocity>,
+    mut bars: Query<(&VelBar, &mut Sprite, &mut Visibility)>,
+) {
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keys.just_pressed(KeyCode::F5) && !shift {
+        show.0 = !show.0;
+    }
+    let dynamics = show.0.then(|| state.kb.all_dynamics());
+    for (bar, mut sp, mut vis) in bars.iter_mut() {
+        let Some(d) = &dynamics else {
+            *vis = Visibility::Hidden;
+            continue;
+        };
+        let v = (d[bar.sc as usize].peak_velocity / VEL_FULL).clamp(0.0, 1.0);
+        *vis = Visibility::Inherited;
+        sp.custom_size = Some(Vec2::new(3.0, v * bar.travel));
+    }
+}
+
+/// Peak markers: the peak-hold smoothing's held peaks, and (Shift+F5) the
+/// deepest point of each key's latest keystroke and of the session.
+fn update_peak_vis(
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    state: Res<AppState>,
+    themes: Res<Themes>,
+    mut peaks: ResMut<KeyPeaks>,
+    mut marks: Query<(&PeakMark, &mut Transform, &mut Visibility, &mut Sprite)>,
+) {
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keys.just_pressed(KeyCode::F5) && shift {
+        peaks.visible = !peaks.visible;
+    }
+    let now = time.elapsed_secs();
+    // Keystrokes that started and ended between two frames only show up
+    // here; the one under way is in the dynamics.
+    let (strokes, next) = state.kb.keystrokes_since(peaks.seq);
+    peaks.seq = next;
+    for s in strokes {
+        peaks.last[s.scancode as usize] = (s.max_depth, now);
+    }
+    let dynamics = state.kb.all_dynamics();
+    for (last, d) in peaks.last.iter_mut().zip(dynamics.iter()) {
+        if d.max_depth > 0.0 {
+            *last = (d.max_depth, now);
+        }
+    }
+
+    let t = themes.get();
+    let held = state.smoother.peaks();
+    for (m, mut tf, mut vis, mut sp) in marks.iter_mut() {
+        let sc = m.sc as usize;
+        let (depth, alpha, c) = match m.kind {
+            Peak::Smoothing => {
+                let p = held.map_or(0.0, |p| p[sc]).clamp(0.0, 1.0);
+                (p, 1.0, t.fill.sample(p))
+            }
+            Peak::Stroke if peaks.visible => {
+                let (p, at) = peaks.last[sc];
+                let alpha = if peaks.fade > 0.0 {
+                    1.0 - (now - at) / peaks.fade
+                } else {
+                    1.0
+                };
+                (p, alpha, t.value)
+            }
+            Peak::Session if peaks.visible => (dynamics[sc].session_max, 0.7, t.warn),
+            _ => (0.0, 0.0, t.value),
+        };
+        if depth <= REST || alpha <= 0.0 {
+            *vis = Visibility::Hidden;
+            continue;
         }
+        *vis = Visibility::Inherited;
+        let p = m.bottom + m.up * depth.min(1.0) * m.travel;
+        tf.translation = p.extend(tf.translation.z);
+        sp.color = color(c).with_alpha(alpha.min(1.0));
     }
 }
 
-fn update_hud(state: Res<AppState>, mut sq: Query<(&mut Text2d, &mut TextColor), With<StatusTxt>>) {
+/// Cursor position in world coordinates.
+fn cursor_world(windows: &Query<&Window>, cams: &Cam2d) -> Option<Vec2> {
+    let cursor = windows.get_single().ok()?.cursor_position()?;
+    let (cam, tf) = cams.get_single().ok()?;
+    cam.viewport_to_world_2d(tf, cursor).ok()
+}
+
+/// Key caps with their shape, for hit-testing clicks.
+type CapQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Cap,
+        &'static Transform,
+        &'static Sprite,
+        &'static InheritedVisibility,
+    ),
+>;
+
+/// Scancode of the key cap under world point `p`; caps may be rotated, and
+/// are not there in the 3D and bar views.
+fn cap_at(p: Vec2, caps: &CapQuery) -> Option<u8> {
+    caps.iter().find_map(|(c, tf, sp, vis)| {
+        if !vis.get() {
+            return None;
+        }
+        let half = sp.custom_size.unwrap_or(Vec2::ZERO) / 2.0;
+        let d = (tf.rotation.inverse() * (p.extend(0.0) - tf.translation))
+            .truncate()
+            .abs();
+        (d.x <= half.x && d.y <= half.y).then_some(c.0)
+    })
+}
+
+/// Caps go amber once pressed, then green on passing or red on failing.
+fn update_qa_vis(
+    qa: Res<QaMode>,
+    state: Res<AppState>,
+    themes: Res<Themes>,
+    mut caps: Query<(&Cap, &mut Sprite)>,
+) {
+    if !qa.0 {
+        return;
+    }
+    let Some(report) = state.kb.qa_report() else {
+        return;
+    };
+    for (c, mut sp) in caps.iter_mut() {
+        let Some(k) = report.keys.iter().find(|k| k.scancode == c.0) else {
+            continue;
+        };
+        let t = themes.get();
+        let status = if k.passed() {
+            t.ok
+        } else if !k.pending() {
+            t.error
+        } else if k.qa.reports > 0 {
+            t.warn
+        } else {
+            continue;
+        };
+        sp.color = color(t.cap.lerp(status, 0.5));
+    }
+}
+
+fn mark_frame(state: Res<AppState>) {
+    state.kb.mark_frame();
+}
+
+fn key_label(layout: &Layout, sc: u8) -> String {
+    layout
+        .key(sc)
+        .map_or_else(|| format!("0x{sc:02X}"), |k| k.label.clone())
+}
+
+fn update_stats(
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    state: Res<AppState>,
+    board: Res<Board>,
+    mut stats: ResMut<Stats>,
+    mut txt: Query<(&mut Text2d, &mut Visibility), With<StatsTxt>>,
+) {
+    let shared = stats.analytics.clone();
+    let Ok(mut a) = shared.lock() else {
+        return;
+    };
+    // Read before the strokes: a key released in between is then both held
+    // and in the batch, never neither.
+    let held_since = state.kb.held_since();
+    let (strokes, next) = state.kb.keystrokes_since(a.next_seq);
+    let now = state.kb.report_now_ms().unwrap_or_else(kb_hall::now_ms);
+    a.next_seq = next;
+    a.extend(strokes, held_since, now);
+
+    if keys.just_pressed(KeyCode::F6) {
+        stats.visible = !stats.visible;
+        stats.refresh.reset();
+    } else if !stats.refresh.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let Ok((mut t, mut vis)) = txt.get_single_mut() else {
+        return;
+    };
+    if !stats.visible {
+        *vis = Visibility::Hidden;
+        return;
+    }
+    *vis = Visibility::Inherited;
+
+    let ms = |v: Option<f64>| v.map_or("-".into(), |v| format!("{v:.0} ms"));
+    let mut out = format!(
+        "Typing stats (F6)\n\nkeystrokes  {}\nWPM  {}  now {}\ndwell p50  {}\nflight mean  {}\n\n",
+        a.total_keystrokes(),
+        a.wpm().map_or("-".into(), |w| format!("{w:.0}")),
+        a.recent_wpm(now).map_or("-".into(), |w| format!("{w:.0}")),
+        ms(a.dwell_percentile(0.5)),
+        ms(a.mean_flight_ms()),
+    );
+
+    let mut top: Vec<_> = a.keys().collect();
+    top.sort_by_key(|(_, k)| std::cmp::Reverse(k.count()));
+    out.push_str("key       n   dwell  depth\n");
+    for (sc, k) in top.iter().take(5) {
+        out.push_str(&format!(
+            "{:<7} {:>4}  {:>4.0}ms  {:>3.0}%\n",
+            key_label(&board.layout, *sc),
+            k.count(),
+            k.dwell_percentile(0.5).unwrap_or(0.0),
+            k.max_depth.mean().unwrap_or(0.0) * 100.0
+        ));
+    }
+
+    let mut slow: Vec<_> = a.bigrams().filter(|(_, s)| s.count >= 3).collect();
+    slow.sort_by(|x, y| {
+        y.1.mean()
+            .unwrap_or(0.0)
+            .total_cmp(&x.1.mean().unwrap_or(0.0))
+    });
+    if !slow.is_empty() {
+        out.push_str("\nslowest bigrams\n");
+        for ((x, y), s) in slow.iter().take(4) {
+            out.push_str(&format!(
+                "{} > {}  {}\n",
+                key_label(&board.layout, *x),
+                key_label(&board.layout, *y),
+                ms(s.mean())
+            ));
+        }
+    }
+    **t = out;
+}
+
+/// Scale the board, HUD, stats panel (right) and scope (bottom) to fit the
+/// window at any size and pixel density. The content is rendered at a scale
+/// factor of window pixels per content unit, so text stays sharp. When the
+/// content changes size (layout, panel or scope), a window that is not
+/// fullscreen grows or shrinks with it at the current zoom.
+fn fit_window(
+    board: Res<Board>,
+    stats: Res<Stats>,
+    scope: Res<Scope>,
+    mut last: Local<Vec2>,
+    mut windows: Query<&mut Window>,
+    mut cams: Query<&mut Transform, Only2d>,
+) {
+    let extra = Vec2::new(
+        if stats.visible { PANEL_W } else { 0.0 },
+        if scope.keys.is_empty() { 0.0 } else { SCOPE_H },
+    );
+    let size = board.win + extra;
+    for mut w in windows.iter_mut() {
+        if *last != Vec2::ZERO && *last != size && w.mode == WindowMode::Windowed {
+            w.resolution.set(size.x, size.y);
+        }
+        let px = Vec2::new(
+            w.resolution.physical_width() as f32,
+            w.resolution.physical_height() as f32,
+        );
+        let zoom = (px / size).min_element();
+        if zoom > 0.0 && (w.resolution.scale_factor() - zoom).abs() > 1e-3 {
+            w.resolution.set_scale_factor_override(Some(zoom));
+        }
+    }
+    *last = size;
+    for mut tf in cams.iter_mut() {
+        tf.translation.x = extra.x / 2.0;
+        tf.translation.y = -extra.y / 2.0;
+    }
+}
+
+/// F11 switches between a window and borderless fullscreen, except in
+/// kiosk mode, which stays fullscreen.
+fn toggle_fullscreen(
+    keys: Res<ButtonInput<KeyCode>>,
+    kiosk: Res<Kiosk>,
+    mut windows: Query<&mut Window>,
+) {
+    if kiosk.0 || !keys.just_pressed(KeyCode::F11) {
+        return;
+    }
+    for mut w in windows.iter_mut() {
+        w.mode = match w.mode {
+            WindowMode::Windowed => WindowMode::BorderlessFullscreen(MonitorSelection::Current),
+            _ => WindowMode::Windowed,
+        };
+    }
+}
+
+/// Bottom-left and top-right corners of the scope plot, for base window
+/// size `win`.
+fn scope_rect(win: Vec2) -> (Vec2, Vec2) {
+    (
+        Vec2::new(-win.x / 2.0 + 30.0, -win.y / 2.0 - SCOPE_H + 16.0),
+        Vec2::new(win.x / 2.0 - 30.0, -win.y / 2.0 - 30.0),
+    )
+}
+
+/// Click a key to add it to the scope or remove it,
+/// right-click to close the scope; the wheel changes the time span.
+fn scope_input(
+    mut scope: ResMut<Scope>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut wheel: EventReader<MouseWheel>,
+    windows: Query<&Window>,
+    cams: Cam2d,
+    caps: CapQuery,
+) {
+    if mouse.just_pressed(MouseButton::Right) {
+        scope.keys.clear();
+    }
+    if mouse.just_pressed(MouseButton::Left) {
+        let hit = cursor_world(&windows, &cams).and_then(|p| cap_at(p, &caps));
+        if let Some(sc) = hit {
+            if let Some(i) = scope.keys.iter().position(|&k| k == sc) {
+                scope.keys.remove(i);
+            } else if scope.keys.len() < SCOPE_COLORS.len() {
+                scope.keys.push(sc);
+            }
+        }
+    }
+    let notches: f32 = wheel.read().map(|e| e.y.signum()).sum();
+    if notches != 0.0 && !scope.keys.is_empty() {
+        let ms = scope.window_ms * 1.25f64.powf(-notches as f64);
+        scope.window_ms = ms.clamp(SCOPE_MS.0, SCOPE_MS.1);
+    }
+}
+
+fn draw_scope(
+    state: Res<AppState>,
+    board: Res<Board>,
+    scope: Res<Scope>,
+    mut gizmos: Gizmos,
+    mut legend: Query<(&ScopeLegend, &mut Text2d), Without<ScopeTxt>>,
+    mut help: Query<&mut Text2d, With<ScopeTxt>>,
+) {
+    for (l, mut t) in legend.iter_mut() {
+        **t = scope
+            .keys
+            .get(l.0)
+            .map_or(String::new(), |&sc| key_label(&board.layout, sc));
+    }
+    for mut t in help.iter_mut() {
+        **t = if scope.keys.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "{:.2} s  |  click keys to add / remove, wheel to zoom, right-click to close",
+                scope.window_ms / 1000.0
+            )
+        };
+    }
+    if scope.keys.is_empty() {
+        return;
+    }
+
+    let (lo, hi) = scope_rect(board.win);
+    let size = hi - lo;
+    let grid = Color::srgb(0.25, 0.25, 0.25);
+    gizmos.rect_2d((lo + hi) / 2.0, size, grid);
+    for q in [0.25, 0.5, 0.75] {
+        let y = lo.y + q * size.y;
+        gizmos.line_2d(Vec2::new(lo.x, y), Vec2::new(hi.x, y), grid.with_alpha(0.4));
+    }
+
+    let window = scope.window_ms;
+    let y_of = |d: f32| lo.y + d.clamp(0.0, 1.0) * size.y;
+    for (i, &sc) in scope.keys.iter().enumerate() {
+        let color = SCOPE_COLORS[i];
+        for (mm, alpha) in [(ACTUATION_MM, 0.5), (RELEASE_MM, 0.25)] {
+            let y = y_of(mm / heatmap::TRAVEL_MM);
+            let (from, to) = (Vec2::new(lo.x, y), Vec2::new(hi.x, y));
+            gizmos.line_2d(from, to, color.with_alpha(alpha));
+        }
+
+        let samples = state.kb.key_history(sc, window);
+        let Some(&(_, last)) = samples.last() else {
+            continue;
+        };
+        let x_of = |age: f64| hi.x - (age.min(window) / window) as f32 * size.x;
+        // No report means no change: hold each depth until the next report
+        // (a step, not a ramp), and the last one up to now.
+        let mut points = Vec::with_capacity(samples.len() * 2 + 1);
+        let mut held = None;
+        for &(age, d) in &samples {
+            let x = x_of(age);
+            if let Some(prev) = held {
+                points.push(Vec2::new(x, y_of(prev)));
+            }
+            points.push(Vec2::new(x, y_of(d)));
+            held = Some(d);
+        }
+        points.push(Vec2::new(hi.x, y_of(last)));
+        gizmos.linestrip_2d(points, color);
+    }
+}
+
+/// F9 re-zeroes keys with a sensor warning.
+#[allow(clippy::too_many_arguments)]
+fn update_hud(
+    keys: Res<ButtonInput<KeyCode>>,
+    state: Res<AppState>,
+    board: Res<Board>,
+    latency: Res<LatencyMode>,
+    qa: Res<QaMode>,
+    usage: Res<Usage>,
+    themes: Res<Themes>,
+    mut sq: Query<(&mut Text2d, &mut TextColor), With<StatusTxt>>,
+) {
     let active = state.kb.is_active();
-    let st = state.kb.status();
+    let mut st = state.kb.status();
+    if latency.0 {
+        let r = state.kb.latency_report();
+        if let (Some(p50), Some(p99)) = (r.frame.percentile(0.5), r.frame.percentile(0.99)) {
+            st = format!("{st}  |  hid->frame p50 {p50:.1} ms  p99 {p99:.1} ms");
+        }
+    }
+    if let (true, Some((done, total))) = (qa.0, state.kb.qa_coverage()) {
+        st = format!("{st}  |  QA: {done}/{total} keys, press each fully and slowly");
+    }
+    if keys.just_pressed(KeyCode::F9) {
+        state.kb.rezero();
+    }
+    let warnings = state.kb.sensor_warnings();
+    if !warnings.is_empty() {
+        let list: Vec<String> = warnings
+            .iter()
+            .take(4)
+            .map(|&(sc, w)| match w {
+                SensorWarning::Drift(d) => format!("{} drift +{d:.0}", key_label(&board.layout, sc)),
+                SensorWarning::Stuck { .. } => format!("{} stuck", key_label(&board.layout, sc)),
+            })
+            .collect();
+        let more = warnings.len().saturating_sub(list.len());
+        let more = if more > 0 { format!(" +{more}") } else { String::new() };
+        st = format!("{st}  |  sensor: {}{more} (F9 re-zero)", list.join(", "));
+    }
+    let smoothing = state.smoother.model();
+    if smoothing != Smoothing::default() {
+        st = format!("{st}  |  {smoothing} (Shift+F1, Shift+Up/Down)");
+    }
+    if let Some(m) = usage.metric {
+        let total = usage.heatmap.lock().map_or(0, |h| h.total_presses());
+        let what = match m {
+            Metric::Presses => "presses",
+            Metric::Travel => "travel",
+        };
+        st = format!("{st}  |  heatmap: {what}, {total} presses (F7 mode, F8 reset)");
+    }
 
     for (mut t, mut c) in sq.iter_mut() {
         **t = st.clone();
-        if active {
-            *c = TextColor(Color::srgb(0.3, 0.9, 0.4));
+        let t = themes.get();
+        *c = TextColor(color(if !warnings.is_empty() {
+            t.error
+        } else if active {
+            t.ok
         } else {
-            *c = TextColor(Color::srgb(0.9, 0.7, 0.3));
-        }
+            t.warn
+        }));
     }
 }
@@ -0,0 +1,752 @@
+//! MIDI output: keys as velocity-sensitive notes, aftertouch and CCs.
+//!
+//! A [`MidiMap`] assigns a [`KeyMapping`] to each scancode. [`MidiMapper`]
+//! turns successive analog snapshots into [`MidiMessage`]s:
+//!
+//! - `note` keys send note-on when the depth crosses `note_on`, with a
+//!   velocity from the keystroke's peak speed (see [`crate::dynamics`]; or,
+//!   from plain snapshots, the average speed from rest to that point), and
+//!   note-off once it falls back below `note_off`. With `aftertouch` set,
+//!   depth while held is sent as polyphonic key pressure.
+//! - `cc` keys send their depth as a 0..127 controller value.
+//!
+//! Messages go to a [`MidiSink`]: [`SmfWriter`] records a Standard MIDI
+//! File, and with the `alsa` feature [`AlsaSink`] plays them through an
+//! ALSA sequencer port.
+//!
+//! Maps can be loaded from JSON:
+//!
+//! ```json
+//! { "noteOn": 0.3, "noteOff": 0.2,
+//!   "keys": { "4": { "type": "note", "note": 60, "aftertouch": true },
+//!             "44": { "type": "cc", "controller": 1 } } }
+//! ```
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dynamics::REST;
+use crate::{now_ms, AnalogKeyboard, KeyDynamics};
+
+/// SMF resolution in ticks per quarter note.
+const TICKS_PER_QUARTER: u16 = 480;
+/// SMF tempo in microseconds per quarter note (120 bpm).
+const TEMPO_US: u32 = 500_000;
+
+/// What one key plays. Channels are 0-based (0 = MIDI channel 1).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeyMapping {
+    Note {
+        note: u8,
+        #[serde(default)]
+        channel: u8,
+        /// Send depth as polyphonic aftertouch while held.
+        #[serde(default)]
+        aftertouch: bool,
+    },
+    Cc {
+        controller: u8,
+        #[serde(default)]
+        channel: u8,
+    },
+}
+
+/// Per-scancode mappings and the note thresholds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MidiMap {
+    /// Depth (0..1) at which a note starts.
+    pub note_on: f32,
+    /// Depth below which a held note stops; keep below `note_on`.
+    pub note_off: f32,
+    /// Key speed in full travels per second that maps to velocity 127.
+    pub full_velocity_speed: f32,
+    /// Ordered by scancode, so simultaneous changes go out in a fixed order.
+    pub keys: BTreeMap<u8, KeyMapping>,
+}
+
+impl Default for MidiMap {
+    fn default() -> Self {
+        Self {
+            note_on: 0.3,
+            note_off: 0.2,
+            full_velocity_speed: 20.0,
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl MidiMap {
+    /// DAW-style "musical typing": A..K play C4..C5 (W E T Y U are the
+    /// black keys), and Space is the mod wheel (CC 1).
+    pub fn musical_typing() -> Self {
+        let notes = [
+            (0x04, 60), // A  C4
+            (0x1A, 61), // W  C#
+            (0x16, 62), // S  D
+            (0x08, 63), // E  D#
+            (0x07, 64), // D  E
+            (0x09, 65), // F  F
+            (0x17, 66), // T  F#
+            (0x0A, 67), // G  G
+            (0x1C, 68), // Y  G#
+            (0x0B, 69), // H  A
+            (0x18, 70), // U  A#
+            (0x0D, 71), // J  B
+            (0x0E, 72), // K  C5
+        ];
+        let mut keys: BTreeMap<u8, KeyMapping> = notes
+            .iter()
+            .map(|&(sc, note)| {
+                let mapping = KeyMapping::Note {
+                    note,
+                    channel: 0,
+                    aftertouch: true,
+                };
+                (sc, mapping)
+            })
+            .collect();
+        keys.insert(
+            0x2C,
+            KeyMapping::Cc {
+                controller: 1,
+                channel: 0,
+            },
+        );
+        Self {
+            keys,
+            ..Self::default()
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    PolyPressure {
+        channel: u8,
+        note: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+}
+
+impl MidiMessage {
+    /// Raw 3-byte channel message.
+    pub fn to_bytes(self) -> [u8; 3] {
+        let (status, a, b, ch) = match self {
+            Self::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => (0x90, note, velocity, channel),
+            Self::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => (0x80, note, velocity, channel),
+            Self::PolyPressure {
+                channel,
+                note,
+                pressure,
+            } => (0xA0, note, pressure, channel),
+            Self::ControlChange {
+                channel,
+                controller,
+                value,
+            } => (0xB0, controller, value, channel),
+        };
+        [status | (ch & 0x0F), a & 0x7F, b & 0x7F]
+    }
+}
+
+fn to_7bit(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 127.0).round() as u8
+}
+
+#[derive(Clone, Copy, Default)]
+struct KeyState {
+    /// Time and depth when the key last left rest, for velocity.
+    stroke: Option<(f64, f32)>,
+    playing: bool,
+    /// Last aftertouch / CC value sent.
+    last: u8,
+}
+
+/// Turns analog snapshots into MIDI messages according to a [`MidiMap`].
+pub struct MidiMapper {
+    map: MidiMap,
+    keys: Box<[KeyState; 256]>,
+}
+
+impl MidiMapper {
+    pub fn new(map: MidiMap) -> Self {
+        Self {
+            map,
+            keys: Box::new([KeyState::default(); 256]),
+        }
+    }
+
+    pub fn map(&self) -> &MidiMap {
+        &self.map
+    }
+
+    /// Messages caused by the change from the previous snapshot to
+    /// `values`, taken at `t_ms`.
+    pub fn update(&mut self, values: &[f32; 256], t_ms: f64) -> Vec<MidiMessage> {
+        self.step(values, None, t_ms)
+    }
+
+    /// Like [`update`](Self::update), but note-on velocity comes from the
+    /// report-accurate peak velocity of each keystroke when there is one.
+    pub fn update_with_dynamics(
+        &mut self,
+        values: &[f32; 256],
+        dynamics: &[KeyDynamics; 256],
+        t_ms: f64,
+    ) -> Vec<MidiMessage> {
+        self.step(values, Some(dynamics), t_ms)
+    }
+
+    fn step(
+        &mut self,
+        values: &[f32; 256],
+        dynamics: Option<&[KeyDynamics; 256]>,
+        t_ms: f64,
+    ) -> Vec<MidiMessage> {
+        let mut out = Vec::new();
+        for (&sc, mapping) in &self.map.keys {
+            let v = values[sc as usize].clamp(0.0, 1.0);
+            let st = &mut self.keys[sc as usize];
+            match *mapping {
+                KeyMapping::Note {
+                    note,
+                    channel,
+                    aftertouch,
+                } => {
+                    if v <= REST {
+                        st.stroke = None;
+                    } else if st.stroke.is_none() {
+                        st.stroke = Some((t_ms, v));
+                    }
+                    if !st.playing && v >= self.map.note_on {
+                        let full = self.map.full_velocity_speed;
+                        let peak = dynamics.map_or(0.0, |d| d[sc as usize].peak_velocity);
+                        let velocity = if peak > 0.0 {
+                            // Depth per ms -> full travels per second.
+                            speed_to_velocity(peak * 1000.0, full)
+                        } else {
+                            velocity(st.stroke, v, t_ms, full)
+                        };
+                        st.playing = true;
+                        st.last = 0;
+                        out.push(MidiMessage::NoteOn {
+                            channel,
+                            note,
+                            velocity,
+                        });
+                    } else if st.playing && v < self.map.note_off {
+                        st.playing = false;
+                        out.push(MidiMessage::NoteOff {
+                            channel,
+                            note,
+                            velocity: 64,
+                        });
+                        continue;
+                    }
+                    if st.playing && aftertouch {
+                        let pressure = to_7bit(v);
+                        if pressure != st.last {
+                            st.last = pressure;
+                            out.push(MidiMessage::PolyPressure {
+                                channel,
+                                note,
+                                pressure,
+                            });
+                        }
+                    }
+                }
+                KeyMapping::Cc {
+                    controller,
+                    channel,
+                } => {
+                    let value = to_7bit(v);
+                    if value != st.last {
+                        st.last = value;
+                        out.push(MidiMessage::ControlChange {
+                            channel,
+                            controller,
+                            value,
+                        });
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Note-offs for every sounding note, e.g. before stopping.
+    pub fn release_all(&mut self) -> Vec<MidiMessage> {
+        let mut out = Vec::new();
+        for (&sc, mapping) in &self.map.keys {
+            let st = &mut self.keys[sc as usize];
+            if let (KeyMapping::Note { note, channel, .. }, true) = (mapping, st.playing) {
+                st.playing = false;
+                out.push(MidiMessage::NoteOff {
+                    channel: *channel,
+                    note: *note,
+                    velocity: 64,
+                });
+            }
+        }
+        out
+    }
+}
+
+/// Velocity from the average speed since the key left rest.
+fn velocity(stroke: Option<(f64, f32)>, v: f32, t_ms: f64, full_speed: f32) -> u8 {
+    let Some((t0, v0)) = stroke else {
+        return 127;
+    };
+    let dt = ((t_ms - t0) / 1000.0) as f32;
+    if dt <= 0.0 {
+        // Rest to threshold within one sample: as fast as we can tell.
+        return 127;
+    }
+    speed_to_velocity((v - v0) / dt, full_speed)
+}
+
+fn speed_to_velocity(speed: f32, full_speed: f32) -> u8 {
+    ((speed / full_speed) * 127.0).round().clamp(1.0, 127.0) as u8
+}
+
+/// Destination for mapped MIDI messages; `t_ms` is epoch milliseconds.
+pub trait MidiSink {
+    fn send(&mut self, t_ms: f64, msg: MidiMessage) -> io::Result<()>;
+}
+
+/// Collects messages in memory.
+impl MidiSink for Vec<(f64, MidiMessage)> {
+    fn send(&mut self, t_ms: f64, msg: MidiMessage) -> io::Result<()> {
+        self.push((t_ms, msg));
+        Ok(())
+    }
+}
+
+/// Records messages into a format 0 Standard MIDI File at 120 bpm.
+#[derive(Default)]
+pub struct SmfWriter {
+    start_ms: Option<f64>,
+    last_tick: u32,
+    track: Vec<u8>,
+}
+
+fn push_vlq(buf: &mut Vec<u8>, mut v: u32) {
+    let mut bytes = vec![(v & 0x7F) as u8];
+    v >>= 7;
+    while v > 0 {
+        bytes.push((v & 0x7F) as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.extend(bytes.iter().rev());
+}
+
+impl SmfWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn tick(&self, t_ms: f64) -> u32 {
+        let ms_per_tick = TEMPO_US as f64 / 1000.0 / TICKS_PER_QUARTER as f64;
+        let elapsed = t_ms - self.start_ms.unwrap_or(t_ms);
+        (elapsed.max(0.0) / ms_per_tick).round() as u32
+    }
+
+    /// The complete file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut track = Vec::with_capacity(self.track.len() + 11);
+        // Tempo meta event at tick 0.
+        track.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03]);
+        track.extend_from_slice(&TEMPO_US.to_be_bytes()[1..]);
+        track.extend_from_slice(&self.track);
+        track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+        let mut out = Vec::with_capacity(track.len() + 22);
+        out.extend_from_slice(b"MThd");
+        out.extend_from_slice(&6u32.to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes()); // format 0
+        out.extend_from_slice(&1u16.to_be_bytes()); // one track
+        out.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+        out.extend_from_slice(b"MTrk");
+        out.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        out.extend_from_slice(&track);
+        out
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+}
+
+impl MidiSink for SmfWriter {
+    fn send(&mut self, t_ms: f64, msg: MidiMessage) -> io::Result<()> {
+        self.start_ms.get_or_insert(t_ms);
+        let tick = self.tick(t_ms).max(self.last_tick);
+        push_vlq(&mut self.track, tick - self.last_tick);
+        self.last_tick = tick;
+        self.track.extend_from_slice(&msg.to_bytes());
+        Ok(())
+    }
+}
+
+/// Plays messages through an ALSA sequencer output port named
+/// `KB Hall:out`; connect it with `aconnect` or a patchbay.
+#[cfg(feature = "alsa")]
+pub struct AlsaSink {
+    seq: alsa::seq::Seq,
+    port: i32,
+}
+
+#[cfg(feature = "alsa")]
+impl AlsaSink {
+    pub fn new() -> io::Result<Self> {
+        use alsa::seq::{PortCap, PortType, Seq};
+        use std::ffi::CStr;
+        let err = |e: alsa::Error| io::Error::other(e.to_string());
+        // `c".."` literals need Rust 1.77.
+        let name = |s: &'static [u8]| CStr::from_bytes_with_nul(s).map_err(io::Error::other);
+        let seq = Seq::open(None, Some(alsa::Direction::Playback), false).map_err(err)?;
+        seq.set_client_name(name(b"KB Hall\0")?).map_err(err)?;
+        let port = seq
+            .create_simple_port(
+                name(b"out\0")?,
+                PortCap::READ | PortCap::SUBS_READ,
+                PortType::MIDI_GENERIC | PortType::APPLICATION,
+            )
+            .map_err(err)?;
+        Ok(Self { seq, port })
+    }
+}
+
+#[cfg(feature = "alsa")]
+impl MidiSink for AlsaSink {
+    fn send(&mut self, _t_ms: f64, msg: MidiMessage) -> io::Result<()> {
+        let err = |e: alsa::Error| io::Error::other(e.to_string());
+        // The encoder is not `Send`, so make one per message.
+        let mut encoder = alsa::seq::MidiEvent::new(16).map_err(err)?;
+        let (_, ev) = encoder.encode(&msg.to_bytes()).map_err(err)?;
+        if let Some(mut ev) = ev {
+            ev.set_source(self.port);
+            ev.set_subs();
+            ev.set_direct();
+            self.seq.event_output_direct(&mut ev).map_err(err)?;
+        }
+        Ok(())
+    }
+}
+
+/// A running mapper thread; [`MidiHandle::stop`] gives the sink back.
+pub struct MidiHandle<S> {
+    stop: Arc<AtomicBool>,
+    thread: thread::JoinHandle<S>,
+}
+
+impl<S> MidiHandle<S> {
+    /// Release held notes, stop the thread and return the sink.
+    pub fn stop(self) -> S {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().expect("MIDI thread panicked")
+    }
+}
+
+/// Map `kb` to MIDI `rate` times per second on a background thread.
+pub(crate) fn start<S: MidiSink + Send + 'static>(
+    kb: &AnalogKeyboard,
+    map: MidiMap,
+    mut sink: S,
+    rate: u32,
+) -> MidiHandle<S> {
+    let kb = kb.clone();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let interval = Duration::from_secs(1) / rate.max(1);
+    let thread = thread::spawn(move || {
+        let mut mapper = MidiMapper::new(map);
+        let mut failed = false;
+        while !stopped.load(Ordering::Relaxed) {
+            let started = Instant::now();
+            let t = now_ms();
+            for msg in mapper.update_with_dynamics(&kb.values(), &kb.all_dynamics(), t) {
+                if let Err(e) = sink.send(t, msg) {
+                    if !failed {
+                        log::warn!("[midi] send failed: {e}");
+                    }
+                    failed = true;
+                }
+            }
+            thread::sleep(interval.saturating_sub(started.elapsed()));
+        }
+        let t = now_ms();
+        for msg in mapper.release_all() {
+            let _ = sink.send(t, msg);
+        }
+        sink
+    });
+    MidiHandle { stop, thread }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_map(aftertouch: bool) -> MidiMap {
+        let mut map = MidiMap::default();
+        map.keys.insert(
+            0x04,
+            KeyMapping::Note {
+                note: 60,
+                channel: 2,
+                aftertouch,
+            },
+        );
+        map
+    }
+
+    fn at(v: f32) -> [f32; 256] {
+        let mut vals = [0.0; 256];
+        vals[0x04] = v;
+        vals
+    }
+
+    #[test]
+    fn message_bytes() {
+        let on = MidiMessage::NoteOn {
+            channel: 2,
+            note: 60,
+            velocity: 100,
+        };
+        assert_eq!(on.to_bytes(), [0x92, 60, 100]);
+        let cc = MidiMessage::ControlChange {
+            channel: 15,
+            controller: 1,
+            value: 127,
+        };
+        assert_eq!(cc.to_bytes(), [0xBF, 1, 127]);
+    }
+
+    #[test]
+    fn note_on_off_with_hysteresis() {
+        let mut m = MidiMapper::new(note_map(false));
+        assert!(m.update(&at(0.1), 0.0).is_empty());
+        let on = m.update(&at(0.5), 10.0);
+        assert!(matches!(
+            on[..],
+            [MidiMessage::NoteOn {
+                channel: 2,
+                note: 60,
+                ..
+            }]
+        ));
+        // Between note_off and note_on: still held.
+        assert!(m.update(&at(0.25), 20.0).is_empty());
+        assert!(m.update(&at(0.6), 30.0).is_empty());
+        let off = m.update(&at(0.1), 40.0);
+        assert!(matches!(off[..], [MidiMessage::NoteOff { note: 60, .. }]));
+    }
+
+    #[test]
+    fn faster_presses_are_louder() {
+        let vel = |dt: f64| {
+            let mut m = MidiMapper::new(note_map(false));
+            m.update(&at(0.05), 0.0);
+            match m.update(&at(0.4), dt)[..] {
+                [MidiMessage::NoteOn { velocity, .. }] => velocity,
+                ref other => panic!("{other:?}"),
+            }
+        };
+        let slow = vel(200.0);
+        let fast = vel(10.0);
+        assert!(fast > slow, "{fast} > {slow}");
+        assert_eq!(vel(1.0), 127);
+        assert!(slow >= 1);
+
+        // Rest to full depth between two samples.
+        let mut m = MidiMapper::new(note_map(false));
+        assert!(matches!(
+            m.update(&at(1.0), 0.0)[..],
+            [MidiMessage::NoteOn { velocity: 127, .. }]
+        ));
+    }
+
+    #[test]
+    fn velocity_from_dynamics_peak() {
+        let mut m = MidiMapper::new(note_map(false));
+        let mut dynamics = [KeyDynamics::default(); 256];
+        dynamics[0x04].peak_velocity = 0.01; // 10 travels/s, half of full speed
+        match m.update_with_dynamics(&at(0.5), &dynamics, 0.0)[..] {
+            [MidiMessage::NoteOn { velocity, .. }] => assert_eq!(velocity, 64),
+            ref other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn aftertouch_and_cc_on_change() {
+        let mut m = MidiMapper::new(note_map(true));
+        let msgs = m.update(&at(0.5), 0.0);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(
+            msgs[1],
+            MidiMessage::PolyPressure {
+                channel: 2,
+                note: 60,
+                pressure: 64
+            }
+        );
+        assert!(m.update(&at(0.5), 1.0).is_empty());
+
+        let mut map = MidiMap::default();
+        map.keys.insert(
+            0x2C,
+            KeyMapping::Cc {
+                controller: 1,
+                channel: 0,
+            },
+        );
+        let mut m = MidiMapper::new(map);
+        let mut vals = [0.0; 256];
+        assert!(m.update(&vals, 0.0).is_empty());
+        vals[0x2C] = 1.0;
+        assert_eq!(
+            m.update(&vals, 1.0),
+            vec![MidiMessage::ControlChange {
+                channel: 0,
+                controller: 1,
+                value: 127
+            }]
+        );
+    }
+
+    #[test]
+    fn map_from_json() {
+        let map = MidiMap::from_json(
+            r#"{ "noteOn": 0.4,
+                 "keys": { "4": { "type": "note", "note": 60, "aftertouch": true },
+                           "44": { "type": "cc", "controller": 1, "channel": 3 } } }"#,
+        )
+        .unwrap();
+        assert_eq!(map.note_on, 0.4);
+        assert_eq!(map.note_off, 0.2);
+        assert_eq!(
+            map.keys[&44],
+            KeyMapping::Cc {
+                controller: 1,
+                channel: 3
+            }
+        );
+        assert_eq!(MidiMap::musical_typing().keys.len(), 14);
+    }
+
+    #[test]
+    fn simultaneous_keys_in_scancode_order() {
+        let mut m = MidiMapper::new(MidiMap::musical_typing());
+        let mut vals = [0.0; 256];
+        for sc in [0x0E, 0x04, 0x1A, 0x07] {
+            vals[sc] = 1.0;
+        }
+        let notes: Vec<u8> = m
+            .update(&vals, 0.0)
+            .into_iter()
+            .filter_map(|msg| match msg {
+                MidiMessage::NoteOn { note, .. } => Some(note),
+                _ => None,
+            })
+            .collect();
+        // A (0x04), D (0x07), K (0x0E), W (0x1A).
+        assert_eq!(notes, [60, 64, 72, 61]);
+    }
+
+    #[test]
+    fn vlq() {
+        let enc = |v| {
+            let mut b = Vec::new();
+            push_vlq(&mut b, v);
+            b
+        };
+        assert_eq!(enc(0), [0x00]);
+        assert_eq!(enc(0x7F), [0x7F]);
+        assert_eq!(enc(0x80), [0x81, 0x00]);
+        assert_eq!(enc(0x0FFF_FFFF), [0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn smf_layout() {
+        let mut smf = SmfWriter::new();
+        let on = MidiMessage::NoteOn {
+            channel: 0,
+            note: 60,
+            velocity: 100,
+        };
+        let off = MidiMessage::NoteOff {
+            channel: 0,
+            note: 60,
+            velocity: 64,
+        };
+        smf.send(1000.0, on).unwrap();
+        smf.send(1500.0, off).unwrap(); // one quarter note later
+        let b = smf.to_bytes();
+        assert_eq!(&b[..14], b"MThd\0\0\0\x06\0\0\0\x01\x01\xE0");
+        assert_eq!(&b[14..18], b"MTrk");
+        let len = u32::from_be_bytes(b[18..22].try_into().unwrap()) as usize;
+        let track = &b[22..];
+        assert_eq!(track.len(), len);
+        assert_eq!(
+            track,
+            [
+                0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // tempo
+                0x00, 0x90, 60, 100, // note on
+                0x83, 0x60, 0x80, 60, 64, // 480 ticks, note off
+                0x00, 0xFF, 0x2F, 0x00, // end of track
+            ]
+        );
+    }
+
+    #[test]
+    fn runner_releases_notes_on_stop() {
+        let kb = AnalogKeyboard::new(0, 0);
+        kb.set_values(&at(1.0));
+        let handle = kb.start_midi(note_map(false), Vec::new(), 500);
+        thread::sleep(Duration::from_millis(50));
+        let msgs: Vec<_> = handle.stop().into_iter().map(|(_, m)| m).collect();
+        assert!(matches!(msgs[0], MidiMessage::NoteOn { note: 60, .. }));
+        assert!(matches!(
+            msgs.last(),
+            Some(MidiMessage::NoteOff { note: 60, .. })
+        ));
+        assert_eq!(msgs.len(), 2);
+    }
+}
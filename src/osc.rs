@@ -0,0 +1,467 @@
+//! OSC-over-UDP output of per-key depth for music and VJ software.
+//!
+//! Each key is published as `/kbhall/key/<scancode> <float 0..1>`, and the
+//! connection state as `/kbhall/active <int 0|1>`. With `bundle` set, the
+//! messages of one tick are grouped into `#bundle`s (split to stay below a
+//! typical MTU); otherwise every message is its own datagram.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::AnalogKeyboard;
+
+pub const DEFAULT_PORT: u16 = 9000;
+pub const MAX_RATE: u32 = 1000;
+/// Largest bundle we send; keeps datagrams from fragmenting on Ethernet.
+const MAX_BUNDLE: usize = 1472;
+/// Seconds between the NTP epoch (1900) and the Unix epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Where and how to send.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscConfig {
+    pub target: SocketAddr,
+    /// Ticks per second; at most one message per key per tick.
+    pub rate: u32,
+    /// Only send keys whose value changed since the previous tick.
+    pub on_change_only: bool,
+    /// Group the messages of a tick into bundles.
+    pub bundle: bool,
+    /// `None` sends every scancode.
+    pub keys: Option<Vec<u8>>,
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            target: SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)),
+            rate: 60,
+            on_change_only: true,
+            bundle: true,
+            keys: None,
+        }
+    }
+}
+
+impl OscConfig {
+    /// Config for `host:port` with the defaults otherwise.
+    pub fn to(target: impl ToSocketAddrs) -> io::Result<Self> {
+        let target = target
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))?;
+        Ok(Self {
+            target,
+            ..Self::default()
+        })
+    }
+
+    /// Parse `HOST:PORT[,rate=N][,changes]` as given to `--osc`. Without
+    /// `changes` every tick carries every key, so a listener that joins
+    /// late or drops a datagram catches up on the next one.
+    pub fn parse(spec: &str) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let mut parts = spec.split(',');
+        let mut config = Self::to(parts.next().unwrap_or_default())?;
+        config.on_change_only = false;
+        for opt in parts {
+            match opt.split_once('=') {
+                Some(("rate", n)) => {
+                    config.rate = n
+                        .parse()
+                        .ok()
+                        .filter(|r| (1..=MAX_RATE).contains(r))
+                        .ok_or_else(|| invalid(format!("rate must be 1..={MAX_RATE}, got {n}")))?;
+                }
+                None if opt == "changes" => config.on_change_only = true,
+                _ => return Err(invalid(format!("unknown option {opt:?}"))),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// An OSC argument; only the types we send.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+}
+
+/// Append `s` NUL-terminated and padded to a multiple of 4 bytes.
+fn push_padded(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.extend_from_slice(s);
+    buf.push(0);
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+/// Encode one OSC message.
+pub fn encode_message(address: &str, args: &[OscArg]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(32);
+    push_padded(&mut buf, address.as_bytes());
+    let tags: String = std::iter::once(',')
+        .chain(args.iter().map(|a| match a {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+        }))
+        .collect();
+    push_padded(&mut buf, tags.as_bytes());
+    for a in args {
+        match a {
+            OscArg::Int(i) => buf.extend_from_slice(&i.to_be_bytes()),
+            OscArg::Float(f) => buf.extend_from_slice(&f.to_be_bytes()),
+        }
+    }
+    buf
+}
+
+/// Encode a bundle of already encoded messages with an NTP time tag.
+pub fn encode_bundle(timetag: u64, messages: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16 + messages.iter().map(|m| m.len() + 4).sum::<usize>());
+    push_padded(&mut buf, b"#bundle");
+    buf.extend_from_slice(&timetag.to_be_bytes());
+    for m in messages {
+        buf.extend_from_slice(&(m.len() as u32).to_be_bytes());
+        buf.extend_from_slice(m);
+    }
+    buf
+}
+
+/// Current time as an OSC (NTP) time tag: seconds.fraction since 1900.
+fn timetag_now() -> u64 {
+    let d = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = d.as_secs() + NTP_UNIX_OFFSET;
+    let frac = ((d.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (secs << 32) | frac
+}
+
+/// Turns successive snapshots into the messages to send.
+struct Encoder {
+    config: OscConfig,
+    last: [Option<f32>; 256],
+    active: Option<bool>,
+}
+
+impl Encoder {
+    fn new(config: OscConfig) -> Self {
+        Self {
+            config,
+            last: [None; 256],
+            active: None,
+        }
+    }
+
+    fn messages(&mut self, values: &[f32; 256], active: bool) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        if !self.config.on_change_only || self.active != Some(active) {
+            out.push(encode_message(
+                "/kbhall/active",
+                &[OscArg::Int(active as i32)],
+            ));
+            self.active = Some(active);
+        }
+        for (sc, &v) in values.iter().enumerate() {
+            if let Some(keys) = &self.config.keys {
+                if !keys.contains(&(sc as u8)) {
+                    continue;
+                }
+            }
+            let v = v.clamp(0.0, 1.0);
+            if self.config.on_change_only && self.last[sc] == Some(v) {
+                continue;
+            }
+            // Untouched keys stay quiet until they first move.
+            if self.config.on_change_only && self.last[sc].is_none() && v == 0.0 {
+                self.last[sc] = Some(v);
+                continue;
+            }
+            self.last[sc] = Some(v);
+            out.push(encode_message(
+                &format!("/kbhall/key/{sc}"),
+                &[OscArg::Float(v)],
+            ));
+        }
+        out
+    }
+
+    /// Datagrams for one tick: single messages, or bundles below `MAX_BUNDLE`.
+    fn packets(&mut self, values: &[f32; 256], active: bool) -> Vec<Vec<u8>> {
+        let messages = self.messages(values, active);
+        if !self.config.bundle || messages.is_empty() {
+            return messages;
+        }
+        let timetag = timetag_now();
+        let mut packets = Vec::new();
+        let mut chunk: Vec<Vec<u8>> = Vec::new();
+        let mut size = 16;
+        for m in messages {
+            if !chunk.is_empty() && size + 4 + m.len() > MAX_BUNDLE {
+                packets.push(encode_bundle(timetag, &chunk));
+                chunk.clear();
+                size = 16;
+            }
+            size += 4 + m.len();
+            chunk.push(m);
+        }
+        packets.push(encode_bundle(timetag, &chunk));
+        packets
+    }
+}
+
+/// A running OSC sender.
+pub struct OscHandle {
+    local: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl OscHandle {
+    /// Local address of the sending socket.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+
+    /// Stop sending and wait for the thread to finish.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().expect("OSC thread panicked");
+    }
+}
+
+/// Start sending `kb` to `config.target` in the background.
+pub(crate) fn start(kb: &AnalogKeyboard, config: OscConfig) -> io::Result<OscHandle> {
+    if config.rate == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "rate must be > 0",
+        ));
+    }
+    let bind: SocketAddr = if config.target.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.connect(config.target)?;
+    let local = socket.local_addr()?;
+    log::info!("[osc] sending to {}", config.target);
+
+    let kb = kb.clone();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let thread = thread::spawn(move || {
+        let interval = Duration::from_secs(1) / config.rate;
+        let mut encoder = Encoder::new(config);
+        while !stopped.load(Ordering::Relaxed) {
+            let started = Instant::now();
+            for p in encoder.packets(&kb.values(), kb.is_active()) {
+                // Nobody listening (ICMP refused) is normal for UDP; keep going.
+                let _ = socket.send(&p);
+            }
+            thread::sleep(interval.saturating_sub(started.elapsed()));
+        }
+    });
+    Ok(OscHandle {
+        local,
+        stop,
+        thread,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal decoder for what `encode_message` produces.
+    fn decode_message(b: &[u8]) -> (String, Vec<OscArg>) {
+        let str_at = |off: usize| {
+            let end = off + b[off..].iter().position(|&c| c == 0).unwrap();
+            (
+                String::from_utf8(b[off..end].to_vec()).unwrap(),
+                (end + 4) & !3,
+            )
+        };
+        let (addr, off) = str_at(0);
+        let (tags, mut off) = str_at(off);
+        let args = tags[1..]
+            .chars()
+            .map(|t| {
+                let raw: [u8; 4] = b[off..off + 4].try_into().unwrap();
+                off += 4;
+                match t {
+                    'i' => OscArg::Int(i32::from_be_bytes(raw)),
+                    'f' => OscArg::Float(f32::from_be_bytes(raw)),
+                    _ => panic!("tag {t}"),
+                }
+            })
+            .collect();
+        (addr, args)
+    }
+
+    fn decode_bundle(b: &[u8]) -> Vec<(String, Vec<OscArg>)> {
+        assert_eq!(&b[..8], b"#bundle\0");
+        let mut off = 16;
+        let mut out = Vec::new();
+        while off < b.len() {
+            let len = u32::from_be_bytes(b[off..off + 4].try_into().unwrap()) as usize;
+            out.push(decode_message(&b[off + 4..off + 4 + len]));
+            off += 4 + len;
+        }
+        out
+    }
+
+    #[test]
+    fn message_encoding() {
+        let m = encode_message("/kbhall/key/4", &[OscArg::Float(0.5)]);
+        // "/kbhall/key/4\0\0\0" ",f\0\0" 0.5
+        assert_eq!(m.len(), 16 + 4 + 4);
+        assert_eq!(&m[16..20], b",f\0\0");
+        assert_eq!(&m[20..], &0.5f32.to_be_bytes());
+        assert_eq!(
+            decode_message(&m),
+            ("/kbhall/key/4".into(), vec![OscArg::Float(0.5)])
+        );
+
+        // Address length a multiple of 4 still gets a full NUL word.
+        let m = encode_message("/abc", &[]);
+        assert_eq!(m, b"/abc\0\0\0\0,\0\0\0");
+    }
+
+    #[test]
+    fn timetag_is_ntp() {
+        let secs = timetag_now() >> 32;
+        assert!(secs > NTP_UNIX_OFFSET + 1_600_000_000);
+    }
+
+    #[test]
+    fn change_only_and_filters() {
+        let mut enc = Encoder::new(OscConfig {
+            bundle: false,
+            keys: Some(vec![4, 5]),
+            ..OscConfig::default()
+        });
+        let mut vals = [0.0f32; 256];
+        let first = enc.messages(&vals, false);
+        assert_eq!(first.len(), 1, "only /kbhall/active until keys move");
+        assert!(enc.messages(&vals, false).is_empty());
+
+        vals[4] = 0.25;
+        vals[6] = 1.0; // filtered out
+        let msgs: Vec<_> = enc
+            .messages(&vals, false)
+            .iter()
+            .map(|m| decode_message(m))
+            .collect();
+        assert_eq!(
+            msgs,
+            vec![("/kbhall/key/4".into(), vec![OscArg::Float(0.25)])]
+        );
+
+        vals[4] = 0.0;
+        let msgs: Vec<_> = enc
+            .messages(&vals, true)
+            .iter()
+            .map(|m| decode_message(m))
+            .collect();
+        assert_eq!(
+            msgs,
+            vec![
+                ("/kbhall/active".into(), vec![OscArg::Int(1)]),
+                ("/kbhall/key/4".into(), vec![OscArg::Float(0.0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_tick_without_change_only() {
+        let mut enc = Encoder::new(OscConfig {
+            on_change_only: false,
+            bundle: false,
+            keys: Some(vec![4]),
+            ..OscConfig::default()
+        });
+        let vals = [0.0f32; 256];
+        assert_eq!(enc.messages(&vals, true).len(), 2);
+        assert_eq!(enc.messages(&vals, true).len(), 2);
+    }
+
+    #[test]
+    fn bundles_split_below_mtu() {
+        let mut enc = Encoder::new(OscConfig::default());
+        let vals = [0.5f32; 256];
+        let packets = enc.packets(&vals, true);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| p.len() <= MAX_BUNDLE));
+        let msgs: Vec<_> = packets.iter().flat_map(|p| decode_bundle(p)).collect();
+        assert_eq!(msgs.len(), 257);
+        assert_eq!(msgs[0].0, "/kbhall/active");
+        assert_eq!(
+            msgs[256],
+            ("/kbhall/key/255".into(), vec![OscArg::Float(0.5)])
+        );
+    }
+
+    #[test]
+    fn sends_to_udp_listener() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let kb = AnalogKeyboard::new(0, 0);
+        let mut vals = [0.0f32; 256];
+        vals[0x2C] = 1.0;
+        kb.set_values(&vals);
+
+        let config = OscConfig {
+            rate: 200,
+            keys: Some(vec![0x2C]),
+            ..OscConfig::to(listener.local_addr().unwrap()).unwrap()
+        };
+        let handle = kb.start_osc(config).unwrap();
+
+        let mut buf = [0u8; 2048];
+        let n = listener.recv(&mut buf).unwrap();
+        let msgs = decode_bundle(&buf[..n]);
+        assert!(msgs.contains(&("/kbhall/key/44".into(), vec![OscArg::Float(1.0)])));
+        assert!(msgs.contains(&("/kbhall/active".into(), vec![OscArg::Int(0)])));
+
+        // Nothing changes, so nothing more arrives.
+        listener
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(listener.recv(&mut buf).is_err());
+
+        // Stopped: even a change is not sent.
+        handle.stop();
+        vals[0x2C] = 0.5;
+        kb.set_values(&vals);
+        assert!(listener.recv(&mut buf).is_err());
+    }
+
+    #[test]
+    fn parse_cli_spec() {
+        let c = OscConfig::parse("127.0.0.1:9001,rate=120,changes").unwrap();
+        assert_eq!(c.target, SocketAddr::from(([127, 0, 0, 1], 9001)));
+        assert_eq!((c.rate, c.on_change_only, c.bundle), (120, true, true));
+
+        let c = OscConfig::parse("127.0.0.1:9001").unwrap();
+        assert_eq!((c.rate, c.on_change_only), (60, false));
+
+        for bad in [
+            "127.0.0.1:9001,rate=0",
+            "127.0.0.1:9001,rate=x",
+            "127.0.0.1:9001,fast",
+            "nohost",
+        ] {
+            assert!(OscConfig::parse(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Transparent keyboard overlay for OBS browser sources.
+//
+// Query parameters:
+//   layout=60        built-in layout (default: the one the app shows)
+//   theme=default    built-in theme (default, viridis, magma, turbo, colorblind, light)
+//   caps=0           hide the key caps, only the fills float over the stream
+//   labels=0         hide key labels
+//   scale=1.5        size multiplier
+//
+// Key rectangles and colours come from /overlay.json (see src/http.rs), the
+// values from the broadcast WebSocket on the same host and port (see
+// src/broadcast.rs); frames carry only the keys that changed.
+
+const params = new URLSearchParams(location.search);
+const SCALE = parseFloat(params.get("scale")) || 1;
+const CAPS = params.get("caps") !== "0";
+const LABELS = params.get("labels") !== "0";
+
+const css = ([r, g, b], a = 1) =>
+  `rgba(${Math.round(r * 255)},${Math.round(g * 255)},${Math.round(b * 255)},${a})`;
+const mix = (a, b, t) => a.map((x, i) => x + (b[i] - x) * t);
+
+// Colour at v of evenly spaced stops, as theme::ColorMap::sample.
+function sample(stops, v) {
+  const last = stops.length - 1;
+  const x = v * last;
+  const i = Math.min(Math.floor(x), Math.max(last - 1, 0));
+  return i + 1 <= last ? mix(stops[i], stops[i + 1], x - i) : stops[i];
+}
+
+const keys = new Map(); // scancode -> { parts: [{ cap, fill, y, h, lo }], pct, travel }
+const values = new Float32Array(256);
+let board; // /overlay.json
+
+async function build() {
+  const query = new URLSearchParams();
+  for (const p of ["layout", "theme"]) if (params.has(p)) query.set(p, params.get(p));
+  const resp = await fetch(`/overlay.json?${query}`);
+  if (!resp.ok) {
+    document.body.textContent = await resp.text();
+    return;
+  }
+  board = await resp.json();
+  const kb = document.getElementById("kb");
+  for (const { sc, label, x, y, w, h, rect2, r, rx, ry } of board.keys) {
+    // Parts are placed unrotated in a group turned about the key's pivot.
+    const group = document.createElement("div");
+    group.className = "key";
+    if (r) {
+      group.style.transformOrigin = `${rx}px ${ry}px`;
+      group.style.transform = `rotate(${r}deg)`;
+    }
+    kb.appendChild(group);
+    const el = (cls, style) => {
+      const d = document.createElement("div");
+      d.className = cls;
+      Object.assign(d.style, style);
+      group.appendChild(d);
+      return d;
+    };
+    const rects = [{ x, y, w, h }];
+    if (rect2) {
+      const [dx, dy, w2, h2] = rect2;
+      rects.push({ x: x + dx, y: y + dy, w: w2, h: h2 });
+    }
+    // The fill rises from the lowest edge of the key to its highest, as in
+    // the visualizer: each part shows the slice of travel it covers.
+    const bottom = Math.max(...rects.map((p) => p.y + p.h));
+    const top = Math.min(...rects.map((p) => p.y));
+    const parts = rects.map((p) => ({
+      cap: el("cap", { left: `${p.x}px`, top: `${p.y}px`, width: `${p.w}px`, height: `${p.h}px` }),
+      fill: el("fill", { left: `${p.x + 2}px`, width: `${p.w - 4}px` }),
+      y: p.y,
+      h: p.h,
+      lo: bottom - (p.y + p.h),
+    }));
+    if (LABELS) {
+      el("lbl", {
+        left: `${x}px`,
+        top: `${y + h / 2 - 13}px`,
+        width: `${w}px`,
+        fontSize: w > 71 ? "10px" : "13px", // wider than 1.5 units
+        color: css(board.label),
+      }).textContent = label;
+    }
+    const pct = el("pct", { left: `${x}px`, top: `${y + h / 2 + 3}px`, width: `${w}px`, fontSize: "8px" });
+    keys.set(sc, { parts, pct, travel: bottom - top - 4 });
+  }
+  kb.style.width = `${board.width}px`;
+  kb.style.height = `${board.height}px`;
+  kb.style.transform = `scale(${SCALE})`;
+  for (const sc of keys.keys()) render(sc);
+  connect();
+}
+
+function render(sc) {
+  const k = keys.get(sc);
+  if (!k) return;
+  const v = Math.min(1, Math.max(0, values[sc]));
+  for (const p of k.parts) {
+    const h = Math.min(Math.max(v * k.travel - p.lo, 0), p.h - 4);
+    p.cap.style.background = CAPS ? css(mix(board.cap, board.capPressed, v)) : "transparent";
+    p.fill.style.background = css(sample(board.fill, v));
+    p.fill.style.height = `${h}px`;
+    p.fill.style.top = `${p.y + p.h - 2 - h}px`;
+  }
+  k.pct.textContent = v > 0.01 ? `${Math.round(v * 100)}%` : "";
+  k.pct.style.color = css(board.value, Math.min(v * 2, 0.9));
+}
+
+function connect() {
+  const ws = new WebSocket(`ws://${location.host}/?format=json&rate=60`);
+  ws.onmessage = (e) => {
+    const frame = JSON.parse(e.data);
+    if (!frame.keys) return;
+    for (const [sc, v] of Object.entries(frame.keys)) {
+      values[sc] = v;
+      render(Number(sc));
+    }
+  };
+  ws.onclose = () => {
+    // A new subscription starts from zero.
+    values.fill(0);
+    for (const sc of keys.keys()) render(sc);
+    setTimeout(connect, 1000);
+  };
+}
+
+build();
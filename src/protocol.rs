@@ -0,0 +1,325 @@
+//! Wire protocol between `bridge.html` and the Rust WebSocket server.
+//!
+//! Control messages are JSON text frames tagged by `type`. Raw bytes (input
+//! and output reports, clock sync) use binary frames:
+//!
+//! ```text
+//! byte 0     message type (MSG_REPORT / MSG_SYNC / MSG_SEND_REPORT)
+//! byte 1     protocol version
+//! byte 2..   payload
+//! ```
+//!
+//! | dir  | frame  | message                                                |
+//! |------|--------|--------------------------------------------------------|
+//! | page | text   | `hello { version, device? }` - must be the first frame |
+//! | rust | text   | `welcome { version }` or `error { message }` + close   |
+//! | page | binary | `0x03 v hid_ts:f64 report..` - raw HID input report    |
+//! | rust | binary | `0x04 v t0:f64` - clock sync request                   |
+//! | page | binary | `0x04 v t0:f64 t1:f64` - sync reply with page clock    |
+//! | rust | binary | `0x05 v id:u32 report_id data..` - output report       |
+//! | page | text   | `ack { id, error? }` - `sendReport` done or failed     |
+//! | page | text   | `device { event, device }` - opened / disconnected     |
+//! | page | text   | `error { message }` - WebHID failure on the page       |
+//! | both | text   | `heartbeat { t }` - sent every second while connected  |
+//!
+//! Timestamps are epoch milliseconds on the sender's clock; all `f64` and
+//! `u32` values are little-endian.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tungstenite::Message as WsMessage;
+
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Binary input report: `[0x03, v, hid_ts]` followed by the raw HID report.
+pub const MSG_REPORT: u8 = 0x03;
+/// Binary clock sync: request `[0x04, v, t0]`, reply `[0x04, v, t0, t1]`.
+pub const MSG_SYNC: u8 = 0x04;
+/// Binary output report for `HIDDevice.sendReport`: `[0x05, v, id, report_id, data..]`.
+pub const MSG_SEND_REPORT: u8 = 0x05;
+/// Type + version + one timestamp.
+pub const HEADER_LEN: usize = 10;
+
+/// One HID top-level collection as reported by WebHID.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HidCollection {
+    pub usage_page: u16,
+    pub usage: u16,
+    #[serde(default)]
+    pub input_reports: Vec<u8>,
+    #[serde(default)]
+    pub output_reports: Vec<u8>,
+    #[serde(default)]
+    pub feature_reports: Vec<u8>,
+}
+
+/// Device details sent by the page once the keyboard is opened.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub product_name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    #[serde(default)]
+    pub collections: Vec<HidCollection>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceEvent {
+    Opened,
+    Disconnected,
+}
+
+/// JSON control messages, in both directions.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Control {
+    Hello {
+        version: u8,
+        #[serde(default)]
+        device: Option<DeviceInfo>,
+    },
+    Welcome {
+        version: u8,
+    },
+    Device {
+        event: DeviceEvent,
+        device: DeviceInfo,
+    },
+    Error {
+        message: String,
+    },
+    Heartbeat {
+        t: f64,
+    },
+    /// Result of an output report; `error` is set when `sendReport` failed.
+    Ack {
+        id: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// A decoded frame from the bridge page.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Incoming {
+    Control(Control),
+    Report { hid_ts: f64, data: Vec<u8> },
+    SyncReply { t0: f64, t1: f64 },
+    Close,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    /// Binary frame for a different protocol version.
+    Version(u8),
+    /// Binary frame too short for its type.
+    Truncated(u8),
+    UnknownType(u8),
+    Json(String),
+    /// Ping/pong and other frames that carry no protocol message.
+    Ignored,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Version(v) => write!(f, "unsupported protocol version {v}"),
+            Self::Truncated(t) => write!(f, "truncated frame 0x{t:02X}"),
+            Self::UnknownType(t) => write!(f, "unknown frame type 0x{t:02X}"),
+            Self::Json(e) => write!(f, "bad control message: {e}"),
+            Self::Ignored => write!(f, "ignored frame"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+fn read_f64(data: &[u8], at: usize) -> Option<f64> {
+    let bytes = data.get(at..at + 8)?;
+    Some(f64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Decode one WebSocket frame sent by the page.
+pub fn decode(msg: &WsMessage) -> Result<Incoming, ProtocolError> {
+    match msg {
+        WsMessage::Text(text) => serde_json::from_str(text)
+            .map(Incoming::Control)
+            .map_err(|e| ProtocolError::Json(e.to_string())),
+        WsMessage::Binary(data) => decode_binary(data),
+        WsMessage::Close(_) => Ok(Incoming::Close),
+        _ => Err(ProtocolError::Ignored),
+    }
+}
+
+fn decode_binary(data: &[u8]) -> Result<Incoming, ProtocolError> {
+    let (&ty, &version) = match data {
+        [ty, version, ..] => (ty, version),
+        [ty] => return Err(ProtocolError::Truncated(*ty)),
+        [] => return Err(ProtocolError::Truncated(0)),
+    };
+    if version != PROTOCOL_VERSION {
+        return Err(ProtocolError::Version(version));
+    }
+    match ty {
+        MSG_REPORT => {
+            let hid_ts = read_f64(data, 2).ok_or(ProtocolError::Truncated(ty))?;
+            Ok(Incoming::Report {
+                hid_ts,
+                data: data[HEADER_LEN..].to_vec(),
+            })
+        }
+        MSG_SYNC => match (read_f64(data, 2), read_f64(data, 10)) {
+            (Some(t0), Some(t1)) => Ok(Incoming::SyncReply { t0, t1 }),
+            _ => Err(ProtocolError::Truncated(ty)),
+        },
+        other => Err(ProtocolError::UnknownType(other)),
+    }
+}
+
+/// Encode a control message as a JSON text frame.
+pub fn encode_control(msg: &Control) -> WsMessage {
+    WsMessage::Text(serde_json::to_string(msg).unwrap_or_default())
+}
+
+/// Encode an input report frame (what the page sends; used by tests and tools).
+pub fn encode_report(hid_ts: f64, report: &[u8]) -> WsMessage {
+    let mut m = vec![MSG_REPORT, PROTOCOL_VERSION];
+    m.extend_from_slice(&hid_ts.to_le_bytes());
+    m.extend_from_slice(report);
+    WsMessage::Binary(m)
+}
+
+pub fn encode_sync_request(t0: f64) -> WsMessage {
+    let mut m = vec![MSG_SYNC, PROTOCOL_VERSION];
+    m.extend_from_slice(&t0.to_le_bytes());
+    WsMessage::Binary(m)
+}
+
+/// Encode an output report for the page to pass to `sendReport`.
+pub fn encode_send_report(id: u32, report_id: u8, data: &[u8]) -> WsMessage {
+    let mut m = vec![MSG_SEND_REPORT, PROTOCOL_VERSION];
+    m.extend_from_slice(&id.to_le_bytes());
+    m.push(report_id);
+    m.extend_from_slice(data);
+    WsMessage::Binary(m)
+}
+
+/// Decode an output report frame into `(id, report_id, data)`.
+pub fn decode_send_report(frame: &[u8]) -> Option<(u32, u8, Vec<u8>)> {
+    if frame.len() < 7 || frame[0] != MSG_SEND_REPORT || frame[1] != PROTOCOL_VERSION {
+        return None;
+    }
+    let id = u32::from_le_bytes(frame[2..6].try_into().ok()?);
+    Some((id, frame[6], frame[7..].to_vec()))
+}
+
+/// Encode a sync reply (what the page sends in answer to a request).
+pub fn encode_sync_reply(t0: f64, t1: f64) -> WsMessage {
+    let mut m = vec![MSG_SYNC, PROTOCOL_VERSION];
+    m.extend_from_slice(&t0.to_le_bytes());
+    m.extend_from_slice(&t1.to_le_bytes());
+    WsMessage::Binary(m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_roundtrip() {
+        let msg = encode_report(12.5, &[0xA0, 0, 0, 0x04, 0x03, 0x00]);
+        assert_eq!(
+            decode(&msg),
+            Ok(Incoming::Report {
+                hid_ts: 12.5,
+                data: vec![0xA0, 0, 0, 0x04, 0x03, 0x00],
+            })
+        );
+    }
+
+    #[test]
+    fn sync_reply_roundtrip() {
+        let msg = encode_sync_reply(1.0, 2.0);
+        assert_eq!(decode(&msg), Ok(Incoming::SyncReply { t0: 1.0, t1: 2.0 }));
+        // a bare request is missing t1
+        assert_eq!(
+            decode(&encode_sync_request(1.0)),
+            Err(ProtocolError::Truncated(MSG_SYNC))
+        );
+    }
+
+    #[test]
+    fn send_report_roundtrip() {
+        let WsMessage::Binary(frame) = encode_send_report(7, 0, &[0x55, 0xA8]) else {
+            panic!("expected binary frame");
+        };
+        assert_eq!(frame[..7], [MSG_SEND_REPORT, PROTOCOL_VERSION, 7, 0, 0, 0, 0]);
+        assert_eq!(decode_send_report(&frame), Some((7, 0, vec![0x55, 0xA8])));
+        assert_eq!(decode_send_report(&frame[..5]), None);
+    }
+
+    #[test]
+    fn ack_json() {
+        let ok = r#"{"type":"ack","id":3}"#;
+        let failed = r#"{"type":"ack","id":4,"error":"NotAllowedError"}"#;
+        assert_eq!(
+            decode(&WsMessage::Text(ok.into())),
+            Ok(Incoming::Control(Control::Ack { id: 3, error: None }))
+        );
+        assert_eq!(
+            decode(&WsMessage::Text(failed.into())),
+            Ok(Incoming::Control(Control::Ack {
+                id: 4,
+                error: Some("NotAllowedError".into())
+            }))
+        );
+    }
+
+    #[test]
+    fn binary_version_mismatch() {
+        let msg = WsMessage::Binary(vec![MSG_REPORT, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xA0]);
+        assert_eq!(decode(&msg), Err(ProtocolError::Version(0)));
+    }
+
+    #[test]
+    fn unknown_binary_type() {
+        let msg = WsMessage::Binary(vec![0x7F, PROTOCOL_VERSION]);
+        assert_eq!(decode(&msg), Err(ProtocolError::UnknownType(0x7F)));
+    }
+
+    #[test]
+    fn hello_json_from_page() {
+        let text = r#"{"type":"hello","version":1,"device":{"productName":"Ace 60 Pro",
+            "vendorId":16868,"productId":8451,
+            "collections":[{"usagePage":65280,"usage":1,"outputReports":[0]}]}}"#;
+        let Ok(Incoming::Control(Control::Hello { version, device })) =
+            decode(&WsMessage::Text(text.into()))
+        else {
+            panic!("expected hello");
+        };
+        assert_eq!(version, 1);
+        let device = device.unwrap();
+        assert_eq!(device.product_name, "Ace 60 Pro");
+        assert_eq!(device.vendor_id, 0x41E4);
+        assert_eq!(device.collections[0].usage_page, 0xFF00);
+        assert_eq!(device.collections[0].output_reports, vec![0]);
+        assert!(device.collections[0].input_reports.is_empty());
+    }
+
+    #[test]
+    fn control_encoding_is_tagged() {
+        let WsMessage::Text(t) = encode_control(&Control::Welcome { version: 1 }) else {
+            panic!("expected text frame");
+        };
+        assert_eq!(t, r#"{"type":"welcome","version":1}"#);
+    }
+
+    #[test]
+    fn bad_json_is_an_error() {
+        let r = decode(&WsMessage::Text("{\"type\":\"nope\"}".into()));
+        assert!(matches!(r, Err(ProtocolError::Json(_))));
+    }
+}
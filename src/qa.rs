@@ -0,0 +1,441 @@
+//! Incoming-inspection QA: press every key of a layout once, fully and
+//! slowly, and get a pass/fail report per key.
+//!
+//! Fed the raw sensor reading of every analog report (before the deadzone
+//! and scaling of `values()`) with its page timestamp. Per key it measures:
+//!
+//! - max raw: the deepest reading, which must reach
+//!   [`Tolerances::min_max_raw`];
+//! - rest noise: standard deviation of the readings while the key is idle,
+//!   at rest (depth up to [`REST`]) and at least [`IDLE_GUARD_MS`] from any
+//!   report above rest, so the edges of a press are not counted;
+//! - travel linearity: the widest span of raw values between rest and the
+//!   deepest reading that no downstroke ever read. A sensor with a dead
+//!   spot jumps over the same span on every press; how fast the operator
+//!   presses only changes how many readings land elsewhere, and every press
+//!   adds to the coverage.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::{self, Write as _};
+use std::io;
+use std::path::Path;
+
+use crate::dynamics::REST;
+use crate::layout::Layout;
+use crate::{raw_depth, ANALOG_DEADZONE, ANALOG_MAX};
+
+/// Rest readings this close to a press are the sensor on its way into or
+/// out of it, not noise.
+pub const IDLE_GUARD_MS: f64 = 100.0;
+
+/// Limits a key must stay within to pass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerances {
+    /// Lowest acceptable max raw reading of a full press.
+    pub min_max_raw: u16,
+    /// Highest acceptable rest noise, in raw units.
+    pub max_noise: f64,
+    /// Widest acceptable span of raw values the downstrokes never read.
+    pub max_gap: u16,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Self {
+            // 90% of full scale
+            min_max_raw: ANALOG_DEADZONE + (ANALOG_MAX * 0.9) as u16,
+            max_noise: 4.0,
+            // 10% of full scale
+            max_gap: (ANALOG_MAX * 0.1) as u16,
+        }
+    }
+}
+
+/// What was measured on one key.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyQa {
+    pub reports: u64,
+    pub max_raw: u16,
+    /// Rest noise; `None` until the key has reported while idle twice.
+    pub noise: Option<f64>,
+    /// Widest raw span between rest and the deepest downstroke reading that
+    /// no downstroke read; `None` until the key went past rest.
+    pub gap: Option<u16>,
+}
+
+/// Why a key failed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// The key never reported.
+    NoReports,
+    /// The deepest reading stayed below the tolerance.
+    Shallow(u16),
+    Noisy(f64),
+    /// A span of travel the sensor never reported: a dead spot.
+    Gap(u16),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::NoReports => write!(f, "not pressed"),
+            Fault::Shallow(raw) => write!(f, "max raw {raw} too low"),
+            Fault::Noisy(n) => write!(f, "rest noise {n:.1} too high"),
+            Fault::Gap(g) => write!(f, "travel gap of {g} raw too wide"),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Track {
+    qa: KeyQa,
+    rest_n: u64,
+    rest_sum: f64,
+    rest_sq: f64,
+    /// Time of the latest report above rest.
+    last_active: Option<f64>,
+    /// Rest readings a press could still follow within `IDLE_GUARD_MS`.
+    idle: VecDeque<(f64, u16)>,
+    /// Distinct readings above rest taken on the way down.
+    downstroke: BTreeSet<u16>,
+    prev_raw: u16,
+}
+
+impl Track {
+    fn record(&mut self, raw: u16, t_ms: f64) {
+        self.qa.reports += 1;
+        self.qa.max_raw = self.qa.max_raw.max(raw);
+        let prev = std::mem::replace(&mut self.prev_raw, raw);
+        if raw_depth(raw) > REST {
+            if raw > prev {
+                self.downstroke.insert(raw);
+                self.qa.gap = Some(self.gap());
+            }
+            // Readings from before the guard were idle; the rest led into
+            // this press.
+            self.count_idle(t_ms);
+            self.idle.clear();
+            self.last_active = Some(t_ms);
+        } else if self.last_active.is_none_or(|a| t_ms - a >= IDLE_GUARD_MS) {
+            self.idle.push_back((t_ms, raw));
+            self.count_idle(t_ms);
+        }
+    }
+
+    /// Widest span between consecutive downstroke readings, starting at
+    /// rest.
+    fn gap(&self) -> u16 {
+        let rest = ANALOG_DEADZONE + (REST * ANALOG_MAX) as u16;
+        let mut prev = rest;
+        let mut widest = 0;
+        for &raw in &self.downstroke {
+            widest = widest.max(raw.saturating_sub(prev));
+            prev = raw;
+        }
+        widest
+    }
+
+    /// Add the readings at least `IDLE_GUARD_MS` before `now` to the noise.
+    fn count_idle(&mut self, now: f64) {
+        while let Some(&(t, raw)) = self.idle.front() {
+            if now - t < IDLE_GUARD_MS {
+                break;
+            }
+            self.idle.pop_front();
+            let r = raw as f64;
+            self.rest_n += 1;
+            self.rest_sum += r;
+            self.rest_sq += r * r;
+        }
+        if self.rest_n >= 2 {
+            let mean = self.rest_sum / self.rest_n as f64;
+            let var = self.rest_sq / self.rest_n as f64 - mean * mean;
+            self.qa.noise = Some(var.max(0.0).sqrt());
+        }
+    }
+}
+
+/// A QA run over the keys of one layout.
+pub struct QaSession {
+    layout: Layout,
+    tolerances: Tolerances,
+    keys: Vec<Track>,
+}
+
+impl QaSession {
+    pub fn new(layout: &Layout, tolerances: Tolerances) -> Self {
+        Self {
+            layout: layout.clone(),
+            tolerances,
+            keys: vec![Track::default(); 256],
+        }
+    }
+
+    /// Feed one analog report: `scancode` read `raw` at time `t_ms`.
+    pub fn record(&mut self, scancode: u8, raw: u16, t_ms: f64) {
+        self.keys[scancode as usize].record(raw, t_ms);
+    }
+
+    pub fn key(&self, scancode: u8) -> KeyQa {
+        self.keys[scancode as usize].qa
+    }
+
+    /// Layout keys pressed to full travel so far, and how many it has.
+    pub fn coverage(&self) -> (usize, usize) {
+        let done = self
+            .layout
+            .scancodes()
+            .filter(|&sc| self.key(sc).max_raw >= self.tolerances.min_max_raw)
+            .count();
+        (done, self.layout.keys.len())
+    }
+
+    /// Results so far for every layout key, in layout order.
+    pub fn report(&self) -> QaReport {
+        let tol = self.tolerances;
+        let keys = self
+            .layout
+            .keys
+            .iter()
+            .map(|key| {
+                let qa = self.key(key.scancode);
+                let mut faults = Vec::new();
+                if qa.reports == 0 {
+                    faults.push(Fault::NoReports);
+                } else if qa.max_raw < tol.min_max_raw {
+                    faults.push(Fault::Shallow(qa.max_raw));
+                }
+                if let Some(n) = qa.noise.filter(|&n| n > tol.max_noise) {
+                    faults.push(Fault::Noisy(n));
+                }
+                if let Some(g) = qa.gap.filter(|&g| g > tol.max_gap) {
+                    faults.push(Fault::Gap(g));
+                }
+                KeyResult {
+                    scancode: key.scancode,
+                    label: key.label.clone(),
+                    qa,
+                    faults,
+                }
+            })
+            .collect();
+        QaReport {
+            tolerances: tol,
+            keys,
+        }
+    }
+}
+
+/// One key's line of the report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyResult {
+    pub scancode: u8,
+    pub label: String,
+    pub qa: KeyQa,
+    /// Empty when the key passed.
+    pub faults: Vec<Fault>,
+}
+
+impl KeyResult {
+    pub fn passed(&self) -> bool {
+        self.faults.is_empty()
+    }
+
+    /// Still waiting for a full press, with nothing else wrong so far.
+    pub fn pending(&self) -> bool {
+        self.faults
+            .iter()
+            .all(|f| matches!(f, Fault::NoReports | Fault::Shallow(_)))
+            && !self.passed()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QaReport {
+    pub tolerances: Tolerances,
+    pub keys: Vec<KeyResult>,
+}
+
+impl QaReport {
+    pub fn passed(&self) -> bool {
+        self.keys.iter().all(KeyResult::passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &KeyResult> {
+        self.keys.iter().filter(|k| !k.passed())
+    }
+
+    /// The report file: verdict, tolerances, a table of every key and the
+    /// reasons each failing key failed.
+    pub fn to_text(&self) -> String {
+        let tol = &self.tolerances;
+        let failed = self.failures().count();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "kb-hall switch QA: {}",
+            if self.passed() { "PASS" } else { "FAIL" }
+        );
+        let _ = writeln!(
+            out,
+            "{} keys, {} pass, {failed} fail",
+            self.keys.len(),
+            self.keys.len() - failed
+        );
+        let _ = writeln!(
+            out,
+            "tolerances: max raw >= {}, rest noise <= {:.1}, travel gap <= {}\n",
+            tol.min_max_raw, tol.max_noise, tol.max_gap
+        );
+        let _ = writeln!(
+            out,
+            "{:<7} {:<4}  {:>7}  {:>5}  {:>4}  result",
+            "key", "sc", "max_raw", "noise", "gap"
+        );
+        let opt = |v: Option<f64>, prec: usize| v.map_or("-".into(), |v| format!("{v:.prec$}"));
+        for k in &self.keys {
+            let _ = writeln!(
+                out,
+                "{:<7} 0x{:02X}  {:>7}  {:>5}  {:>4}  {}",
+                k.label,
+                k.scancode,
+                k.qa.max_raw,
+                opt(k.qa.noise, 1),
+                opt(k.qa.gap.map(f64::from), 0),
+                if k.passed() { "pass" } else { "FAIL" }
+            );
+        }
+        if failed > 0 {
+            out.push_str("\nout of tolerance\n");
+            for k in self.failures() {
+                let why: Vec<String> = k.faults.iter().map(Fault::to_string).collect();
+                let _ = writeln!(
+                    out,
+                    "{} (0x{:02X}): {}",
+                    k.label,
+                    k.scancode,
+                    why.join(", ")
+                );
+            }
+        }
+        out
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_keys() -> Layout {
+        Layout::from_json(r#"{ "rows": [[{ "sc": 4, "label": "A" }, { "sc": 5, "label": "B" }]] }"#)
+            .unwrap()
+    }
+
+    /// Idle readings alternating 2 and 3 every 10 ms for `ms`.
+    fn idle(q: &mut QaSession, sc: u8, t0: f64, ms: f64) {
+        for i in 0..(ms / 10.0) as usize {
+            q.record(sc, 2 + (i % 2) as u16, t0 + 10.0 * i as f64);
+        }
+    }
+
+    /// A steady press to `peak` over 15 ms and back, edged by readings below
+    /// rest on the way in and out.
+    fn press(q: &mut QaSession, sc: u8, peak: u16, t0: f64) {
+        q.record(sc, 30, t0);
+        for i in 1..=30 {
+            q.record(sc, peak * i / 30, t0 + 0.5 * i as f64);
+        }
+        q.record(sc, 30, t0 + 20.0);
+        q.record(sc, 12, t0 + 30.0);
+    }
+
+    #[test]
+    fn full_steady_press_passes() {
+        let mut q = QaSession::new(&two_keys(), Tolerances::default());
+        idle(&mut q, 0x04, 0.0, 500.0);
+        press(&mut q, 0x04, 1500, 500.0);
+        idle(&mut q, 0x04, 540.0, 500.0);
+        let k = q.key(0x04);
+        assert_eq!(k.max_raw, 1500);
+        assert_eq!(k.gap, Some(50));
+        // The edges of the press (30, 12) are not noise.
+        assert!((k.noise.unwrap() - 0.5).abs() < 0.01, "{:?}", k.noise);
+        assert_eq!(q.coverage(), (1, 2));
+
+        let r = q.report();
+        assert!(r.keys[0].passed());
+        assert_eq!(r.keys[1].faults, vec![Fault::NoReports]);
+        assert!(r.keys[1].pending());
+        assert!(!r.passed());
+        assert!(r.to_text().starts_with("kb-hall switch QA: FAIL"));
+    }
+
+    #[test]
+    fn noise_needs_sustained_idle() {
+        let mut q = QaSession::new(&two_keys(), Tolerances::default());
+        // Rest readings only between presses closer together than the guard.
+        for n in 0..5 {
+            press(&mut q, 0x04, 1500, n as f64 * 150.0);
+        }
+        assert_eq!(q.key(0x04).noise, None);
+        assert!(q.report().keys[0].passed());
+    }
+
+    #[test]
+    fn flags_shallow_and_noisy_keys() {
+        let mut q = QaSession::new(&two_keys(), Tolerances::default());
+        press(&mut q, 0x04, 800, 0.0);
+        for (i, raw) in [0, 20, 0, 20, 0, 20].into_iter().enumerate() {
+            q.record(0x04, raw, 200.0 + 50.0 * i as f64);
+        }
+        let r = q.report();
+        assert!(matches!(
+            r.keys[0].faults[..],
+            [Fault::Shallow(800), Fault::Noisy(_)]
+        ));
+        assert!(!r.keys[0].pending());
+        assert_eq!(r.failures().count(), 2);
+    }
+
+    #[test]
+    fn travel_gaps() {
+        let mut q = QaSession::new(&two_keys(), Tolerances::default());
+        // 0x04 jumps from 600 to 1000 however slowly it is pressed.
+        for n in 0..3 {
+            let t0 = n as f64 * 200.0;
+            for (i, raw) in (50..=600)
+                .step_by(25)
+                .chain((1000..=1500).step_by(25))
+                .enumerate()
+            {
+                q.record(0x04, raw, t0 + i as f64);
+            }
+            q.record(0x04, 0, t0 + 100.0);
+        }
+        // 0x05 is pressed fast, three times; together the presses cover
+        // its travel.
+        for (n, offset) in [0, 100, 200].into_iter().enumerate() {
+            let t0 = n as f64 * 200.0;
+            q.record(0x05, 50 + offset, t0);
+            for i in 1..=5 {
+                q.record(0x05, 50 + offset + 300 * i, t0 + i as f64);
+            }
+            q.record(0x05, 0, t0 + 100.0);
+        }
+        assert!(q.key(0x05).max_raw >= Tolerances::default().min_max_raw);
+
+        let r = q.report();
+        assert_eq!(r.keys[0].faults, vec![Fault::Gap(400)]);
+        assert!(r.keys[1].passed(), "{:?}", r.keys[1]);
+        let text = r.to_text();
+        assert!(text.contains("travel gap of 400 raw too wide"), "{text}");
+        assert!(
+            text.contains("A       0x04     1500      -   400  FAIL"),
+            "{text}"
+        );
+    }
+}
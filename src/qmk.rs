@@ -0,0 +1,560 @@
+//! Import QMK `info.json` and VIA definitions, with the firmware's keymap.
+//!
+//! The geometry comes from `layouts.<name>.layout` of a QMK `info.json`
+//! (keys with `x`, `y`, `w`, `h` and `matrix: [row, col]`) or from the KLE
+//! `layouts.keymap` of a VIA definition, whose legends are `"row,col"`. Of
+//! VIA's layout options only the default (choice 0) is kept.
+//!
+//! A keymap gives each key its scancode and legends. QMK's `keymap.json`
+//! lists each layer's keycodes in layout order, so it goes with an
+//! `info.json`; a VIA backup lists them in matrix order, so it goes with a
+//! VIA definition. Basic keycodes (`KC_A`, `KC_ENT`, ..) are HID usages;
+//! mod-tap, layer-tap and modifier wrappers use the keycode they wrap.
+//! Momentary layer keys (`MO`, `TT`, `OSL`, `LM`) on the base layer become
+//! Fn (`0xFF`), and the first one's layer is the one shown while Fn is held.
+//! Transparent keys show the base legend.
+//!
+//! Without a keymap, an `info.json` is mapped by its `label`s like KLE
+//! legends.
+
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::kle::{self, Import};
+use crate::layout::{Key, Layout};
+
+#[derive(Debug)]
+pub enum QmkError {
+    Json(serde_json::Error),
+    /// Not a definition or keymap we can read.
+    Format(&'static str),
+    /// The keymap's layout is not in the definition.
+    NoLayout(String),
+    /// A keymap layer of this many keycodes does not fit the layout.
+    KeymapSize(usize),
+}
+
+impl fmt::Display for QmkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QmkError::Json(e) => write!(f, "{e}"),
+            QmkError::Format(what) => f.write_str(what),
+            QmkError::NoLayout(name) => write!(f, "no layout {name:?} in the definition"),
+            QmkError::KeymapSize(n) => {
+                write!(f, "keymap layer of {n} keycodes does not fit the layout")
+            }
+        }
+    }
+}
+
+impl std::error::Error for QmkError {}
+
+impl From<serde_json::Error> for QmkError {
+    fn from(e: serde_json::Error) -> Self {
+        QmkError::Json(e)
+    }
+}
+
+/// Whether `json` is a QMK `info.json` or VIA definition.
+pub fn is_definition(json: &str) -> bool {
+    serde_json::from_str::<Value>(json).is_ok_and(|v| v["layouts"].is_object())
+}
+
+/// Read a definition and, if given, a keymap for it.
+pub fn import(definition: &str, keymap: Option<&str>) -> Result<Import, QmkError> {
+    let def: Value = serde_json::from_str(definition)?;
+    let keymap: Option<Value> = keymap.map(serde_json::from_str).transpose()?;
+    let name = def["keyboard_name"]
+        .as_str()
+        .or(def["name"].as_str())
+        .unwrap_or_default()
+        .to_string();
+    let via = def["layouts"]["keymap"].is_array();
+    let (slots, cols) = if via {
+        via_slots(&def)
+    } else {
+        qmk_slots(&def, keymap.as_ref())?
+    };
+    let (keys, unmapped, fn_layer) = match &keymap {
+        Some(km) => apply_keymap(slots, cols, km, via)?,
+        None if via => return Err(QmkError::Format("a VIA definition needs a keymap")),
+        None => {
+            let placed = slots.into_iter().map(|s| (s.label, s.key)).collect();
+            let (keys, unmapped) = kle::assign_scancodes(placed);
+            (keys, unmapped, None)
+        }
+    };
+    let mut layout = Layout {
+        name,
+        keys,
+        fn_layer,
+    };
+    kle::normalize(&mut layout);
+    Ok(Import { layout, unmapped })
+}
+
+/// A key of the definition, before it has a scancode.
+struct Slot {
+    key: Key,
+    label: String,
+    matrix: Option<(usize, usize)>,
+}
+
+/// Keys of the `info.json` layout the keymap names (else the first), and
+/// the matrix width.
+fn qmk_slots(def: &Value, keymap: Option<&Value>) -> Result<(Vec<Slot>, usize), QmkError> {
+    let layouts = def["layouts"]
+        .as_object()
+        .ok_or(QmkError::Format("no layouts"))?;
+    let layout = match keymap.and_then(|k| k["layout"].as_str()) {
+        Some(name) => {
+            let name = def["layout_aliases"][name].as_str().unwrap_or(name);
+            layouts
+                .get(name)
+                .ok_or_else(|| QmkError::NoLayout(name.to_string()))?
+        }
+        None => layouts
+            .values()
+            .next()
+            .ok_or(QmkError::Format("no layouts"))?,
+    };
+    let num =
+        |k: &Value, field: &str, default: f32| k[field].as_f64().map_or(default, |n| n as f32);
+    let slots: Vec<Slot> = layout["layout"]
+        .as_array()
+        .ok_or(QmkError::Format("layout without keys"))?
+        .iter()
+        .map(|k| Slot {
+            key: Key {
+                x: num(k, "x", 0.0),
+                y: num(k, "y", 0.0),
+                w: num(k, "w", 1.0),
+                h: num(k, "h", 1.0),
+                r: num(k, "r", 0.0),
+                rx: num(k, "rx", 0.0),
+                ry: num(k, "ry", 0.0),
+                ..Key::default()
+            },
+            label: k["label"].as_str().unwrap_or_default().to_string(),
+            matrix: match k["matrix"].as_array().map(Vec::as_slice) {
+                Some([r, c]) => Some((
+                    r.as_u64().unwrap_or(0) as usize,
+                    c.as_u64().unwrap_or(0) as usize,
+                )),
+                _ => None,
+            },
+        })
+        .collect();
+    let cols = match def["matrix_size"]["cols"].as_u64() {
+        Some(c) => c as usize,
+        None => slots
+            .iter()
+            .filter_map(|s| s.matrix)
+            .map(|(_, c)| c + 1)
+            .max()
+            .unwrap_or(0),
+    };
+    Ok((slots, cols))
+}
+
+/// Keys of a VIA definition's default layout options, and the matrix width.
+fn via_slots(def: &Value) -> (Vec<Slot>, usize) {
+    let rows = def["layouts"]["keymap"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let (_, placed) = kle::place(rows);
+    let slots = placed
+        .into_iter()
+        .filter_map(|(legend, key)| {
+            let lines: Vec<&str> = legend.split('\n').collect();
+            // Layout options are "group,choice" in the bottom-right legend.
+            let choice = lines.get(3).and_then(|o| o.split(',').nth(1));
+            if choice.is_some_and(|c| c.trim() != "0") {
+                return None;
+            }
+            let (r, c) = lines[0].split_once(',')?;
+            Some(Slot {
+                key,
+                label: String::new(),
+                matrix: Some((r.trim().parse().ok()?, c.trim().parse().ok()?)),
+            })
+        })
+        .collect();
+    (slots, def["matrix"]["cols"].as_u64().unwrap_or(0) as usize)
+}
+
+type Mapped = (Vec<Key>, Vec<String>, Option<usize>);
+
+/// Map `slots` through `keymap`, whose layers are in matrix order if
+/// `matrix_order` (a VIA backup), else in layout order (`keymap.json`).
+fn apply_keymap(
+    slots: Vec<Slot>,
+    cols: usize,
+    keymap: &Value,
+    matrix_order: bool,
+) -> Result<Mapped, QmkError> {
+    let layers: Vec<Vec<Keycode>> = keymap["layers"]
+        .as_array()
+        .ok_or(QmkError::Format("keymap without layers"))?
+        .iter()
+        .map(|l| {
+            let kcs = l.as_array().map(Vec::as_slice).unwrap_or_default();
+            kcs.iter()
+                .map(|kc| Keycode::parse(kc.as_str().unwrap_or_default()))
+                .collect()
+        })
+        .collect();
+    let Some(base) = layers.first() else {
+        return Err(QmkError::Format("keymap without layers"));
+    };
+    let index = |i: usize, s: &Slot| match (matrix_order, s.matrix) {
+        (false, _) => Some(i),
+        (true, Some((r, c))) if cols > 0 && c < cols => Some(r * cols + c),
+        _ => None,
+    };
+    let fits = if matrix_order {
+        slots
+            .iter()
+            .enumerate()
+            .all(|(i, s)| index(i, s).is_some_and(|at| at < base.len()))
+    } else {
+        base.len() == slots.len()
+    };
+    if !fits {
+        return Err(QmkError::KeymapSize(base.len()));
+    }
+
+    let mut keys = Vec::new();
+    let mut unmapped = Vec::new();
+    let mut fn_layer = None;
+    for (i, slot) in slots.into_iter().enumerate() {
+        let at = index(i, &slot).unwrap_or_default();
+        let kc = &base[at];
+        let scancode = match (kc.hid, kc.momentary) {
+            (Some(hid), _) => hid,
+            (None, Some(layer)) => {
+                fn_layer = fn_layer.or(Some(layer));
+                0xFF
+            }
+            _ => {
+                unmapped.push(kc.name.clone());
+                continue;
+            }
+        };
+        let label = kc.legend.clone();
+        let upper = layers[1..]
+            .iter()
+            .map(|l| match l.get(at) {
+                Some(k) if !k.transparent => k.legend.clone(),
+                _ => label.clone(),
+            })
+            .collect();
+        keys.push(Key {
+            scancode,
+            label,
+            layers: upper,
+            ..slot.key
+        });
+    }
+    Ok((keys, unmapped, fn_layer))
+}
+
+/// What a QMK keycode means for the visualizer.
+#[derive(Debug, Default, PartialEq)]
+struct Keycode {
+    name: String,
+    /// HID usage, for keycodes that send one.
+    hid: Option<u8>,
+    legend: String,
+    /// Layer switched on while the key is held.
+    momentary: Option<usize>,
+    transparent: bool,
+}
+
+impl Keycode {
+    fn parse(kc: &str) -> Self {
+        let kc = kc.trim();
+        let name = kc.to_string();
+        match kc {
+            "KC_TRNS" | "KC_TRANSPARENT" | "_______" => {
+                return Self {
+                    name,
+                    transparent: true,
+                    ..Self::default()
+                }
+            }
+            "KC_NO" | "XXXXXXX" => {
+                return Self {
+                    name,
+                    ..Self::default()
+                }
+            }
+            _ => {}
+        }
+        // Wrappers: MO(1), LT(2, KC_SPC), LCTL(KC_A), MT(MOD_LSFT, KC_Z), ..
+        if let Some((f, args)) = kc.strip_suffix(')').and_then(|k| k.split_once('(')) {
+            let (first, last) = (
+                args.split(',').next().unwrap_or_default().trim(),
+                args.rsplit(',').next().unwrap_or_default(),
+            );
+            let layer = first.parse::<usize>().ok();
+            return match (f, layer) {
+                ("MO" | "TT" | "OSL" | "LM", Some(n)) => Self {
+                    name,
+                    legend: if n == 1 {
+                        "Fn".into()
+                    } else {
+                        format!("Fn{n}")
+                    },
+                    momentary: Some(n),
+                    ..Self::default()
+                },
+                ("TG" | "TO" | "DF", _) => Self {
+                    legend: name.clone(),
+                    name,
+                    ..Self::default()
+                },
+                _ => Self {
+                    name,
+                    ..Self::parse(last)
+                },
+            };
+        }
+        let short = kc.strip_prefix("KC_").unwrap_or(kc);
+        let (hid, legend) = match basic(short) {
+            Some((hid, legend)) => (hid, legend),
+            None => (None, kc.strip_prefix("QK_").unwrap_or(short).to_string()),
+        };
+        Self {
+            name,
+            hid,
+            legend,
+            ..Self::default()
+        }
+    }
+}
+
+/// HID usage and legend of a basic keycode, without its `KC_` prefix.
+fn basic(name: &str) -> Option<(Option<u8>, String)> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c @ 'A'..='Z'), None) => return Some((Some(0x04 + (c as u8 - b'A')), name.into())),
+        (Some('0'), None) => return Some((Some(0x27), name.into())),
+        (Some(c @ '1'..='9'), None) => return Some((Some(0x1E + (c as u8 - b'1')), name.into())),
+        _ => {}
+    }
+    if let Some(f) = name.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()) {
+        let hid = match f {
+            1..=12 => 0x3A + f - 1,
+            13..=24 => 0x68 + f - 13,
+            _ => return None,
+        };
+        return Some((Some(hid), name.into()));
+    }
+    let keypad = name.strip_prefix("KP_").or(name.strip_prefix('P'));
+    if let Some(d) = keypad
+        .and_then(|d| d.parse::<u8>().ok())
+        .filter(|d| *d <= 9)
+    {
+        let hid = if d == 0 { 0x62 } else { 0x59 + d - 1 };
+        return Some((Some(hid), d.to_string()));
+    }
+    let (hid, legend) = match name {
+        "ENT" | "ENTER" => (0x28, "Enter"),
+        "ESC" | "ESCAPE" => (0x29, "Esc"),
+        "BSPC" | "BACKSPACE" => (0x2A, "Bksp"),
+        "TAB" => (0x2B, "Tab"),
+        "SPC" | "SPACE" => (0x2C, "Space"),
+        "MINS" | "MINUS" => (0x2D, "-"),
+        "EQL" | "EQUAL" => (0x2E, "="),
+        "LBRC" | "LEFT_BRACKET" => (0x2F, "["),
+        "RBRC" | "RIGHT_BRACKET" => (0x30, "]"),
+        "BSLS" | "BACKSLASH" => (0x31, "\\"),
+        "NUHS" | "NONUS_HASH" => (0x32, "#"),
+        "SCLN" | "SEMICOLON" => (0x33, ";"),
+        "QUOT" | "QUOTE" => (0x34, "'"),
+        "GRV" | "GRAVE" => (0x35, "`"),
+        "COMM" | "COMMA" => (0x36, ","),
+        "DOT" => (0x37, "."),
+        "SLSH" | "SLASH" => (0x38, "/"),
+        "CAPS" | "CAPS_LOCK" => (0x39, "Caps"),
+        "PSCR" | "PRINT_SCREEN" => (0x46, "PrtSc"),
+        "SCRL" | "SCROLL_LOCK" => (0x47, "ScrLk"),
+        "PAUS" | "PAUSE" | "BRK" => (0x48, "Pause"),
+        "INS" | "INSERT" => (0x49, "Ins"),
+        "HOME" => (0x4A, "Home"),
+        "PGUP" | "PAGE_UP" => (0x4B, "PgUp"),
+        "DEL" | "DELETE" => (0x4C, "Del"),
+        "END" => (0x4D, "End"),
+        "PGDN" | "PAGE_DOWN" => (0x4E, "PgDn"),
+        "RGHT" | "RIGHT" => (0x4F, "Right"),
+        "LEFT" => (0x50, "Left"),
+        "DOWN" => (0x51, "Down"),
+        "UP" => (0x52, "Up"),
+        "NUM" | "NUM_LOCK" => (0x53, "Num"),
+        "PSLS" | "KP_SLASH" => (0x54, "/"),
+        "PAST" | "KP_ASTERISK" => (0x55, "*"),
+        "PMNS" | "KP_MINUS" => (0x56, "-"),
+        "PPLS" | "KP_PLUS" => (0x57, "+"),
+        "PENT" | "KP_ENTER" => (0x58, "Enter"),
+        "PDOT" | "KP_DOT" => (0x63, "."),
+        "NUBS" | "NONUS_BACKSLASH" => (0x64, "\\"),
+        "APP" | "APPLICATION" => (0x65, "Menu"),
+        "LCTL" | "LEFT_CTRL" => (0xE0, "Ctrl"),
+        "LSFT" | "LEFT_SHIFT" => (0xE1, "Shift"),
+        "LALT" | "LEFT_ALT" | "LOPT" => (0xE2, "Alt"),
+        "LGUI" | "LEFT_GUI" | "LCMD" | "LWIN" => (0xE3, "Win"),
+        "RCTL" | "RIGHT_CTRL" => (0xE4, "Ctrl"),
+        "RSFT" | "RIGHT_SHIFT" => (0xE5, "Shift"),
+        "RALT" | "RIGHT_ALT" | "ROPT" | "ALGR" => (0xE6, "Alt"),
+        "RGUI" | "RIGHT_GUI" | "RCMD" | "RWIN" => (0xE7, "Win"),
+        // Consumer keys: not in the keyboard's scancodes, legend only.
+        _ => {
+            let legend = match name {
+                "MUTE" | "AUDIO_MUTE" => "Mute",
+                "VOLU" | "AUDIO_VOL_UP" => "Vol+",
+                "VOLD" | "AUDIO_VOL_DOWN" => "Vol-",
+                "MPLY" | "MEDIA_PLAY_PAUSE" => "Play",
+                "MNXT" | "MEDIA_NEXT_TRACK" => "Next",
+                "MPRV" | "MEDIA_PREV_TRACK" => "Prev",
+                "BRIU" | "BRIGHTNESS_UP" => "Bri+",
+                "BRID" | "BRIGHTNESS_DOWN" => "Bri-",
+                _ => return None,
+            };
+            return Some((None, legend.into()));
+        }
+    };
+    Some((Some(hid), legend.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &str = include_str!("../tests/fixtures/qmk/info.json");
+    const KEYMAP: &str = include_str!("../tests/fixtures/qmk/keymap.json");
+    const VIA: &str = include_str!("../tests/fixtures/qmk/via.json");
+    const VIA_BACKUP: &str = include_str!("../tests/fixtures/qmk/via-backup.json");
+
+    fn geometry(l: &Layout) -> Vec<(u8, f32, f32, f32, f32)> {
+        l.keys
+            .iter()
+            .map(|k| (k.scancode, k.x, k.y, k.w, k.h))
+            .collect()
+    }
+
+    #[test]
+    fn info_json_with_keymap_layers() {
+        let imp = import(INFO, Some(KEYMAP)).unwrap();
+        assert!(imp.unmapped.is_empty(), "{:?}", imp.unmapped);
+        let l = imp.layout;
+        assert_eq!(l.name, "Hall Pad");
+        assert_eq!(l.keys.len(), 11);
+        assert_eq!((l.width(), l.height()), (5.0, 3.0));
+        assert_eq!(l.fn_layer, Some(1));
+
+        let legends = |sc| {
+            let k = l.key(sc).unwrap();
+            (0..4).map(|n| k.legend(n)).collect::<Vec<_>>()
+        };
+        assert_eq!(legends(0x29), ["Esc", "`", "BOOT", "Esc"]);
+        assert_eq!(legends(0xFF), ["Fn", "Fn", "Fn", "Fn"]);
+        // Layer-tap: the tap keycode's scancode.
+        assert_eq!(legends(0x2C), ["Space", "Play", "Space", "Space"]);
+        assert_eq!(legends(0xE0), ["Ctrl", "", "Ctrl", "Ctrl"]);
+        assert_eq!(l.key(0xFF).map(|k| (k.x, k.y, k.w)), Some((0.0, 2.0, 1.25)));
+    }
+
+    #[test]
+    fn info_json_alone_maps_labels() {
+        let imp = import(INFO, None).unwrap();
+        assert!(imp.unmapped.is_empty(), "{:?}", imp.unmapped);
+        assert_eq!(imp.layout.fn_layer, None);
+        assert_eq!(
+            geometry(&imp.layout),
+            geometry(&import(INFO, Some(KEYMAP)).unwrap().layout)
+        );
+        assert_eq!(imp.layout.key(0x2A).unwrap().legend(1), "Backspace");
+    }
+
+    #[test]
+    fn via_definition_with_backup() {
+        let imp = import(VIA, Some(VIA_BACKUP)).unwrap();
+        assert!(imp.unmapped.is_empty(), "{:?}", imp.unmapped);
+        let qmk = import(INFO, Some(KEYMAP)).unwrap().layout;
+        // The split Ctrl option is left out.
+        assert_eq!(geometry(&imp.layout), geometry(&qmk));
+        assert_eq!(imp.layout.fn_layer, Some(1));
+        assert_eq!(imp.layout.key(0x1A).unwrap().legend(1), "Up");
+        assert!(matches!(import(VIA, None), Err(QmkError::Format(_))));
+    }
+
+    #[test]
+    fn via_backup_is_in_matrix_order() {
+        // Every matrix position is on the layout, right to left.
+        let def = r#"{ "name": "Duo", "matrix": { "rows": 1, "cols": 2 },
+            "layouts": { "keymap": [["0,1", "0,0"]] } }"#;
+        let backup = r#"{ "layers": [["KC_A", "KC_B"]] }"#;
+        let l = import(def, Some(backup)).unwrap().layout;
+        assert_eq!(
+            l.keys.iter().map(|k| (k.scancode, k.x)).collect::<Vec<_>>(),
+            [(0x05, 0.0), (0x04, 1.0)]
+        );
+
+        // A second layer key stays on the board; the first sets the layer.
+        let backup = r#"{ "layers": [["MO(1)", "MO(2)"], ["KC_A", "KC_B"], ["KC_C", "KC_D"]] }"#;
+        let imp = import(def, Some(backup)).unwrap();
+        assert!(imp.unmapped.is_empty(), "{:?}", imp.unmapped);
+        let l = imp.layout;
+        assert_eq!(l.fn_layer, Some(2));
+        assert_eq!(
+            l.keys
+                .iter()
+                .map(|k| (k.scancode, k.legend(0)))
+                .collect::<Vec<_>>(),
+            [(0xFF, "Fn2"), (0xFF, "Fn")]
+        );
+    }
+
+    #[test]
+    fn keycodes() {
+        let kc = Keycode::parse;
+        assert_eq!(
+            (kc("LT(2, KC_SPC)").hid, kc("LT(2, KC_SPC)").momentary),
+            (Some(0x2C), None)
+        );
+        assert_eq!(kc("LCTL(LSFT(KC_A))").hid, Some(0x04));
+        assert_eq!(kc("MT(MOD_LCTL | MOD_LSFT, KC_ESC)").legend, "Esc");
+        assert_eq!(kc("OSL(3)").momentary, Some(3));
+        assert_eq!(kc("TG(1)").momentary, None);
+        assert_eq!(
+            (kc("KC_P0").hid, kc("KC_KP_5").hid),
+            (Some(0x62), Some(0x5D))
+        );
+        assert_eq!(
+            (kc("KC_F13").hid, kc("KC_F25").legend.as_str()),
+            (Some(0x68), "F25")
+        );
+        assert_eq!(
+            (kc("RGB_TOG").hid, kc("RGB_TOG").legend.as_str()),
+            (None, "RGB_TOG")
+        );
+        assert!(kc("_______").transparent && !kc("XXXXXXX").transparent);
+    }
+
+    #[test]
+    fn keymap_must_fit_the_layout() {
+        let other = r#"{ "layout": "LAYOUT_iso", "layers": [[]] }"#;
+        assert!(
+            matches!(import(INFO, Some(other)), Err(QmkError::NoLayout(n)) if n == "LAYOUT_iso")
+        );
+        let short = r#"{ "layers": [["KC_A", "KC_B"]] }"#;
+        assert!(matches!(
+            import(INFO, Some(short)),
+            Err(QmkError::KeymapSize(2))
+        ));
+        assert!(is_definition(INFO) && is_definition(VIA) && !is_definition(KEYMAP));
+    }
+}
@@ -0,0 +1,275 @@
+//! Sensor health: resting floor, noise, drift and stuck keys.
+//!
+//! Hall sensors drift with temperature, so a released key does not always
+//! read the same raw value. [`SensorMonitor`] follows each key's floor (the
+//! average raw reading at rest) and its noise (standard deviation at rest),
+//! and flags two problems:
+//!
+//! - drift: the floor climbed past `ANALOG_DEADZONE`, so the key is about to
+//!   read as pressed while released. Readings within [`FLOOR_BAND`] of the
+//!   floor keep counting as rest, so the floor is still followed once it
+//!   has climbed past [`REST`];
+//! - stuck: the key's last reading is a press, and it has not reported for
+//!   [`STUCK_MS`]. Keys only report when their reading changes, so this is
+//!   checked periodically ([`SensorMonitor::check`]), not on reports.
+//!
+//! Each key has a zero offset that is subtracted from its raw reading before
+//! the deadzone is applied. A drifted key is re-zeroed to its floor,
+//! rounded; a stuck key is re-zeroed to its frozen reading, but only on
+//! request, never automatically. A lower reading moves the zero back down.
+
+use crate::dynamics::REST;
+use crate::{raw_depth, ANALOG_DEADZONE};
+
+/// How long a pressed key may go without a report before it counts as stuck.
+pub const STUCK_MS: f64 = 30_000.0;
+/// Readings this close to the floor, in raw units, are the floor moving,
+/// not a press.
+pub const FLOOR_BAND: f64 = 8.0;
+/// Weight of each rest reading in the floor and noise averages.
+const FLOOR_ALPHA: f64 = 0.05;
+/// Rest readings needed before drift is reported.
+const MIN_REST_SAMPLES: u64 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SensorWarning {
+    /// The floor is this far above the key's zero, past the deadzone.
+    Drift(f64),
+    /// Reading this raw value, pressed, without a report for the given ms.
+    Stuck { raw: u16, ms: f64 },
+}
+
+/// Health of one key's sensor.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyHealth {
+    /// Average raw reading at rest; `None` before the key reported at rest.
+    pub floor: Option<f64>,
+    /// Standard deviation of the raw readings at rest.
+    pub noise: Option<f64>,
+    /// Offset subtracted from the raw reading.
+    pub zero: u16,
+    pub last_raw: u16,
+    /// Time of the latest report, page clock ms.
+    pub last_ms: f64,
+    pub warning: Option<SensorWarning>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Track {
+    rest_n: u64,
+    floor: f64,
+    var: f64,
+    zero: u16,
+    last_raw: u16,
+    last_ms: Option<f64>,
+    /// Age of the pressed `last_raw` when last checked, once past `STUCK_MS`.
+    stuck_ms: Option<f64>,
+}
+
+impl Track {
+    fn depth(&self, raw: u16) -> f32 {
+        raw_depth(raw.saturating_sub(self.zero))
+    }
+
+    /// At rest, or close enough to the floor to be the floor moving.
+    fn resting(&self, raw: u16) -> bool {
+        self.depth(raw) <= REST
+            || (self.rest_n > 0 && (raw as f64 - self.floor).abs() <= FLOOR_BAND)
+    }
+
+    fn warning(&self) -> Option<SensorWarning> {
+        if let Some(ms) = self.stuck_ms {
+            return Some(SensorWarning::Stuck {
+                raw: self.last_raw,
+                ms,
+            });
+        }
+        let above = self.floor - self.zero as f64;
+        (self.rest_n >= MIN_REST_SAMPLES && above > ANALOG_DEADZONE as f64)
+            .then_some(SensorWarning::Drift(above))
+    }
+}
+
+/// Floor, noise and zero offset of all 256 scancodes.
+pub struct SensorMonitor {
+    keys: Box<[Track; 256]>,
+    /// Re-zero keys as soon as they are flagged.
+    pub auto_rezero: bool,
+}
+
+impl Default for SensorMonitor {
+    fn default() -> Self {
+        Self {
+            keys: Box::new([Track::default(); 256]),
+            auto_rezero: false,
+        }
+    }
+}
+
+impl SensorMonitor {
+    /// Feed one analog report: `scancode` read `raw` at time `t_ms`.
+    pub fn record(&mut self, scancode: u8, raw: u16, t_ms: f64) {
+        let tr = &mut self.keys[scancode as usize];
+        tr.last_raw = raw;
+        tr.last_ms = Some(t_ms);
+        tr.stuck_ms = None;
+        // A key re-zeroed while it was held recovers once it is released.
+        tr.zero = tr.zero.min(raw);
+        if !tr.resting(raw) {
+            return;
+        }
+        let r = raw as f64;
+        if tr.rest_n == 0 {
+            tr.floor = r;
+        } else {
+            let d = r - tr.floor;
+            tr.floor += FLOOR_ALPHA * d;
+            tr.var = (1.0 - FLOOR_ALPHA) * (tr.var + FLOOR_ALPHA * d * d);
+        }
+        tr.rest_n += 1;
+    }
+
+    /// Flag keys whose last reading is a press that is [`STUCK_MS`] old at
+    /// `now_ms` (page clock); call periodically.
+    pub fn check(&mut self, now_ms: f64) {
+        for tr in self.keys.iter_mut() {
+            let age = tr.last_ms.map(|t| now_ms - t);
+            tr.stuck_ms = age.filter(|&a| a >= STUCK_MS && !tr.resting(tr.last_raw));
+        }
+    }
+
+    /// Depth of a raw reading of `scancode`, after its zero offset.
+    pub fn depth(&self, scancode: u8, raw: u16) -> f32 {
+        self.keys[scancode as usize].depth(raw)
+    }
+
+    pub fn zero(&self, scancode: u8) -> u16 {
+        self.keys[scancode as usize].zero
+    }
+
+    pub fn key(&self, scancode: u8) -> KeyHealth {
+        let tr = &self.keys[scancode as usize];
+        let rested = tr.rest_n > 0;
+        KeyHealth {
+            floor: rested.then_some(tr.floor),
+            noise: (tr.rest_n > 1).then(|| tr.var.sqrt()),
+            zero: tr.zero,
+            last_raw: tr.last_raw,
+            last_ms: tr.last_ms.unwrap_or(0.0),
+            warning: tr.warning(),
+        }
+    }
+
+    /// Every flagged key, by scancode.
+    pub fn warnings(&self) -> Vec<(u8, SensorWarning)> {
+        (0..=255u8)
+            .filter_map(|sc| Some((sc, self.keys[sc as usize].warning()?)))
+            .collect()
+    }
+
+    /// Re-zero one flagged key: to its frozen reading when stuck, to its
+    /// floor (rounded) when drifted. Returns the key's depth under the new
+    /// zero, or `None` if it did not move.
+    pub fn rezero(&mut self, scancode: u8) -> Option<f32> {
+        let tr = &mut self.keys[scancode as usize];
+        let zero = match tr.warning()? {
+            SensorWarning::Stuck { raw, .. } => raw,
+            SensorWarning::Drift(_) => tr.floor.round() as u16,
+        };
+        if zero == tr.zero {
+            return None;
+        }
+        tr.zero = zero;
+        tr.stuck_ms = None;
+        Some(tr.depth(tr.last_raw))
+    }
+
+    /// Re-zero flagged keys, stuck ones only if `stuck`; returns the keys
+    /// that moved with their new depth.
+    pub fn rezero_flagged(&mut self, stuck: bool) -> Vec<(u8, f32)> {
+        self.warnings()
+            .into_iter()
+            .filter(|(_, w)| stuck || !matches!(w, SensorWarning::Stuck { .. }))
+            .filter_map(|(sc, _)| Some((sc, self.rezero(sc)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floor_and_noise_follow_rest_readings() {
+        let mut m = SensorMonitor::default();
+        for i in 0..200 {
+            m.record(4, if i % 2 == 0 { 2 } else { 6 }, i as f64);
+        }
+        let k = m.key(4);
+        assert!((k.floor.unwrap() - 4.0).abs() < 0.2);
+        assert!((k.noise.unwrap() - 2.0).abs() < 0.2);
+        assert_eq!(k.warning, None);
+        assert_eq!(m.key(5).floor, None);
+    }
+
+    #[test]
+    fn drifted_floor_is_followed_past_rest_and_rezeroed() {
+        let mut m = SensorMonitor::default();
+        // The floor creeps from 5 to 80 raw and settles there, with +-1
+        // noise; 80 reads as a press, but the floor is still followed.
+        for i in 0..1700u16 {
+            m.record(4, 5 + (i / 20).min(75) + i % 2 * 2 - 1, i as f64);
+        }
+        assert!(m.depth(4, 80) > REST);
+        let floor = m.key(4).floor.unwrap();
+        assert!((floor - 80.0).abs() < 0.5, "{floor}");
+        assert!(matches!(m.warnings()[..], [(4, SensorWarning::Drift(d))] if d > 70.0));
+
+        // Re-zeroed to the rounded floor despite the noise.
+        assert_eq!(m.rezero_flagged(false), vec![(4, 0.0)]);
+        assert_eq!(m.zero(4), 80);
+        assert_eq!(m.depth(4, 81), 0.0);
+        assert!(m.warnings().is_empty());
+
+        // A real press is not taken for the floor.
+        m.record(4, 800, 2000.0);
+        assert!((m.key(4).floor.unwrap() - 80.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn pressed_key_without_reports_is_stuck() {
+        let mut m = SensorMonitor::default();
+        m.record(4, 0, 0.0);
+        m.record(4, 400, 10.0);
+        m.check(10.0 + STUCK_MS - 1.0);
+        assert!(m.warnings().is_empty());
+
+        m.check(10.0 + STUCK_MS + 5_000.0);
+        assert_eq!(
+            m.warnings(),
+            vec![(
+                4,
+                SensorWarning::Stuck {
+                    raw: 400,
+                    ms: STUCK_MS + 5_000.0
+                }
+            )]
+        );
+        // Never re-zeroed automatically, only on request.
+        assert!(m.rezero_flagged(false).is_empty());
+        assert_eq!(m.zero(4), 0);
+        assert_eq!(m.rezero_flagged(true), vec![(4, 0.0)]);
+        assert_eq!(m.zero(4), 400);
+        assert!(m.warnings().is_empty());
+
+        // A report clears the flag; a key at rest may go quiet for good.
+        m.record(4, 400, 50_000.0);
+        m.record(4, 0, 50_010.0);
+        m.record(5, 400, 50_000.0);
+        m.check(40_000.0 + STUCK_MS);
+        assert!(m.warnings().is_empty());
+        m.check(50_010.0 + 10.0 * STUCK_MS);
+        assert_eq!(m.warnings().len(), 1);
+        assert_eq!(m.warnings()[0].0, 5);
+    }
+}
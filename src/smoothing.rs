@@ -0,0 +1,313 @@
+//! How a visualizer's shown depth follows the analog values.
+//!
+//! [`Smoother`] is fed the `values()` snapshot once per frame with the frame
+//! time and keeps a smoothed value per key. The [`Smoothing`] models trade
+//! lag for calm: `raw` shows every frame's value and hides nothing, `slew`
+//! limits how fast the value moves (the visualizer's default), `exp` and
+//! `spring` ease toward it, and `peak` shows the raw value with a marker at
+//! the recent peak that holds, then falls.
+//!
+//! Models parse from `NAME` or `NAME:PARAMS`, e.g. `slew:25,8`, `exp:0.03`,
+//! `spring:40` or `peak:1,2`; see [`Smoothing::parse`].
+
+use std::fmt;
+
+/// Model names, in the order [`Smoothing::next`] cycles them.
+pub const MODELS: [&str; 5] = ["raw", "slew", "exp", "spring", "peak"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Smoothing {
+    /// The value as it is.
+    Raw,
+    /// Moves at most `rise` (pressing) or `fall` (releasing) full travels
+    /// per second.
+    Slew { rise: f32, fall: f32 },
+    /// Closes the gap exponentially, 63% of it every `tau` seconds.
+    Exponential { tau: f32 },
+    /// Critically damped spring with natural frequency `omega` (rad/s): as
+    /// fast as it gets without overshooting a step.
+    Spring { omega: f32 },
+    /// The raw value, plus a peak held for `hold` seconds that then falls
+    /// at `decay` full travels per second.
+    PeakHold { hold: f32, decay: f32 },
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Smoothing::Slew {
+            rise: 25.0,
+            fall: 8.0,
+        }
+    }
+}
+
+impl Smoothing {
+    /// The model called `name`, with its default parameters.
+    pub fn by_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "raw" => Smoothing::Raw,
+            "slew" => Smoothing::default(),
+            "exp" => Smoothing::Exponential { tau: 0.03 },
+            "spring" => Smoothing::Spring { omega: 40.0 },
+            "peak" => Smoothing::PeakHold {
+                hold: 1.0,
+                decay: 2.0,
+            },
+            _ => return None,
+        })
+    }
+
+    /// `NAME` or `NAME:PARAMS` with the parameters in declaration order,
+    /// comma separated; left out ones keep their defaults.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (name, params) = spec.split_once(':').unwrap_or((spec, ""));
+        let mut model = Self::by_name(name)?;
+        let params = params
+            .split(',')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.trim().parse::<f32>().ok().filter(|v| *v > 0.0))
+            .collect::<Option<Vec<_>>>()?;
+        let slots: Vec<&mut f32> = match &mut model {
+            Smoothing::Raw => vec![],
+            Smoothing::Slew { rise, fall } => vec![rise, fall],
+            Smoothing::Exponential { tau } => vec![tau],
+            Smoothing::Spring { omega } => vec![omega],
+            Smoothing::PeakHold { hold, decay } => vec![hold, decay],
+        };
+        if params.len() > slots.len() {
+            return None;
+        }
+        for (slot, v) in slots.into_iter().zip(params) {
+            *slot = v;
+        }
+        Some(model)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Smoothing::Raw => "raw",
+            Smoothing::Slew { .. } => "slew",
+            Smoothing::Exponential { .. } => "exp",
+            Smoothing::Spring { .. } => "spring",
+            Smoothing::PeakHold { .. } => "peak",
+        }
+    }
+
+    /// The next model in [`MODELS`], with its default parameters.
+    pub fn next(&self) -> Self {
+        let i = MODELS.iter().position(|&n| n == self.name()).unwrap_or(0);
+        Self::by_name(MODELS[(i + 1) % MODELS.len()]).unwrap_or_default()
+    }
+
+    /// The same model, `factor` times as quick to follow the value.
+    pub fn faster(&self, factor: f32) -> Self {
+        match *self {
+            Smoothing::Raw => Smoothing::Raw,
+            Smoothing::Slew { rise, fall } => Smoothing::Slew {
+                rise: rise * factor,
+                fall: fall * factor,
+            },
+            Smoothing::Exponential { tau } => Smoothing::Exponential { tau: tau / factor },
+            Smoothing::Spring { omega } => Smoothing::Spring {
+                omega: omega * factor,
+            },
+            Smoothing::PeakHold { hold, decay } => Smoothing::PeakHold {
+                hold: hold / factor,
+                decay: decay * factor,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Smoothing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Smoothing::Raw => write!(f, "raw"),
+            Smoothing::Slew { rise, fall } => write!(f, "slew {rise:.1}/s up, {fall:.1}/s down"),
+            Smoothing::Exponential { tau } => write!(f, "exp {:.0} ms", tau * 1000.0),
+            Smoothing::Spring { omega } => write!(f, "spring {omega:.0} rad/s"),
+            Smoothing::PeakHold { hold, decay } => {
+                write!(f, "peak hold {hold:.2} s, falls {decay:.1}/s")
+            }
+        }
+    }
+}
+
+/// Per-key smoothed values under a [`Smoothing`] model.
+#[derive(Clone, Debug)]
+pub struct Smoother {
+    model: Smoothing,
+    value: [f32; 256],
+    /// Spring velocity, per second.
+    velocity: [f32; 256],
+    peak: [f32; 256],
+    /// Seconds since the peak was last raised.
+    held: [f32; 256],
+}
+
+impl Default for Smoother {
+    fn default() -> Self {
+        Self::new(Smoothing::default())
+    }
+}
+
+impl Smoother {
+    pub fn new(model: Smoothing) -> Self {
+        Self {
+            model,
+            value: [0.0; 256],
+            velocity: [0.0; 256],
+            peak: [0.0; 256],
+            held: [0.0; 256],
+        }
+    }
+
+    pub fn model(&self) -> Smoothing {
+        self.model
+    }
+
+    /// Switches the model; the shown values carry over, spring velocities
+    /// and peaks start again.
+    pub fn set_model(&mut self, model: Smoothing) {
+        self.model = model;
+        self.velocity = [0.0; 256];
+        self.peak = self.value;
+        self.held = [0.0; 256];
+    }
+
+    /// Advances by `dt` seconds toward `target`.
+    pub fn update(&mut self, target: &[f32; 256], dt: f32) {
+        let dt = dt.max(0.0);
+        for (i, &t) in target.iter().enumerate() {
+            let v = &mut self.value[i];
+            match self.model {
+                Smoothing::Raw | Smoothing::PeakHold { .. } => *v = t,
+                Smoothing::Slew { rise, fall } => {
+                    *v = if t > *v {
+                        (*v + rise * dt).min(t)
+                    } else {
+                        (*v - fall * dt).max(t)
+                    };
+                }
+                Smoothing::Exponential { tau } => *v += (t - *v) * (1.0 - (-dt / tau).exp()),
+                Smoothing::Spring { omega } => {
+                    // Exact step of x'' = -2ωx' - ω²x around the target.
+                    let e = *v - t;
+                    let c = self.velocity[i] + omega * e;
+                    let decay = (-omega * dt).exp();
+                    *v = t + (e + c * dt) * decay;
+                    self.velocity[i] = (self.velocity[i] - omega * c * dt) * decay;
+                }
+            }
+            if let Smoothing::PeakHold { hold, decay } = self.model {
+                let p = &mut self.peak[i];
+                if t >= *p {
+                    *p = t;
+                    self.held[i] = 0.0;
+                } else {
+                    self.held[i] += dt;
+                    if self.held[i] > hold {
+                        *p = (*p - decay * dt).max(t);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The values to show, 0.0..1.0 (a spring can pass the ends briefly).
+    pub fn values(&self) -> &[f32; 256] {
+        &self.value
+    }
+
+    /// Held peaks, with the peak-hold model only.
+    pub fn peaks(&self) -> Option<&[f32; 256]> {
+        matches!(self.model, Smoothing::PeakHold { .. }).then_some(&self.peak)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SC: usize = 0x04;
+
+    fn run(s: &mut Smoother, target: f32, secs: f32) -> f32 {
+        let mut vals = [0.0; 256];
+        vals[SC] = target;
+        let steps = (secs / 0.001).round() as usize;
+        for _ in 0..steps {
+            s.update(&vals, 0.001);
+        }
+        s.values()[SC]
+    }
+
+    #[test]
+    fn raw_and_slew() {
+        let mut s = Smoother::new(Smoothing::Raw);
+        assert_eq!(run(&mut s, 0.7, 0.001), 0.7);
+
+        let mut s = Smoother::new(Smoothing::Slew {
+            rise: 10.0,
+            fall: 1.0,
+        });
+        assert!((run(&mut s, 1.0, 0.05) - 0.5).abs() < 1e-3);
+        assert_eq!(run(&mut s, 1.0, 0.1), 1.0);
+        assert!((run(&mut s, 0.0, 0.2) - 0.8).abs() < 1e-3);
+    }
+
+    #[test]
+    fn exponential_and_spring() {
+        let mut s = Smoother::new(Smoothing::Exponential { tau: 0.05 });
+        assert!((run(&mut s, 1.0, 0.05) - (1.0 - (-1.0f32).exp())).abs() < 1e-3);
+
+        // Critically damped: settles without overshooting.
+        let mut s = Smoother::new(Smoothing::Spring { omega: 40.0 });
+        let mut max = 0.0f32;
+        for _ in 0..500 {
+            max = max.max(run(&mut s, 1.0, 0.001));
+        }
+        assert!(max <= 1.0 + 1e-4, "overshoot {max}");
+        assert!(s.values()[SC] > 0.999);
+    }
+
+    #[test]
+    fn peak_holds_then_falls() {
+        let mut s = Smoother::new(Smoothing::PeakHold {
+            hold: 0.5,
+            decay: 1.0,
+        });
+        run(&mut s, 0.8, 0.01);
+        assert_eq!(run(&mut s, 0.0, 0.4), 0.0);
+        assert_eq!(s.peaks().unwrap()[SC], 0.8);
+        run(&mut s, 0.0, 0.3);
+        assert!((s.peaks().unwrap()[SC] - 0.6).abs() < 0.01);
+        run(&mut s, 0.0, 1.0);
+        assert_eq!(s.peaks().unwrap()[SC], 0.0);
+        assert!(Smoother::default().peaks().is_none());
+    }
+
+    #[test]
+    fn parse_specs() {
+        assert_eq!(Smoothing::parse("slew"), Some(Smoothing::default()));
+        assert_eq!(
+            Smoothing::parse("slew:30"),
+            Some(Smoothing::Slew {
+                rise: 30.0,
+                fall: 8.0
+            })
+        );
+        assert_eq!(
+            Smoothing::parse("peak:0.5,4"),
+            Some(Smoothing::PeakHold {
+                hold: 0.5,
+                decay: 4.0
+            })
+        );
+        assert_eq!(Smoothing::parse("spring:x"), None);
+        assert_eq!(Smoothing::parse("exp:1,2"), None);
+        assert_eq!(Smoothing::parse("exp:-1"), None);
+        assert_eq!(Smoothing::parse("bogus"), None);
+        assert_eq!(Smoothing::Raw.next().name(), "slew");
+        assert_eq!(Smoothing::parse("peak").unwrap().next(), Smoothing::Raw);
+    }
+}
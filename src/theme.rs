@@ -0,0 +1,379 @@
+//! Colour themes for the visualizer: background, key caps, text, status
+//! colours and the colour maps the depth fill and usage heatmap use.
+//!
+//! Built-in themes are in [`BUILTIN`]. A theme file is JSON with any of the
+//! [`Theme`] fields; the rest come from `base` (a built-in name, default
+//! `"default"`):
+//!
+//! ```json
+//! { "name": "Mono", "base": "light", "background": "#ffffff",
+//!   "fill": ["#cccccc", "#000000"], "heatmap": "magma" }
+//! ```
+//!
+//! Colours are `"#rrggbb"` or `[r, g, b]` in 0..1. A colour map is the name
+//! of a built-in one (`classic`, `heat`, `viridis`, `magma`, `turbo`,
+//! `cividis`) or a list of evenly spaced colours.
+
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// An sRGB colour, components 0..1.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "RgbDef", into = "[f32; 3]")]
+pub struct Rgb(pub [f32; 3]);
+
+impl Rgb {
+    pub const fn gray(v: f32) -> Self {
+        Rgb([v, v, v])
+    }
+
+    /// `self` at `t = 0` to `other` at `t = 1`.
+    pub fn lerp(self, other: Rgb, t: f32) -> Rgb {
+        Rgb(std::array::from_fn(|i| {
+            self.0[i] + (other.0[i] - self.0[i]) * t
+        }))
+    }
+}
+
+impl From<Rgb> for [f32; 3] {
+    fn from(c: Rgb) -> Self {
+        c.0
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RgbDef {
+    Hex(String),
+    Floats([f32; 3]),
+}
+
+impl TryFrom<RgbDef> for Rgb {
+    type Error = String;
+
+    fn try_from(def: RgbDef) -> Result<Self, String> {
+        let hex = match def {
+            RgbDef::Floats(c) => return Ok(Rgb(c)),
+            RgbDef::Hex(s) => s,
+        };
+        let digits = hex.strip_prefix('#').filter(|d| d.len() == 6);
+        let n = digits
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| format!("bad colour {hex:?}, expected \"#rrggbb\""))?;
+        Ok(Rgb([16, 8, 0].map(|s| ((n >> s) & 0xFF) as f32 / 255.0)))
+    }
+}
+
+const fn rgb(r: f32, g: f32, b: f32) -> Rgb {
+    Rgb([r, g, b])
+}
+
+/// Evenly spaced colours, linearly interpolated.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "MapDef", into = "Vec<Rgb>")]
+pub struct ColorMap(Vec<Rgb>);
+
+impl ColorMap {
+    /// A built-in colour map.
+    pub fn by_name(name: &str) -> Option<Self> {
+        let stops: &[Rgb] = match name {
+            "classic" => &CLASSIC,
+            "heat" => &HEAT,
+            "viridis" => &VIRIDIS,
+            "magma" => &MAGMA,
+            "turbo" => &TURBO,
+            "cividis" => &CIVIDIS,
+            _ => return None,
+        };
+        Some(ColorMap(stops.to_vec()))
+    }
+
+    /// Colour at `t`, clamped to 0..1.
+    pub fn sample(&self, t: f32) -> Rgb {
+        let last = self.0.len() - 1;
+        let x = t.clamp(0.0, 1.0) * last as f32;
+        let i = (x as usize).min(last.saturating_sub(1));
+        match self.0.get(i + 1) {
+            Some(&next) => self.0[i].lerp(next, x - i as f32),
+            None => self.0[i],
+        }
+    }
+}
+
+impl From<ColorMap> for Vec<Rgb> {
+    fn from(m: ColorMap) -> Self {
+        m.0
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MapDef {
+    Name(String),
+    Stops(Vec<Rgb>),
+}
+
+impl TryFrom<MapDef> for ColorMap {
+    type Error = String;
+
+    fn try_from(def: MapDef) -> Result<Self, String> {
+        match def {
+            MapDef::Name(n) => ColorMap::by_name(&n).ok_or_else(|| format!("no colour map {n:?}")),
+            MapDef::Stops(s) if s.is_empty() => Err("empty colour map".into()),
+            MapDef::Stops(s) => Ok(ColorMap(s)),
+        }
+    }
+}
+
+/// Blue at rest, green mid-travel, red at the bottom (`r = v²`, ..).
+const CLASSIC: [Rgb; 11] = [
+    rgb(0.00, 0.40, 1.00),
+    rgb(0.01, 0.52, 0.96),
+    rgb(0.04, 0.64, 0.92),
+    rgb(0.09, 0.76, 0.88),
+    rgb(0.16, 0.88, 0.84),
+    rgb(0.25, 1.00, 0.80),
+    rgb(0.36, 0.88, 0.76),
+    rgb(0.49, 0.76, 0.72),
+    rgb(0.64, 0.64, 0.68),
+    rgb(0.81, 0.52, 0.64),
+    rgb(1.00, 0.40, 0.60),
+];
+/// Dark blue through red to yellow.
+const HEAT: [Rgb; 3] = [
+    rgb(0.10, 0.15, 0.45),
+    rgb(0.85, 0.20, 0.10),
+    rgb(1.00, 0.85, 0.20),
+];
+const VIRIDIS: [Rgb; 11] = [
+    rgb(0.267, 0.005, 0.329),
+    rgb(0.283, 0.141, 0.458),
+    rgb(0.254, 0.265, 0.530),
+    rgb(0.207, 0.372, 0.553),
+    rgb(0.164, 0.471, 0.558),
+    rgb(0.128, 0.567, 0.551),
+    rgb(0.135, 0.659, 0.518),
+    rgb(0.267, 0.749, 0.441),
+    rgb(0.478, 0.821, 0.3182),
+    rgb(0.741, 0.873, 0.150),
+    rgb(0.993, 0.906, 0.144),
+];
+const MAGMA: [Rgb; 11] = [
+    rgb(0.001, 0.000, 0.014),
+    rgb(0.079, 0.054, 0.212),
+    rgb(0.232, 0.060, 0.438),
+    rgb(0.390, 0.100, 0.502),
+    rgb(0.550, 0.161, 0.506),
+    rgb(0.716, 0.215, 0.475),
+    rgb(0.869, 0.288, 0.409),
+    rgb(0.968, 0.440, 0.360),
+    rgb(0.995, 0.624, 0.427),
+    rgb(0.996, 0.813, 0.573),
+    rgb(0.987, 0.991, 0.750),
+];
+const TURBO: [Rgb; 11] = [
+    rgb(0.190, 0.072, 0.232),
+    rgb(0.288, 0.346, 0.867),
+    rgb(0.184, 0.618, 0.960),
+    rgb(0.154, 0.844, 0.765),
+    rgb(0.304, 0.975, 0.512),
+    rgb(0.589, 0.982, 0.313),
+    rgb(0.876, 0.862, 0.196),
+    rgb(1.000, 0.637, 0.136),
+    rgb(0.958, 0.363, 0.091),
+    rgb(0.721, 0.125, 0.031),
+    rgb(0.480, 0.016, 0.011),
+];
+/// Blue to yellow, readable with red-green colour blindness.
+const CIVIDIS: [Rgb; 5] = [
+    rgb(0.000, 0.135, 0.305),
+    rgb(0.255, 0.310, 0.425),
+    rgb(0.490, 0.485, 0.470),
+    rgb(0.740, 0.680, 0.420),
+    rgb(0.996, 0.909, 0.218),
+];
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Theme {
+    pub name: String,
+    pub background: Rgb,
+    /// Cap at rest; pressed caps go towards `cap_pressed`.
+    pub cap: Rgb,
+    pub cap_pressed: Rgb,
+    /// Key legends.
+    pub label: Rgb,
+    /// Depth percentage and heatmap numbers on the caps.
+    pub value: Rgb,
+    /// The stats panel.
+    pub text: Rgb,
+    /// Hints and the scope's help line.
+    pub dim: Rgb,
+    /// Connected, QA pass.
+    pub ok: Rgb,
+    /// Waiting, QA in progress, the selected key.
+    pub warn: Rgb,
+    /// Sensor warnings, QA failures.
+    pub error: Rgb,
+    /// Depth fill, from rest to bottom-out.
+    pub fill: ColorMap,
+    /// Usage heatmap, from least to most used.
+    pub heatmap: ColorMap,
+}
+
+/// Built-in theme names, in hotkey order.
+pub const BUILTIN: &[&str] = &[
+    "default",
+    "viridis",
+    "magma",
+    "turbo",
+    "colorblind",
+    "light",
+];
+
+/// Look up a built-in theme.
+pub fn by_name(name: &str) -> Option<Theme> {
+    let map = |n| ColorMap::by_name(n).expect("built-in colour map");
+    let dark = Theme {
+        name: name.to_string(),
+        background: Rgb::gray(0.09),
+        cap: Rgb::gray(0.20),
+        cap_pressed: Rgb::gray(0.40),
+        label: Rgb::gray(0.63),
+        value: Rgb::gray(1.0),
+        text: Rgb::gray(0.75),
+        dim: Rgb::gray(0.53),
+        ok: rgb(0.3, 0.9, 0.4),
+        warn: rgb(0.9, 0.7, 0.3),
+        error: rgb(1.0, 0.45, 0.35),
+        fill: map("classic"),
+        heatmap: map("heat"),
+    };
+    Some(match name {
+        "default" => dark,
+        "viridis" | "magma" | "turbo" => Theme {
+            fill: map(name),
+            heatmap: map(name),
+            ..dark
+        },
+        // Okabe-Ito sky blue, yellow and orange instead of green and red.
+        "colorblind" => Theme {
+            ok: rgb(0.34, 0.71, 0.91),
+            warn: rgb(0.94, 0.89, 0.26),
+            error: rgb(0.90, 0.62, 0.0),
+            fill: map("cividis"),
+            heatmap: map("cividis"),
+            ..dark
+        },
+        "light" => Theme {
+            background: Rgb::gray(0.95),
+            cap: Rgb::gray(0.85),
+            cap_pressed: Rgb::gray(0.70),
+            label: Rgb::gray(0.25),
+            value: Rgb::gray(0.10),
+            text: Rgb::gray(0.20),
+            dim: Rgb::gray(0.40),
+            ok: rgb(0.10, 0.55, 0.20),
+            warn: rgb(0.70, 0.50, 0.0),
+            error: rgb(0.80, 0.20, 0.15),
+            heatmap: map("magma"),
+            ..dark
+        },
+        _ => return None,
+    })
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        by_name("default").expect("built-in theme")
+    }
+}
+
+impl Theme {
+    /// Parse a theme file: its fields over those of its `base` theme.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let Value::Object(fields) = serde_json::from_str(json)? else {
+            return Err(serde::de::Error::custom("a theme is a JSON object"));
+        };
+        let base = match fields.get("base") {
+            None => Theme::default(),
+            Some(b) => b
+                .as_str()
+                .and_then(by_name)
+                .ok_or_else(|| serde::de::Error::custom(format!("no built-in theme {b}")))?,
+        };
+        let Value::Object(mut merged) = serde_json::to_value(base)? else {
+            unreachable!("a theme serializes to an object");
+        };
+        merged.extend(fields.into_iter().filter(|(k, _)| k != "base"));
+        serde_json::from_value(Value::Object(merged))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Self::from_json(&json).map_err(io::Error::other)
+    }
+
+    /// A built-in theme name, else a path to a theme file.
+    pub fn by_name_or_path(s: &str) -> io::Result<Self> {
+        match by_name(s) {
+            Some(t) => Ok(t),
+            None => Self::load(s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Rgb, b: Rgb) -> bool {
+        a.0.iter().zip(b.0).all(|(x, y)| (x - y).abs() < 0.01)
+    }
+
+    #[test]
+    fn maps_interpolate_between_stops() {
+        let heat = ColorMap::by_name("heat").unwrap();
+        assert_eq!(heat.sample(0.0), HEAT[0]);
+        assert_eq!(heat.sample(0.5), HEAT[1]);
+        assert_eq!(heat.sample(2.0), HEAT[2]);
+        assert!(close(heat.sample(0.25), HEAT[0].lerp(HEAT[1], 0.5)));
+        assert_eq!(ColorMap(vec![Rgb::gray(0.5)]).sample(0.7), Rgb::gray(0.5));
+    }
+
+    #[test]
+    fn classic_matches_the_old_fill_formula() {
+        let classic = ColorMap::by_name("classic").unwrap();
+        for i in 0..=20 {
+            let v = i as f32 / 20.0;
+            let g = 0.4 + 0.6 * (1.0 - (v - 0.5).abs() * 2.0).max(0.0);
+            assert!(
+                close(classic.sample(v), rgb(v * v, g, 1.0 - v * 0.4)),
+                "{v}"
+            );
+        }
+    }
+
+    #[test]
+    fn builtins_and_files() {
+        for name in BUILTIN {
+            assert_eq!(by_name(name).unwrap().name, *name);
+        }
+        let t = Theme::from_json(
+            r##"{ "name": "Mono", "base": "light", "background": "#ff8000",
+                  "fill": ["#000000", [1, 1, 1]], "heatmap": "turbo" }"##,
+        )
+        .unwrap();
+        assert_eq!(t.name, "Mono");
+        assert_eq!(t.background, rgb(1.0, 128.0 / 255.0, 0.0));
+        assert_eq!(t.cap, by_name("light").unwrap().cap);
+        assert_eq!(t.fill.sample(0.5), Rgb::gray(0.5));
+        assert_eq!(t.heatmap, ColorMap::by_name("turbo").unwrap());
+
+        assert!(Theme::from_json(r#"{ "fill": "rainbow" }"#).is_err());
+        assert!(Theme::from_json(r##"{ "cap": "#12345" }"##).is_err());
+        assert!(Theme::from_json(r#"{ "base": "neon" }"#).is_err());
+    }
+}
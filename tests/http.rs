@@ -0,0 +1,137 @@
+//! Raw HTTP requests against the bridge page server, started the way an
+//! application starts it.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use kb_hall::AnalogKeyboard;
+
+/// A keyboard with its bridge running; returns the page port and token.
+fn start() -> (AnalogKeyboard, u16, String) {
+    let kb = AnalogKeyboard::new(0x41E4, 0x2103);
+    let url = kb.start_bridge().unwrap();
+    let (addr, token) = url.split_once("/?token=").unwrap();
+    let port = addr.rsplit(':').next().unwrap().parse().unwrap();
+    (kb, port, token.to_string())
+}
+
+/// Send raw bytes and return (status code, headers+body as text).
+fn raw(port: u16, request: &str) -> (u16, String) {
+    let mut s = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    s.write_all(request.as_bytes()).unwrap();
+    let mut out = String::new();
+    s.read_to_string(&mut out).unwrap();
+    let code = out
+        .split(' ')
+        .nth(1)
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(0);
+    (code, out)
+}
+
+fn get(port: u16, target: &str) -> (u16, String) {
+    raw(
+        port,
+        &format!("GET {target} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n"),
+    )
+}
+
+fn body(resp: &str) -> &str {
+    resp.split_once("\r\n\r\n").map_or("", |(_, b)| b)
+}
+
+#[test]
+fn page_requires_token() {
+    let (_kb, port, token) = start();
+    let (code, resp) = get(port, &format!("/?token={token}"));
+    assert_eq!(code, 200);
+    assert!(resp.contains("Content-Type: text/html"));
+    assert!(body(&resp).contains(&token));
+    assert!(!body(&resp).contains("__WS_PORT__"));
+
+    assert_eq!(get(port, "/").0, 403);
+    assert_eq!(get(port, "/?token=nope").0, 403);
+}
+
+#[test]
+fn static_assets() {
+    let (_kb, port, _) = start();
+    let (code, js) = get(port, "/bridge.js");
+    assert_eq!(code, 200);
+    assert!(js.contains("text/javascript"));
+    assert!(body(&js).contains("function onInput"));
+
+    let (code, css) = get(port, "/bridge.css");
+    assert_eq!(code, 200);
+    assert!(css.contains("text/css"));
+}
+
+#[test]
+fn unknown_paths_are_404() {
+    let (_kb, port, _) = start();
+    assert_eq!(get(port, "/favicon.ico").0, 404);
+    assert_eq!(get(port, "/bridge.html").0, 404);
+}
+
+#[test]
+fn health_and_head() {
+    let (_kb, port, _) = start();
+    let (code, resp) = get(port, "/health");
+    assert_eq!((code, body(&resp)), (200, "ok\n"));
+
+    let (code, resp) = raw(port, "HEAD /health HTTP/1.1\r\n\r\n");
+    assert_eq!(code, 200);
+    assert!(resp.contains("Content-Length: 3"));
+    assert_eq!(body(&resp), "");
+}
+
+#[test]
+fn status_reports_keyboard_state() {
+    let (kb, port, token) = start();
+    let mut vals = [0.0f32; 256];
+    vals[0x04] = 0.5;
+    kb.set_values(&vals);
+
+    assert_eq!(get(port, "/status").0, 403);
+    let (code, resp) = get(port, &format!("/status?token={token}"));
+    assert_eq!(code, 200);
+    assert!(resp.contains("Content-Type: application/json"));
+    let json: serde_json::Value = serde_json::from_str(body(&resp)).unwrap();
+    assert_eq!(json["active"], false);
+    assert_eq!(json["productId"], 0x2103);
+    assert_eq!(json["pressed"], serde_json::json!([4]));
+    assert!(json["device"].is_null());
+
+    let from = |origin: &str| {
+        let req = format!("GET /status?token={token} HTTP/1.1\r\nOrigin: {origin}\r\n\r\n");
+        raw(port, &req).0
+    };
+    assert_eq!(from(&format!("http://127.0.0.1:{port}")), 200);
+    assert_eq!(from("http://example.com"), 403);
+}
+
+#[test]
+fn rejects_bad_requests() {
+    let (_kb, port, _) = start();
+    assert_eq!(raw(port, "POST /status HTTP/1.1\r\n\r\n").0, 405);
+    assert_eq!(raw(port, "GARBAGE\r\n\r\n").0, 400);
+    assert_eq!(raw(port, "GET /health HTTP/1.1\r\nno colon\r\n\r\n").0, 400);
+
+    let huge = format!("GET / HTTP/1.1\r\nX-Pad: {}\r\n\r\n", "a".repeat(8 * 1024));
+    assert_eq!(raw(port, &huge).0, 431);
+}
+
+#[test]
+fn request_arrives_in_pieces() {
+    let (_kb, port, _) = start();
+    let mut s = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    s.write_all(b"GET /hea").unwrap();
+    thread::sleep(Duration::from_millis(50));
+    s.write_all(b"lth HTTP/1.1\r\n\r\n").unwrap();
+    let mut out = String::new();
+    s.read_to_string(&mut out).unwrap();
+    assert!(out.starts_with("HTTP/1.1 200 OK"));
+}